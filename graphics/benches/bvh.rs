@@ -0,0 +1,68 @@
+//! Build & ray query timings for the BVH on the gargoyle model
+//!
+//! Run with `cargo bench --bench bvh`
+
+use std::{path::Path, time::Instant};
+
+use cgmath::*;
+use graphics::{
+    bvh::{Bvh, Ray},
+    obj,
+};
+
+const RAYS: usize = 200_000;
+
+fn main() {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/garg.obj");
+    let model = obj::load_model(&file).expect("model loading failed");
+    let sphere = model.bounding_sphere().expect("model has no vertices");
+
+    let start = Instant::now();
+    let bvh = Bvh::new(&model);
+    println!(
+        "build: {} triangles, {} nodes in {:.1?}",
+        bvh.triangle_count(),
+        bvh.node_count(),
+        start.elapsed()
+    );
+
+    // Rays from points spread over a sphere around the model, aimed at its center
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|i| {
+            let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / RAYS as f32;
+            let r = (1.0 - z * z).sqrt();
+            let (sin, cos) = (golden_angle * i as f32).sin_cos();
+            let direction = Vector3::new(r * cos, r * sin, z);
+            let origin = sphere.center + direction * sphere.radius * 2.0;
+            let jitter = Vector3::new(sin, cos, z) * sphere.radius * 0.3;
+            Ray::new(origin, sphere.center + jitter - origin)
+        })
+        .collect();
+
+    let start = Instant::now();
+    let hits = rays
+        .iter()
+        .filter(|ray| bvh.closest_hit(ray, 0.0, f32::INFINITY).is_some())
+        .count();
+    report("closest hit", hits, start);
+
+    let start = Instant::now();
+    let hits = rays
+        .iter()
+        .filter(|ray| bvh.any_hit(ray, 0.0, f32::INFINITY).is_some())
+        .count();
+    report("any hit", hits, start);
+}
+
+fn report(name: &str, hits: usize, start: Instant) {
+    let elapsed = start.elapsed();
+    println!(
+        "{}: {} rays, {} hits in {:.1?} ({:.2} Mrays/s)",
+        name,
+        RAYS,
+        hits,
+        elapsed,
+        RAYS as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
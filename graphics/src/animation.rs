@@ -0,0 +1,410 @@
+//! Keyframe animation of scene nodes & cameras
+//!
+//! A track holds keyframes of one value over time, and an animation binds tracks to the
+//! translation, rotation or scale of a node or the camera. Renderers advance their
+//! animations by the frame delta in `Renderer::update`
+
+use std::ops::{Add, Mul};
+use std::time::Duration;
+
+use cgmath::*;
+
+use crate::{camera::Camera, scene::SceneGraph};
+
+/// Value that can be keyframed
+pub trait Animatable: Copy + Zero + Add<Output = Self> + Mul<f32, Output = Self> {
+    /// Blend from self to other by amount, from 0 to 1
+    fn interpolate(self, other: Self, amount: f32) -> Self;
+
+    /// Fix up a value built by weighting keyframes, such as a rotation that's no longer
+    /// unit length
+    fn normalized(self) -> Self {
+        self
+    }
+}
+
+impl Animatable for Vector3<f32> {
+    fn interpolate(self, other: Self, amount: f32) -> Self {
+        self.lerp(other, amount)
+    }
+}
+
+impl Animatable for Quaternion<f32> {
+    /// Spherical interpolation along the shortest arc
+    fn interpolate(self, other: Self, amount: f32) -> Self {
+        let other = if self.dot(other) < 0.0 { -other } else { other };
+        self.slerp(other, amount)
+    }
+
+    fn normalized(self) -> Self {
+        self.normalize()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Hold each keyframe until the next
+    Step,
+    #[default]
+    Linear,
+
+    /// Cubic Hermite spline through the keyframes, shaped by their tangents
+    CubicSpline,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T> {
+    /// Seconds from the start of the animation
+    pub time: f32,
+    pub value: T,
+
+    /// Rate of change per second arriving at & leaving the keyframe, only used by
+    /// `Interpolation::CubicSpline`
+    pub in_tangent: T,
+    pub out_tangent: T,
+}
+
+impl<T: Animatable> Keyframe<T> {
+    /// Keyframe with flat tangents
+    pub fn new(time: f32, value: T) -> Self {
+        Keyframe {
+            time,
+            value,
+            in_tangent: T::zero(),
+            out_tangent: T::zero(),
+        }
+    }
+}
+
+/// Keyframes of one value, sorted by time
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    pub keyframes: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(keyframes: Vec<Keyframe<T>>, interpolation: Interpolation) -> Self {
+        Track {
+            keyframes,
+            interpolation,
+        }
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Value at a time, held at the first & last keyframes outside of the track
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let first = self.keyframes.first()?;
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 {
+            return Some(first.value);
+        }
+        let (previous, next) = match self.keyframes.get(next) {
+            Some(keyframe) => (&self.keyframes[next - 1], keyframe),
+            None => return self.keyframes.last().map(|keyframe| keyframe.value),
+        };
+
+        let span = next.time - previous.time;
+        let amount = (time - previous.time) / span;
+        Some(match self.interpolation {
+            Interpolation::Step => previous.value,
+            Interpolation::Linear => previous.value.interpolate(next.value, amount),
+            Interpolation::CubicSpline => {
+                let (t, t2, t3) = (amount, amount * amount, amount * amount * amount);
+                let value = previous.value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + previous.out_tangent * ((t3 - 2.0 * t2 + t) * span)
+                    + next.value * (-2.0 * t3 + 3.0 * t2)
+                    + next.in_tangent * ((t3 - t2) * span);
+                value.normalized()
+            }
+        })
+    }
+}
+
+/// Property of a node or camera driven by a track
+#[derive(Debug, Clone)]
+pub enum Channel {
+    /// Node translation, or camera eye position
+    Translation(Track<Vector3<f32>>),
+
+    /// Node rotation, or camera orientation looking down its -z axis with y up
+    Rotation(Track<Quaternion<f32>>),
+
+    /// Node scale, ignored for cameras
+    Scale(Track<Vector3<f32>>),
+}
+
+impl Channel {
+    fn duration(&self) -> f32 {
+        match self {
+            Channel::Translation(track) | Channel::Scale(track) => track.duration(),
+            Channel::Rotation(track) => track.duration(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Index of a node in the renderer's scene graph
+    Node(usize),
+    Camera,
+}
+
+/// Tracks bound to nodes & the camera, played together
+#[derive(Debug, Clone, Default)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<(Target, Channel)>,
+
+    /// Whether to start over after the last keyframe, rather than holding it
+    pub looping: bool,
+    time: f32,
+}
+
+impl Animation {
+    pub fn new(name: &str, looping: bool) -> Self {
+        Animation {
+            name: name.to_string(),
+            looping,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_channel(&mut self, target: Target, channel: Channel) {
+        self.channels.push((target, channel));
+    }
+
+    /// Time of the last keyframe of any track
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .map(|(_, channel)| channel.duration())
+            .fold(0.0, f32::max)
+    }
+
+    /// Seconds played, within the duration
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        let duration = self.duration();
+        self.time = if self.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        };
+    }
+
+    /// Step forward by a frame delta & apply the result
+    pub fn update(&mut self, delta: Duration, scene: &mut SceneGraph, camera: &mut Camera) {
+        self.seek(self.time + delta.as_secs_f32());
+        self.apply(scene, camera);
+    }
+
+    /// Set every bound property to its value at the current time
+    ///
+    /// Panics if a node isn't in the scene
+    pub fn apply(&self, scene: &mut SceneGraph, camera: &mut Camera) {
+        for (target, channel) in self.channels.iter() {
+            match target {
+                Target::Node(node) => {
+                    let transform = &mut scene.node_mut(*node).transform;
+                    match channel {
+                        Channel::Translation(track) => {
+                            if let Some(translation) = track.sample(self.time) {
+                                transform.translate = translation.into();
+                            }
+                        }
+                        Channel::Rotation(track) => {
+                            if let Some(rotation) = track.sample(self.time) {
+                                transform.rotation = rotation;
+                            }
+                        }
+                        Channel::Scale(track) => {
+                            if let Some(scale) = track.sample(self.time) {
+                                transform.scale = scale.into();
+                            }
+                        }
+                    }
+                }
+                Target::Camera => match channel {
+                    Channel::Translation(track) => {
+                        if let Some(eye) = track.sample(self.time) {
+                            let offset = camera.target() - camera.eye();
+                            let eye = Point3::from_vec(eye);
+                            camera.set_view(eye, eye + offset, camera.up());
+                        }
+                    }
+                    Channel::Rotation(track) => {
+                        if let Some(rotation) = track.sample(self.time) {
+                            // Keep the distance to the target, looking in the new direction
+                            let distance = (camera.target() - camera.eye()).magnitude();
+                            let forward = rotation.rotate_vector(-Vector3::unit_z());
+                            let up = rotation.rotate_vector(Vector3::unit_y());
+                            let eye = camera.eye();
+                            camera.set_view(eye, eye + forward * distance, up);
+                        }
+                    }
+                    Channel::Scale(_) => {}
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cgmath::*;
+
+    use crate::{camera::Camera, scene::SceneGraph, transform::Transform};
+
+    use super::*;
+
+    fn track(interpolation: Interpolation) -> Track<Vector3<f32>> {
+        Track::new(
+            vec![
+                Keyframe::new(1.0, Vector3::new(0.0, 0.0, 0.0)),
+                Keyframe::new(3.0, Vector3::new(4.0, 2.0, 0.0)),
+            ],
+            interpolation,
+        )
+    }
+
+    #[test]
+    pub fn interpolation_modes() {
+        let linear = track(Interpolation::Linear);
+        assert_abs_diff_eq!(linear.sample(2.0).unwrap(), Vector3::new(2.0, 1.0, 0.0));
+        assert_abs_diff_eq!(linear.sample(0.0).unwrap(), Vector3::zero());
+        assert_abs_diff_eq!(linear.sample(5.0).unwrap(), Vector3::new(4.0, 2.0, 0.0));
+
+        let step = track(Interpolation::Step);
+        assert_abs_diff_eq!(step.sample(2.9).unwrap(), Vector3::zero());
+        assert_abs_diff_eq!(step.sample(3.0).unwrap(), Vector3::new(4.0, 2.0, 0.0));
+
+        // Flat tangents ease in & out, but pass the middle at the same point
+        let mut cubic = track(Interpolation::CubicSpline);
+        assert_abs_diff_eq!(cubic.sample(2.0).unwrap(), Vector3::new(2.0, 1.0, 0.0));
+        assert!(cubic.sample(1.5).unwrap().x < linear.sample(1.5).unwrap().x);
+
+        // Tangents matching the slope give a straight line
+        for keyframe in cubic.keyframes.iter_mut() {
+            keyframe.in_tangent = Vector3::new(2.0, 1.0, 0.0);
+            keyframe.out_tangent = Vector3::new(2.0, 1.0, 0.0);
+        }
+        assert_abs_diff_eq!(
+            cubic.sample(1.5).unwrap(),
+            linear.sample(1.5).unwrap(),
+            epsilon = 1e-5
+        );
+
+        assert!(Track::<Vector3<f32>>::new(vec![], Interpolation::Linear)
+            .sample(1.0)
+            .is_none());
+    }
+
+    #[test]
+    pub fn rotations_slerp() {
+        let track = Track::new(
+            vec![
+                Keyframe::new(0.0, Quaternion::one()),
+                Keyframe::new(1.0, Quaternion::from_angle_y(Deg(90.0))),
+            ],
+            Interpolation::Linear,
+        );
+        assert_abs_diff_eq!(
+            track.sample(0.5).unwrap(),
+            Quaternion::from_angle_y(Deg(45.0)),
+            epsilon = 1e-5
+        );
+
+        // The same rotation with the opposite sign takes the short way round
+        let flipped = Track::new(
+            vec![
+                Keyframe::new(0.0, Quaternion::one()),
+                Keyframe::new(1.0, -Quaternion::from_angle_y(Deg(90.0))),
+            ],
+            Interpolation::Linear,
+        );
+        let middle = flipped.sample(0.5).unwrap();
+        assert_abs_diff_eq!(
+            middle.rotate_vector(Vector3::unit_x()),
+            Quaternion::from_angle_y(Deg(45.0)).rotate_vector(Vector3::unit_x()),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    pub fn looping() {
+        let mut animation = Animation::new("move", true);
+        animation.add_channel(
+            Target::Node(0),
+            Channel::Translation(track(Interpolation::Linear)),
+        );
+        assert_eq!(animation.duration(), 3.0);
+
+        animation.seek(4.0);
+        assert_abs_diff_eq!(animation.time(), 1.0);
+        animation.seek(-0.5);
+        assert_abs_diff_eq!(animation.time(), 2.5);
+
+        animation.looping = false;
+        animation.seek(4.0);
+        assert_abs_diff_eq!(animation.time(), 3.0);
+    }
+
+    #[test]
+    pub fn drives_nodes_and_camera() {
+        let mut scene = SceneGraph::new();
+        let node = scene.add_node("node", Transform::new(), None);
+        let mut camera = Camera::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::origin(),
+            Vector3::unit_y(),
+            1.0,
+            45.0,
+            0.1,
+            100.0,
+        );
+
+        let mut animation = Animation::new("tour", false);
+        animation.add_channel(
+            Target::Node(node),
+            Channel::Translation(track(Interpolation::Linear)),
+        );
+        animation.add_channel(
+            Target::Node(node),
+            Channel::Scale(Track::new(
+                vec![Keyframe::new(0.0, Vector3::new(2.0, 2.0, 2.0))],
+                Interpolation::Step,
+            )),
+        );
+        animation.add_channel(
+            Target::Camera,
+            Channel::Rotation(Track::new(
+                vec![
+                    Keyframe::new(0.0, Quaternion::one()),
+                    Keyframe::new(2.0, Quaternion::from_angle_y(Deg(90.0))),
+                ],
+                Interpolation::Linear,
+            )),
+        );
+
+        animation.update(Duration::from_secs(2), &mut scene, &mut camera);
+        assert_eq!(scene.node(node).transform.translate, [2.0, 1.0, 0.0]);
+        assert_eq!(scene.node(node).transform.scale, [2.0; 3]);
+
+        // A quarter turn left has the camera looking down -x from where it stands
+        assert_abs_diff_eq!(camera.eye(), Point3::new(0.0, 0.0, 5.0));
+        assert_abs_diff_eq!(camera.target(), Point3::new(-5.0, 0.0, 5.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(camera.up(), Vector3::unit_y(), epsilon = 1e-5);
+    }
+}
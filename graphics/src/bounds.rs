@@ -0,0 +1,203 @@
+//! Bounding volumes for meshes & models
+
+use cgmath::*;
+
+use crate::model::{Mesh, Model};
+
+/// Axis-aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// Smallest box containing every point, `None` if there are no finite points
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Option<Aabb> {
+        points
+            .into_iter()
+            .filter(|point| point.x.is_finite() && point.y.is_finite() && point.z.is_finite())
+            .fold(None, |aabb: Option<Aabb>, point| match aabb {
+                Some(aabb) => Some(aabb.grow(point)),
+                None => Some(Aabb {
+                    min: point,
+                    max: point,
+                }),
+            })
+    }
+
+    /// Smallest box containing this box & the point
+    pub fn grow(&self, point: Point3<f32>) -> Aabb {
+        Aabb {
+            min: Point3::new(
+                self.min.x.min(point.x),
+                self.min.y.min(point.y),
+                self.min.z.min(point.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(point.x),
+                self.max.y.max(point.y),
+                self.max.z.max(point.z),
+            ),
+        }
+    }
+
+    /// Smallest box containing both boxes
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        self.grow(other.min).grow(other.max)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Length of the box along each axis
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// Total area of the box's faces
+    pub fn surface_area(&self) -> f32 {
+        let size = self.size();
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        (0..3).all(|axis| self.min[axis] <= point[axis] && point[axis] <= self.max[axis])
+    }
+}
+
+/// Sphere enclosing a set of points
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around the center of the points' bounding box, `None` if there are no
+    /// finite points
+    ///
+    /// This isn't the smallest enclosing sphere, but is exact for symmetric shapes
+    pub fn from_points<I>(points: I) -> Option<BoundingSphere>
+    where
+        I: IntoIterator<Item = Point3<f32>>,
+        I::IntoIter: Clone,
+    {
+        let points = points.into_iter();
+        let center = Aabb::from_points(points.clone())?.center();
+        let radius = points
+            .map(|point| point.distance(center))
+            .filter(|distance| distance.is_finite())
+            .fold(0.0, f32::max);
+
+        Some(BoundingSphere { center, radius })
+    }
+
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        point.distance(self.center) <= self.radius
+    }
+
+    /// Sphere enclosing this one after a transform, growing by the largest scale of any
+    /// axis so it stays enclosing under non-uniform scales
+    pub fn transform(&self, matrix: Matrix4<f32>) -> BoundingSphere {
+        let scale = [matrix.x, matrix.y, matrix.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+
+        BoundingSphere {
+            center: Point3::from_homogeneous(matrix * self.center.to_homogeneous()),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+impl Mesh {
+    /// Bounding box of the mesh's vertices, `None` if it has none
+    pub fn aabb(&self) -> Option<Aabb> {
+        Aabb::from_points(self.positions())
+    }
+
+    /// Bounding sphere of the mesh's vertices, `None` if it has none
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        BoundingSphere::from_points(self.positions())
+    }
+
+    fn positions(&self) -> impl Iterator<Item = Point3<f32>> + Clone + '_ {
+        self.vertices.iter().map(|vertex| vertex.position.into())
+    }
+}
+
+impl Model {
+    /// Bounding box of every mesh, `None` if there are no vertices
+    pub fn aabb(&self) -> Option<Aabb> {
+        Aabb::from_points(self.positions())
+    }
+
+    /// Bounding sphere of every mesh, `None` if there are no vertices
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        BoundingSphere::from_points(self.positions())
+    }
+
+    fn positions(&self) -> impl Iterator<Item = Point3<f32>> + Clone + '_ {
+        self.meshes.iter().flat_map(Mesh::positions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use crate::test_util::load_model;
+
+    use super::*;
+
+    #[test]
+    pub fn cube_bounds() {
+        let model = load_model("cube.obj");
+        let aabb = model.aabb().unwrap();
+        assert_abs_diff_eq!(aabb.min, Point3::new(-1.0, -1.0, -1.0));
+        assert_abs_diff_eq!(aabb.max, Point3::new(1.0, 1.0, 1.0));
+        assert_abs_diff_eq!(aabb.size(), Vector3::new(2.0, 2.0, 2.0));
+
+        let sphere = model.bounding_sphere().unwrap();
+        assert_abs_diff_eq!(sphere.center, Point3::origin());
+        assert!(sphere.radius <= 3.0f32.sqrt() + 1e-5);
+        for vertex in &model.meshes[0].vertices {
+            assert!(aabb.contains(vertex.position.into()));
+            assert!(sphere.radius + 1e-5 >= Point3::from(vertex.position).distance(sphere.center));
+        }
+    }
+
+    #[test]
+    pub fn sphere_bounds() {
+        let mesh = load_model("sphere.obj").meshes.remove(0);
+        let sphere = mesh.bounding_sphere().unwrap();
+        assert_abs_diff_eq!(sphere.center, Point3::origin(), epsilon = 1e-5);
+        assert_abs_diff_eq!(sphere.radius, 1.0, epsilon = 1e-5);
+    }
+
+    #[test]
+    pub fn transformed_sphere() {
+        let sphere = BoundingSphere {
+            center: Point3::new(1.0, 0.0, 0.0),
+            radius: 2.0,
+        };
+        let matrix = Matrix4::from_translation(Vector3::new(0.0, 5.0, 0.0))
+            * Matrix4::from_nonuniform_scale(1.0, 3.0, 0.5);
+        let transformed = sphere.transform(matrix);
+        assert_abs_diff_eq!(transformed.center, Point3::new(1.0, 5.0, 0.0));
+        assert_abs_diff_eq!(transformed.radius, 6.0);
+    }
+
+    #[test]
+    pub fn empty_bounds() {
+        let model = Model::default();
+        assert!(model.aabb().is_none());
+        assert!(model.bounding_sphere().is_none());
+
+        let aabb = Aabb::from_points([Point3::new(f32::NAN, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0)]);
+        assert_eq!(aabb.unwrap().min, Point3::new(1.0, 2.0, 3.0));
+    }
+}
@@ -0,0 +1,615 @@
+//! Bounding volume hierarchy for ray queries against a model's triangles
+//!
+//! The hierarchy is split with the surface area heuristic, choosing the split that
+//! minimises the expected cost of tracing a random ray through it
+
+use cgmath::*;
+
+use crate::{
+    bounds::Aabb,
+    model::{Model, ModelVertex},
+};
+
+/// Number of buckets centroids are sorted into when looking for a split
+const BINS: usize = 16;
+
+/// Most triangles a leaf can hold before it's always split
+const MAX_LEAF_TRIANGLES: usize = 8;
+
+/// Cost of testing a ray against a node's bounds, relative to testing a triangle
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// Ray with an origin & direction, the direction doesn't need to be normalized
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray { origin, direction }
+    }
+
+    /// Point `t` lengths of the direction along the ray
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+}
+
+/// Where a ray hit a triangle
+#[derive(Debug, Copy, Clone)]
+pub struct Hit {
+    /// Index of the mesh in the model
+    pub mesh: usize,
+
+    /// Index of the triangle in the mesh
+    pub triangle: usize,
+
+    /// Ray parameter of the hit, in lengths of the ray direction
+    pub t: f32,
+
+    /// Weights of the triangle's three corners at the hit
+    pub barycentrics: [f32; 3],
+
+    /// Vertex attributes interpolated to the hit, with the normal & tangent normalized
+    pub vertex: ModelVertex,
+
+    /// Unit normal of the triangle, following its winding
+    pub face_normal: Vector3<f32>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    aabb: Aabb,
+
+    /// First triangle for a leaf, or the second child for an interior node, whose first
+    /// child always follows it
+    offset: u32,
+
+    /// Number of triangles, zero for interior nodes
+    count: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Triangle {
+    mesh: u32,
+    triangle: u32,
+    positions: [Point3<f32>; 3],
+}
+
+impl Triangle {
+    fn aabb(&self) -> Aabb {
+        Aabb::from_points(self.positions).unwrap_or(Aabb {
+            min: self.positions[0],
+            max: self.positions[0],
+        })
+    }
+
+    fn centroid(&self) -> Point3<f32> {
+        Point3::centroid(&self.positions)
+    }
+
+    /// Möller–Trumbore intersection, returning the ray parameter & barycentrics of the
+    /// second & third corners. Both sides of the triangle are hit
+    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        let [a, b, c] = self.positions;
+        let (edge_1, edge_2) = (b - a, c - a);
+        let p = ray.direction.cross(edge_2);
+        let determinant = edge_1.dot(p);
+        if determinant.abs() < f32::EPSILON * edge_1.magnitude() * edge_2.magnitude() {
+            return None;
+        }
+
+        let inverse = 1.0 / determinant;
+        let s = ray.origin - a;
+        let u = s.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge_1);
+        let v = ray.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge_2.dot(q) * inverse;
+        (t > t_min && t < t_max).then_some((t, u, v))
+    }
+}
+
+/// Triangle hit by a ray, found without the model's vertices
+#[derive(Debug, Copy, Clone)]
+pub struct TriangleHit {
+    /// Index of the mesh in the model
+    pub mesh: usize,
+
+    /// Index of the triangle in the mesh
+    pub triangle: usize,
+
+    /// Ray parameter of the hit, in lengths of the ray direction
+    pub t: f32,
+
+    /// Weights of the triangle's three corners at the hit
+    pub barycentrics: [f32; 3],
+}
+
+/// Bounding volume hierarchy over every triangle of a model, keeping its own copy of
+/// the positions so it can outlive the model
+#[derive(Debug)]
+pub struct TriangleBvh {
+    nodes: Vec<Node>,
+    triangles: Vec<Triangle>,
+}
+
+/// Bounding volume hierarchy over every triangle of a model, interpolating the model's
+/// vertices at each hit
+#[derive(Debug)]
+pub struct Bvh<'a> {
+    model: &'a Model,
+    hierarchy: TriangleBvh,
+}
+
+impl<'a> Bvh<'a> {
+    /// Build a hierarchy over the model's triangles, skipping any with out of range indices
+    pub fn new(model: &'a Model) -> Bvh<'a> {
+        Bvh {
+            model,
+            hierarchy: TriangleBvh::new(model),
+        }
+    }
+
+    /// Number of nodes in the hierarchy
+    pub fn node_count(&self) -> usize {
+        self.hierarchy.node_count()
+    }
+
+    /// Number of triangles in the hierarchy
+    pub fn triangle_count(&self) -> usize {
+        self.hierarchy.triangle_count()
+    }
+
+    /// Bounds of every triangle, `None` if there are none
+    pub fn aabb(&self) -> Option<Aabb> {
+        self.hierarchy.aabb()
+    }
+
+    /// Nearest hit along the ray with `t_min < t < t_max`
+    pub fn closest_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        self.hierarchy
+            .traverse(ray, t_min, t_max, false)
+            .map(|(triangle, t, barycentrics)| self.hit(triangle, t, barycentrics))
+    }
+
+    /// Any hit along the ray with `t_min < t < t_max`, which is quicker to find than the
+    /// closest one when only occlusion matters
+    pub fn any_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<Hit> {
+        self.hierarchy
+            .traverse(ray, t_min, t_max, true)
+            .map(|(triangle, t, barycentrics)| self.hit(triangle, t, barycentrics))
+    }
+
+    fn hit(&self, triangle: &Triangle, t: f32, barycentrics: [f32; 3]) -> Hit {
+        let mesh = &self.model.meshes[triangle.mesh as usize];
+        let corners = [0, 1, 2]
+            .map(|i| mesh.vertices[mesh.indices[triangle.triangle as usize * 3 + i] as usize]);
+
+        // Interpolate every attribute as one array of floats
+        let mut attributes = [0.0; 12];
+        for (corner, weight) in corners.iter().zip(barycentrics) {
+            let values: [f32; 12] = bytemuck::cast(*corner);
+            for (attribute, value) in attributes.iter_mut().zip(values) {
+                *attribute += value * weight;
+            }
+        }
+        let mut vertex: ModelVertex = bytemuck::cast(attributes);
+        let normal = Vector3::from(vertex.normal);
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+        let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+        if tangent.magnitude2() > 0.0 {
+            let tangent = tangent.normalize();
+            let sign = corners[0].tangent[3];
+            vertex.tangent = [tangent.x, tangent.y, tangent.z, sign];
+        }
+
+        let [a, b, c] = triangle.positions;
+        let face_normal = (b - a).cross(c - a);
+        let face_normal = if face_normal.magnitude2() > 0.0 {
+            face_normal.normalize()
+        } else {
+            face_normal
+        };
+
+        Hit {
+            mesh: triangle.mesh as usize,
+            triangle: triangle.triangle as usize,
+            t,
+            barycentrics,
+            vertex,
+            face_normal,
+        }
+    }
+}
+
+impl TriangleBvh {
+    /// Build a hierarchy over the model's triangles, skipping any with out of range indices
+    pub fn new(model: &Model) -> TriangleBvh {
+        let mut triangles = vec![];
+        for (mesh_index, mesh) in model.meshes.iter().enumerate() {
+            for (triangle, indices) in mesh.indices.chunks_exact(3).enumerate() {
+                let positions = [0, 1, 2].map(|i| {
+                    mesh.vertices
+                        .get(indices[i] as usize)
+                        .map(|vertex| Point3::from(vertex.position))
+                });
+                if let [Some(a), Some(b), Some(c)] = positions {
+                    triangles.push(Triangle {
+                        mesh: mesh_index as u32,
+                        triangle: triangle as u32,
+                        positions: [a, b, c],
+                    });
+                }
+            }
+        }
+
+        let mut bvh = TriangleBvh {
+            nodes: Vec::with_capacity(triangles.len() * 2),
+            triangles,
+        };
+        if !bvh.triangles.is_empty() {
+            let centroids: Vec<Point3<f32>> =
+                bvh.triangles.iter().map(Triangle::centroid).collect();
+            let mut order: Vec<usize> = (0..bvh.triangles.len()).collect();
+            bvh.build(&mut order, 0, &centroids);
+
+            let triangles = std::mem::take(&mut bvh.triangles);
+            bvh.triangles = order.into_iter().map(|i| triangles[i]).collect();
+        }
+
+        bvh
+    }
+
+    /// Number of nodes in the hierarchy
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Number of triangles in the hierarchy
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Bounds of every triangle, `None` if there are none
+    pub fn aabb(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.aabb)
+    }
+
+    /// Nearest triangle hit along the ray with `t_min < t < t_max`
+    pub fn closest_hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<TriangleHit> {
+        self.traverse(ray, t_min, t_max, false)
+            .map(|(triangle, t, barycentrics)| TriangleHit {
+                mesh: triangle.mesh as usize,
+                triangle: triangle.triangle as usize,
+                t,
+                barycentrics,
+            })
+    }
+
+    /// Recursively split `order[..]`, whose triangles start at `first`, adding its node &
+    /// children
+    fn build(&mut self, order: &mut [usize], first: usize, centroids: &[Point3<f32>]) {
+        let aabb = order
+            .iter()
+            .map(|i| self.triangles[*i].aabb())
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            aabb,
+            offset: first as u32,
+            count: order.len() as u32,
+        });
+
+        let split = match self.find_split(order, &aabb, centroids) {
+            Some(split) => split,
+            None => return,
+        };
+
+        // Partition the triangles either side of the split plane
+        let (axis, position) = split;
+        let mut middle = 0;
+        for i in 0..order.len() {
+            if centroids[order[i]][axis] < position {
+                order.swap(i, middle);
+                middle += 1;
+            }
+        }
+        if middle == 0 || middle == order.len() {
+            return;
+        }
+
+        let (left, right) = order.split_at_mut(middle);
+        self.build(left, first, centroids);
+        self.nodes[node].offset = self.nodes.len() as u32;
+        self.nodes[node].count = 0;
+        self.build(right, first + middle, centroids);
+    }
+
+    /// Axis & position of the cheapest split by the surface area heuristic, `None` if a
+    /// leaf is cheaper
+    fn find_split(
+        &self,
+        order: &[usize],
+        aabb: &Aabb,
+        centroids: &[Point3<f32>],
+    ) -> Option<(usize, f32)> {
+        if order.len() <= 2 {
+            return None;
+        }
+
+        let centroid_bounds = Aabb::from_points(order.iter().map(|i| centroids[*i]))?;
+        let mut best: Option<(f32, usize, f32)> = None;
+        for (axis, (min, max)) in [0, 1, 2]
+            .map(|axis| (centroid_bounds.min[axis], centroid_bounds.max[axis]))
+            .into_iter()
+            .enumerate()
+        {
+            if max - min <= f32::EPSILON * max.abs().max(min.abs()) {
+                continue;
+            }
+
+            let scale = BINS as f32 / (max - min);
+            let bin = |i: usize| (((centroids[i][axis] - min) * scale) as usize).min(BINS - 1);
+            let mut counts = [0; BINS];
+            let mut bounds: [Option<Aabb>; BINS] = [None; BINS];
+            for i in order {
+                let bin = bin(*i);
+                let triangle = self.triangles[*i].aabb();
+                counts[bin] += 1;
+                bounds[bin] = Some(bounds[bin].map_or(triangle, |b| b.union(&triangle)));
+            }
+
+            // Sweep from the right to find the cost of everything above each split
+            let mut right_costs = [0.0; BINS];
+            let mut right_bounds: Option<Aabb> = None;
+            let mut right_count = 0;
+            for bin in (1..BINS).rev() {
+                right_bounds = union(right_bounds, bounds[bin]);
+                right_count += counts[bin];
+                right_costs[bin] =
+                    right_bounds.map_or(0.0, |b| b.surface_area()) * right_count as f32;
+            }
+
+            let mut left_bounds: Option<Aabb> = None;
+            let mut left_count = 0;
+            for bin in 1..BINS {
+                left_bounds = union(left_bounds, bounds[bin - 1]);
+                left_count += counts[bin - 1];
+                let cost = left_bounds.map_or(0.0, |b| b.surface_area()) * left_count as f32
+                    + right_costs[bin];
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, min + bin as f32 / scale));
+                }
+            }
+        }
+
+        let (cost, axis, position) = best?;
+        let area = aabb.surface_area().max(f32::MIN_POSITIVE);
+        let split_cost = TRAVERSAL_COST + cost / area;
+        let leaf_cost = order.len() as f32;
+        (split_cost < leaf_cost || order.len() > MAX_LEAF_TRIANGLES).then_some((axis, position))
+    }
+
+    /// Triangle hit along the ray, its ray parameter & the barycentrics of the hit
+    fn traverse(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        any: bool,
+    ) -> Option<(&Triangle, f32, [f32; 3])> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = ray.direction.map(|d| 1.0 / d);
+        let mut closest = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                let first = node.offset as usize;
+                for triangle in &self.triangles[first..first + node.count as usize] {
+                    if let Some((t, u, v)) = triangle.intersect(ray, t_min, t_max) {
+                        t_max = t;
+                        closest = Some((triangle, t, u, v));
+                        if any {
+                            break;
+                        }
+                    }
+                }
+                if any && closest.is_some() {
+                    break;
+                }
+                continue;
+            }
+
+            // Visit the nearer child first so later boxes can be skipped
+            let children = [index + 1, node.offset as usize];
+            let distances = children.map(|child| {
+                slab_test(
+                    &self.nodes[child].aabb,
+                    ray,
+                    inverse_direction,
+                    t_min,
+                    t_max,
+                )
+            });
+            match distances {
+                [Some(first), Some(second)] if first <= second => {
+                    stack.extend([children[1], children[0]])
+                }
+                [Some(_), Some(_)] => stack.extend(children),
+                [Some(_), None] => stack.push(children[0]),
+                [None, Some(_)] => stack.push(children[1]),
+                [None, None] => {}
+            }
+        }
+
+        closest.map(|(triangle, t, u, v)| (triangle, t, [1.0 - u - v, u, v]))
+    }
+}
+
+fn union(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.union(&b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Ray parameter where the ray enters the box, `None` if it misses it between `t_min` &
+/// `t_max`
+fn slab_test(
+    aabb: &Aabb,
+    ray: &Ray,
+    inverse_direction: Vector3<f32>,
+    t_min: f32,
+    t_max: f32,
+) -> Option<f32> {
+    let (mut near, mut far) = (t_min, t_max);
+    for axis in 0..3 {
+        let t_0 = (aabb.min[axis] - ray.origin[axis]) * inverse_direction[axis];
+        let t_1 = (aabb.max[axis] - ray.origin[axis]) * inverse_direction[axis];
+        // NaN from a zero direction on the slab's edge compares false & is ignored
+        near = near.max(t_0.min(t_1));
+        far = far.min(t_0.max(t_1));
+    }
+
+    (near <= far).then_some(near)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use crate::{model::Model, test_util::load_model};
+
+    use super::*;
+
+    /// Deterministic pseudo-random numbers in [0, 1)
+    fn random(state: &mut u64) -> f32 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*state >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn random_ray(state: &mut u64) -> Ray {
+        let mut point = || Vector3::new(random(state), random(state), random(state)) * 4.0;
+        let origin = Point3::from_vec(point()) - Vector3::new(2.0, 2.0, 2.0);
+        let target = Point3::from_vec(point() * 0.5) - Vector3::new(1.0, 1.0, 1.0);
+        Ray::new(origin, target - origin)
+    }
+
+    /// Closest hit found by testing every triangle
+    fn brute_force(bvh: &Bvh, ray: &Ray) -> Option<(usize, usize, f32)> {
+        bvh.hierarchy
+            .triangles
+            .iter()
+            .filter_map(|triangle| {
+                let (t, _, _) = triangle.intersect(ray, 0.0, f32::INFINITY)?;
+                Some((triangle.mesh as usize, triangle.triangle as usize, t))
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+    }
+
+    #[test]
+    pub fn sphere_hit() {
+        let model = load_model("sphere.obj");
+        let bvh = Bvh::new(&model);
+        assert_eq!(bvh.triangle_count(), model.meshes[0].indices.len() / 3);
+
+        let ray = Ray::new(Point3::new(0.01, 0.02, 5.0), Vector3::new(0.0, 0.0, -2.0));
+        let hit = bvh.closest_hit(&ray, 0.0, f32::INFINITY).unwrap();
+        assert_abs_diff_eq!(hit.t, 2.0, epsilon = 0.01);
+        assert_abs_diff_eq!(hit.barycentrics.iter().sum::<f32>(), 1.0, epsilon = 1e-5);
+        assert_abs_diff_eq!(
+            Point3::from(hit.vertex.position),
+            ray.at(hit.t),
+            epsilon = 1e-5
+        );
+        assert_abs_diff_eq!(
+            Vector3::from(hit.vertex.normal),
+            Vector3::unit_z(),
+            epsilon = 0.05
+        );
+        assert!(hit.face_normal.z > 0.9);
+
+        // Without the model only the triangle is found, the same one
+        let triangle = TriangleBvh::new(&model)
+            .closest_hit(&ray, 0.0, f32::INFINITY)
+            .unwrap();
+        assert_eq!((triangle.mesh, triangle.triangle), (hit.mesh, hit.triangle));
+        assert_eq!(triangle.t, hit.t);
+
+        // The far side is hit when starting inside
+        let inside = bvh.closest_hit(&ray, 2.5, f32::INFINITY).unwrap();
+        assert_abs_diff_eq!(inside.t, 3.0, epsilon = 0.01);
+        assert!(bvh.closest_hit(&ray, 0.0, 1.9).is_none());
+
+        let miss = Ray::new(Point3::new(2.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(bvh.closest_hit(&miss, 0.0, f32::INFINITY).is_none());
+        assert!(bvh.any_hit(&miss, 0.0, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    pub fn matches_brute_force() {
+        let model = load_model("torus.obj");
+        let bvh = Bvh::new(&model);
+        assert!(bvh.node_count() > 1);
+
+        let mut state = 1;
+        let mut hits = 0;
+        for _ in 0..500 {
+            let ray = random_ray(&mut state);
+            let hit = bvh.closest_hit(&ray, 0.0, f32::INFINITY);
+            let expected = brute_force(&bvh, &ray);
+            assert_eq!(hit.is_some(), expected.is_some());
+            assert_eq!(
+                bvh.any_hit(&ray, 0.0, f32::INFINITY).is_some(),
+                hit.is_some()
+            );
+
+            if let (Some(hit), Some((_, _, t))) = (hit, expected) {
+                assert_abs_diff_eq!(hit.t, t, epsilon = 1e-5);
+                hits += 1;
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    pub fn axis_aligned_rays() {
+        let model = load_model("cube.obj");
+        let bvh = Bvh::new(&model);
+
+        for direction in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+            let ray = Ray::new(Point3::origin() - direction * 3.0, direction);
+            let hit = bvh.closest_hit(&ray, 0.0, f32::INFINITY).unwrap();
+            assert_abs_diff_eq!(hit.t, 2.0, epsilon = 1e-5);
+            assert_abs_diff_eq!(hit.face_normal, -direction, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    pub fn empty_model() {
+        let model = Model::default();
+        let bvh = Bvh::new(&model);
+        let ray = Ray::new(Point3::origin(), Vector3::unit_z());
+        assert!(bvh.aabb().is_none());
+        assert!(bvh.closest_hit(&ray, 0.0, f32::INFINITY).is_none());
+    }
+}
@@ -1,14 +1,69 @@
+use std::time::Duration;
+
 use cgmath::*;
-use winit::event::*;
+use winit::{dpi::PhysicalSize, event::*};
+
+use crate::{bounds::BoundingSphere, bvh::Ray, ControlEvent};
+
+/// How the camera's view is projected onto the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Vertical field of view in degrees
+    Perspective { fovy: f32 },
+
+    /// Height of the view in world units, so sizes don't change with distance
+    Orthographic { height: f32 },
+
+    /// Perspective with no far plane, vertical field of view in degrees
+    ///
+    /// Depth is reversed, 1 at the near plane falling towards 0 at infinity, which keeps
+    /// far depths precise. Draw with a greater than depth test, clearing to 0
+    InfinitePerspective { fovy: f32 },
 
-use crate::ControlEvent;
+    /// Perspective through an off-axis window, given by the tangents of the angles from
+    /// the view direction to each of its edges, so left & bottom are usually negative
+    ///
+    /// Changing the aspect widens or narrows the window about its middle
+    Frustum {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+    },
+}
+
+impl Projection {
+    /// Whether depth runs from 1 near to 0 far, rather than 0 near to 1 far
+    pub fn reversed_depth(&self) -> bool {
+        matches!(self, Projection::InfinitePerspective { .. })
+    }
+
+    /// Tangents of the angles from the view direction to the left, right, bottom & top
+    /// edges of the view, all 0 for orthographic projections
+    fn tangents(&self, aspect: f32) -> [f32; 4] {
+        match *self {
+            Projection::Perspective { fovy } | Projection::InfinitePerspective { fovy } => {
+                let half_height = Rad::from(Deg(fovy / 2.0)).0.tan();
+                let half_width = half_height * aspect;
+                [-half_width, half_width, -half_height, half_height]
+            }
+            Projection::Orthographic { .. } => [0.0; 4],
+            Projection::Frustum {
+                left,
+                right,
+                bottom,
+                top,
+            } => [left, right, bottom, top],
+        }
+    }
+}
 
 pub struct Camera {
     eye: Point3<f32>,
     target: Point3<f32>,
     up: Vector3<f32>,
     aspect: f32,
-    fovy: f32,
+    projection: Projection,
     znear: f32,
     zfar: f32,
 }
@@ -28,17 +83,223 @@ impl Camera {
             target,
             up,
             aspect,
-            fovy,
+            projection: Projection::Perspective { fovy },
             znear,
             zfar,
         }
     }
 
+    /// Camera with an orthographic projection showing `height` world units vertically
+    pub fn orthographic(
+        eye: Point3<f32>,
+        target: Point3<f32>,
+        up: Vector3<f32>,
+        aspect: f32,
+        height: f32,
+        znear: f32,
+        zfar: f32,
+    ) -> Camera {
+        Camera {
+            projection: Projection::Orthographic { height },
+            ..Camera::new(eye, target, up, aspect, 45.0, znear, zfar)
+        }
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        self.eye
+    }
+
+    pub fn target(&self) -> Point3<f32> {
+        self.target
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.up
+    }
+
+    /// Look from eye towards target, with up along the top of the screen
+    pub fn set_view(&mut self, eye: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) {
+        self.eye = eye;
+        self.target = target;
+        self.up = up;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Width of the view over its height
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    /// Set the width of the view over its height, whenever the window is resized
+    pub fn set_aspect(&mut self, aspect: f32) {
+        if let Projection::Frustum { left, right, .. } = &mut self.projection {
+            let middle = (*left + *right) / 2.0;
+            let scale = aspect / self.aspect;
+            *left = middle + (*left - middle) * scale;
+            *right = middle + (*right - middle) * scale;
+        }
+        self.aspect = aspect;
+    }
+
+    /// Height of the view in world units at the distance of the target
+    pub fn view_height(&self) -> f32 {
+        match self.projection {
+            Projection::Orthographic { height } => height,
+            _ => {
+                let [_, _, bottom, top] = self.projection.tangents(self.aspect);
+                (top - bottom) * (self.target - self.eye).magnitude()
+            }
+        }
+    }
+
     pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         let view = Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        let proj = match self.projection {
+            Projection::Perspective { fovy } => {
+                perspective(Deg(fovy), self.aspect, self.znear, self.zfar)
+            }
+            Projection::InfinitePerspective { .. } => {
+                // Already in wgpu's depth range, z is the near distance & w the distance
+                let [left, right, bottom, top] = self.projection.tangents(self.aspect);
+                #[rustfmt::skip]
+                let proj = Matrix4::new(
+                    2.0 / (right - left), 0.0, 0.0, 0.0,
+                    0.0, 2.0 / (top - bottom), 0.0, 0.0,
+                    (right + left) / (right - left), (top + bottom) / (top - bottom), 0.0, -1.0,
+                    0.0, 0.0, self.znear, 0.0,
+                );
+                return proj * view;
+            }
+            Projection::Frustum {
+                left,
+                right,
+                bottom,
+                top,
+            } => frustum(
+                left * self.znear,
+                right * self.znear,
+                bottom * self.znear,
+                top * self.znear,
+                self.znear,
+                self.zfar,
+            ),
+            Projection::Orthographic { height } => {
+                let (half_width, half_height) = (height * self.aspect / 2.0, height / 2.0);
+                ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+        };
+
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+
+    /// Ray from the eye through a point on the screen, in normalized device coordinates
+    /// from -1 to 1 with y up
+    ///
+    /// Orthographic rays all point the same way, starting from the eye's plane
+    pub fn ray(&self, x: f32, y: f32) -> Ray {
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+
+        match self.projection {
+            Projection::Orthographic { height } => {
+                let (half_width, half_height) = (height * self.aspect / 2.0, height / 2.0);
+                let origin = self.eye + right * (x * half_width) + up * (y * half_height);
+
+                Ray::new(origin, forward)
+            }
+            _ => {
+                let [left, right_edge, bottom, top] = self.projection.tangents(self.aspect);
+                let across = left + (right_edge - left) * (x + 1.0) / 2.0;
+                let above = bottom + (top - bottom) * (y + 1.0) / 2.0;
+                let direction = forward + right * across + up * above;
+
+                Ray::new(self.eye, direction.normalize())
+            }
+        }
+    }
 
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+    /// Position in pixels of a world point, from the top left corner of a viewport, or
+    /// None if it's behind a perspective camera
+    pub fn world_to_screen(
+        &self,
+        point: Point3<f32>,
+        viewport: PhysicalSize<u32>,
+    ) -> Option<Point2<f32>> {
+        let clip = self.build_view_projection_matrix() * point.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        Some(Point2::new(
+            (x + 1.0) / 2.0 * viewport.width as f32,
+            (1.0 - y) / 2.0 * viewport.height as f32,
+        ))
+    }
+
+    /// Ray through a pixel position, from the top left corner of a viewport, for picking
+    /// what's under the cursor
+    pub fn pixel_ray(&self, pixel: Point2<f32>, viewport: PhysicalSize<u32>) -> Ray {
+        let x = pixel.x / viewport.width as f32 * 2.0 - 1.0;
+        let y = 1.0 - pixel.y / viewport.height as f32 * 2.0;
+        self.ray(x, y)
+    }
+
+    /// World point under a pixel position, from the top left corner of a viewport, on
+    /// the plane through the target facing the camera
+    pub fn screen_to_world(&self, pixel: Point2<f32>, viewport: PhysicalSize<u32>) -> Point3<f32> {
+        let ray = self.pixel_ray(pixel, viewport);
+
+        let forward = (self.target - self.eye).normalize();
+        let distance = (self.target - ray.origin).dot(forward) / ray.direction.dot(forward);
+        ray.origin + ray.direction * distance
+    }
+
+    /// Look at the center of the sphere from just far enough away to see all of it,
+    /// keeping the current view direction & fitting the clip planes to the sphere
+    ///
+    /// Orthographic cameras stay back by the sphere's diameter & size the view to fit.
+    /// Off-axis frustums fit the sphere in the part of the view around its direction
+    pub fn frame(&mut self, sphere: &BoundingSphere) {
+        let mut direction = self.eye - self.target;
+        if direction.cross(self.up).magnitude2() <= f32::EPSILON * direction.magnitude2() {
+            // Looking along the up vector leaves the view undefined, look from the front
+            direction = Vector3::unit_z();
+        }
+
+        // The view must fit the sphere in whichever of its width or height is narrower
+        let radius = sphere.radius.max(f32::EPSILON);
+        let distance = match &mut self.projection {
+            Projection::Orthographic { height } => {
+                *height = 2.0 * radius / self.aspect.min(1.0);
+                2.0 * radius
+            }
+            projection => {
+                let [left, right, bottom, top] = projection.tangents(self.aspect);
+                let narrowest = (-left).min(right).min(-bottom).min(top);
+                radius / narrowest.max(f32::EPSILON).atan().sin()
+            }
+        };
+
+        self.target = sphere.center;
+        self.eye = sphere.center + direction.normalize() * distance;
+        self.znear = radius * 0.01;
+        self.zfar = (distance + radius) * 10.0;
     }
 }
 
@@ -51,7 +312,13 @@ const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 );
 
 pub trait ControlMode {
-    fn update_camera(&self, controller: &mut CameraController, camera: &mut Camera);
+    /// Apply input since the last update, delta is the time since then
+    fn update_camera(
+        &self,
+        controller: &mut CameraController,
+        camera: &mut Camera,
+        delta: Duration,
+    );
 }
 
 pub struct CameraController<'a> {
@@ -67,6 +334,24 @@ pub struct CameraController<'a> {
     x_delta: f32,
     y_delta: f32,
 
+    /// Mouse buttons held for dragging
+    is_rotate_dragging: bool,
+    is_pan_dragging: bool,
+
+    /// Scroll wheel lines still to be applied, positive away from the user
+    scroll_delta: f32,
+
+    /// Bounds to frame on the next update
+    focus: Option<BoundingSphere>,
+
+    /// Cursor position in pixels from the top left of the window, & its movement still
+    /// to be applied
+    cursor: Option<Point2<f32>>,
+    cursor_delta: Vector2<f32>,
+
+    /// Size of the window in pixels, for converting between the screen & the world
+    viewport: PhysicalSize<u32>,
+
     mode: &'a dyn ControlMode,
 }
 
@@ -82,7 +367,14 @@ impl<'a> CameraController<'a> {
             is_down_pressed: false,
             x_delta: 0.0,
             y_delta: 0.0,
-            mode: mode,
+            is_rotate_dragging: false,
+            is_pan_dragging: false,
+            scroll_delta: 0.0,
+            focus: None,
+            cursor: None,
+            cursor_delta: Vector2::zero(),
+            viewport: PhysicalSize::new(1, 1),
+            mode,
         }
     }
 
@@ -127,6 +419,41 @@ impl<'a> CameraController<'a> {
                         _ => false,
                     }
                 }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    let is_pressed = *state == ElementState::Pressed;
+                    match button {
+                        MouseButton::Left => {
+                            self.is_rotate_dragging = is_pressed;
+                            true
+                        }
+                        MouseButton::Middle => {
+                            self.is_pan_dragging = is_pressed;
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    self.scroll_delta += match delta {
+                        MouseScrollDelta::LineDelta(_, lines) => *lines,
+                        MouseScrollDelta::PixelDelta(position) => {
+                            position.y as f32 / PIXELS_PER_LINE
+                        }
+                    };
+                    true
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    let position = Point2::new(position.x as f32, position.y as f32);
+                    if let Some(cursor) = self.cursor {
+                        self.cursor_delta += position - cursor;
+                    }
+                    self.cursor = Some(position);
+                    true
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    self.cursor = None;
+                    true
+                }
                 _ => false,
             },
             ControlEvent::DeviceEvent(event) => match event {
@@ -140,15 +467,45 @@ impl<'a> CameraController<'a> {
         }
     }
 
-    pub fn update_camera(&mut self, camera: &mut Camera) {
-        self.mode.update_camera(self, camera);
+    /// Frame the bounds on the next update, keeping the view direction
+    pub fn focus(&mut self, sphere: BoundingSphere) {
+        self.focus = Some(sphere);
+    }
+
+    /// Cursor position in pixels from the top left of the window, if it's over it
+    pub fn cursor(&self) -> Option<Point2<f32>> {
+        self.cursor
+    }
+
+    pub fn viewport(&self) -> PhysicalSize<u32> {
+        self.viewport
+    }
+
+    /// Set the size of the window in pixels, whenever it changes
+    pub fn set_viewport(&mut self, size: PhysicalSize<u32>) {
+        self.viewport = PhysicalSize::new(size.width.max(1), size.height.max(1));
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, delta: Duration) {
+        if let Some(sphere) = self.focus.take() {
+            camera.frame(&sphere);
+        }
+        self.mode.update_camera(self, camera, delta);
     }
 }
 
+/// Scroll distance of one wheel notch for touchpads, which scroll by pixels
+const PIXELS_PER_LINE: f32 = 40.0;
+
 pub struct RotateMode;
 
 impl ControlMode for RotateMode {
-    fn update_camera(&self, controller: &mut CameraController, camera: &mut Camera) {
+    fn update_camera(
+        &self,
+        controller: &mut CameraController,
+        camera: &mut Camera,
+        _delta: Duration,
+    ) {
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
@@ -176,58 +533,325 @@ impl ControlMode for RotateMode {
     }
 }
 
-pub struct MoveMode;
+/// First-person flying, looking around with the mouse & moving with the keys
+///
+/// The controller's speed is the rotation in degrees per pixel the mouse moves
+pub struct MoveMode {
+    /// Furthest the view can tilt up or down, short of looking straight along the up
+    /// vector where turning flips the view
+    pub max_pitch: Deg<f32>,
+
+    /// Distance moved per second while a key is held
+    pub move_speed: f32,
+}
+
+impl MoveMode {
+    pub const fn new() -> Self {
+        MoveMode {
+            max_pitch: Deg(89.0),
+            move_speed: 2.0,
+        }
+    }
+}
+
+impl Default for MoveMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ControlMode for MoveMode {
-    fn update_camera(&self, controller: &mut CameraController, camera: &mut Camera) {
+    fn update_camera(
+        &self,
+        controller: &mut CameraController,
+        camera: &mut Camera,
+        delta: Duration,
+    ) {
         let mut view = camera.target - camera.eye;
 
-        // Handle look first, then move along the view vector
-        let rotate = Matrix3::from_angle_x(Deg::<f32>(controller.y_delta * controller.speed))
-            * Matrix3::from_angle_y(Deg::<f32>(controller.x_delta * controller.speed));
-        view = rotate * view;
-        camera.target = camera.eye + view;
-
+        // Handle look first, then move along the view direction
+        if controller.x_delta != 0.0 || controller.y_delta != 0.0 {
+            view = turn(
+                view,
+                camera.up,
+                Deg(controller.x_delta * controller.speed),
+                Deg(controller.y_delta * controller.speed),
+                self.max_pitch,
+            );
+            camera.target = camera.eye + view;
+        }
         controller.x_delta = 0.0;
         controller.y_delta = 0.0;
 
-        if controller.is_forward_pressed {
-            camera.eye += view * controller.speed;
-            camera.target += view * controller.speed;
+        let forward = view.normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = camera.up.normalize();
+        let held = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let direction = forward
+            * held(
+                controller.is_forward_pressed,
+                controller.is_backward_pressed,
+            )
+            + right * held(controller.is_right_pressed, controller.is_left_pressed)
+            + up * held(controller.is_up_pressed, controller.is_down_pressed);
+
+        // Moving diagonally is no faster than along one axis
+        if direction.magnitude2() > 0.0 {
+            let step = direction.normalize() * (self.move_speed * delta.as_secs_f32());
+            camera.eye += step;
+            camera.target += step;
         }
+    }
+}
 
-        if controller.is_backward_pressed {
-            camera.eye -= view * controller.speed;
-            camera.target -= view * controller.speed;
+/// Turn a direction about the up vector by yaw, then tilt it towards the up vector by
+/// pitch, keeping it within `max_pitch` of level & keeping its length
+///
+/// Turning this way never rolls the horizon, unlike rotating about fixed world axes
+fn turn(
+    direction: Vector3<f32>,
+    up: Vector3<f32>,
+    yaw: Deg<f32>,
+    pitch: Deg<f32>,
+    max_pitch: Deg<f32>,
+) -> Vector3<f32> {
+    let length = direction.magnitude();
+    if length == 0.0 {
+        return direction;
+    }
+    let up = up.normalize();
+    let direction = Basis3::from_axis_angle(up, yaw).rotate_vector(direction / length);
+
+    let mut level = direction - up * direction.dot(up);
+    if level.magnitude2() <= f32::EPSILON {
+        // Looking straight along the up vector, any horizontal direction will do
+        level = if up.x.abs() < 0.9 {
+            up.cross(Vector3::unit_x())
+        } else {
+            up.cross(Vector3::unit_y())
+        };
+    }
+    let limit = Rad::from(max_pitch).0;
+    let pitch =
+        (direction.dot(up).clamp(-1.0, 1.0).asin() + Rad::from(pitch).0).clamp(-limit, limit);
+
+    (level.normalize() * pitch.cos() + up * pitch.sin()) * length
+}
+
+/// Orbit around the target, rotating by left dragging, panning by middle dragging &
+/// moving closer or further away by scrolling
+///
+/// Rotation is an arcball: the cursor drags a ball filling the window, turning the scene
+/// about the axis between where it was & where it is on the ball. The up vector stays
+/// put, so turns about the view direction are left out & the horizon stays level
+pub struct OrbitMode {
+    /// Furthest the view can tilt above or below the target, short of looking straight
+    /// along the up vector where the rotation flips
+    pub max_pitch: Deg<f32>,
+
+    /// Fraction of the distance to the target moved per scroll line
+    pub zoom_step: f32,
+
+    /// Closest the eye can get to the target
+    pub min_distance: f32,
+
+    /// Fraction of the distance to the target panned per pixel dragged
+    pub pan_step: f32,
+}
+
+impl OrbitMode {
+    pub const fn new() -> Self {
+        OrbitMode {
+            max_pitch: Deg(89.0),
+            zoom_step: 0.1,
+            min_distance: 0.01,
+            pan_step: 0.002,
         }
+    }
+}
 
-        if controller.is_right_pressed {
-            camera.eye += view.cross(camera.up) * controller.speed;
-            camera.target += view.cross(camera.up) * controller.speed;
+impl Default for OrbitMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ControlMode for OrbitMode {
+    fn update_camera(
+        &self,
+        controller: &mut CameraController,
+        camera: &mut Camera,
+        _delta: Duration,
+    ) {
+        let up = camera.up.normalize();
+        let mut offset = camera.eye - camera.target;
+        let mut distance = offset.magnitude();
+
+        if let (true, Some(cursor)) = (controller.is_rotate_dragging, controller.cursor) {
+            let viewport = controller.viewport;
+            let from = arcball_point(cursor - controller.cursor_delta, viewport);
+            let to = arcball_point(cursor, viewport);
+            let axis = from.cross(to);
+            if axis.magnitude2() > 0.0 {
+                // The axis is in view space, the eye turns the opposite way to the scene
+                let angle = Deg::from(from.angle(to));
+                let axis = axis.normalize();
+                let back = offset.normalize();
+                let right = up.cross(back).normalize();
+                let screen_up = back.cross(right);
+                let world_axis = right * axis.x + screen_up * axis.y + back * axis.z;
+                offset = turn(
+                    offset,
+                    up,
+                    -angle * world_axis.dot(up),
+                    angle * axis.x,
+                    self.max_pitch,
+                );
+            }
         }
 
-        if controller.is_left_pressed {
-            camera.eye -= view.cross(camera.up) * controller.speed;
-            camera.target -= view.cross(camera.up) * controller.speed;
+        if controller.scroll_delta != 0.0 {
+            distance = (distance * (1.0 - self.zoom_step).powf(controller.scroll_delta))
+                .max(self.min_distance);
+            offset = offset.normalize() * distance;
+        }
+
+        if controller.is_pan_dragging {
+            let forward = -offset.normalize();
+            let right = forward.cross(up).normalize();
+            let screen_up = right.cross(forward);
+            let step = distance * self.pan_step;
+            let pan = screen_up * (controller.cursor_delta.y * step)
+                - right * (controller.cursor_delta.x * step);
+            camera.target += pan;
+        }
+
+        camera.eye = camera.target + offset;
+        controller.x_delta = 0.0;
+        controller.y_delta = 0.0;
+        controller.cursor_delta = Vector2::zero();
+        controller.scroll_delta = 0.0;
+    }
+}
+
+/// Point on a unit ball filling the smaller side of the viewport under the cursor, in view
+/// space with x right, y up & z towards the viewer. Outside the ball the cursor is
+/// taken to its edge
+fn arcball_point(cursor: Point2<f32>, viewport: PhysicalSize<u32>) -> Vector3<f32> {
+    let radius = viewport.width.min(viewport.height) as f32 / 2.0;
+    let x = (cursor.x - viewport.width as f32 / 2.0) / radius;
+    let y = (viewport.height as f32 / 2.0 - cursor.y) / radius;
+    let length2 = x * x + y * y;
+    if length2 <= 1.0 {
+        Vector3::new(x, y, (1.0 - length2).sqrt())
+    } else {
+        Vector3::new(x, y, 0.0) / length2.sqrt()
+    }
+}
+
+/// Flat 2D viewing, panning by dragging with the left or middle button or with the keys
+/// & zooming towards the cursor by scrolling
+///
+/// Dragging moves the view so the point grabbed stays under the cursor
+pub struct PanZoomMode {
+    /// Fraction of the view height zoomed in per scroll line
+    pub zoom_step: f32,
+
+    /// Fraction of the view height panned per second while a key is held
+    pub pan_speed: f32,
+
+    /// Range of view heights in world units that zooming is kept within
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+impl PanZoomMode {
+    pub const fn new() -> Self {
+        PanZoomMode {
+            zoom_step: 0.1,
+            pan_speed: 0.5,
+            min_height: 0.001,
+            max_height: 1000.0,
+        }
+    }
+}
+
+impl Default for PanZoomMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ControlMode for PanZoomMode {
+    fn update_camera(
+        &self,
+        controller: &mut CameraController,
+        camera: &mut Camera,
+        delta: Duration,
+    ) {
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+        let viewport = controller.viewport;
+        let mut pan = Vector3::zero();
+
+        if controller.is_rotate_dragging || controller.is_pan_dragging {
+            // Screen y points down, so dragging down moves the view up
+            let world_per_pixel = camera.view_height() / viewport.height as f32;
+            pan += (up * controller.cursor_delta.y - right * controller.cursor_delta.x)
+                * world_per_pixel;
         }
 
-        if controller.is_up_pressed {
-            camera.eye += camera.up * controller.speed;
-            camera.target += camera.up * controller.speed;
+        let held = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let direction = right * held(controller.is_right_pressed, controller.is_left_pressed)
+            + up * held(
+                controller.is_forward_pressed,
+                controller.is_backward_pressed,
+            );
+        if direction.magnitude2() > 0.0 {
+            pan += direction.normalize()
+                * (camera.view_height() * self.pan_speed * delta.as_secs_f32());
         }
+        camera.eye += pan;
+        camera.target += pan;
 
-        if controller.is_down_pressed {
-            camera.eye -= camera.up * controller.speed;
-            camera.target -= camera.up * controller.speed;
+        if controller.scroll_delta != 0.0 {
+            let height = camera.view_height();
+            let scale = ((1.0 - self.zoom_step).powf(controller.scroll_delta) * height)
+                .clamp(self.min_height, self.max_height)
+                / height;
+
+            // Scaling the view about the cursor keeps the point under it in place
+            let anchor = match controller.cursor {
+                Some(cursor) => camera.screen_to_world(cursor, viewport),
+                None => camera.target,
+            };
+            match &mut camera.projection {
+                Projection::Orthographic { height } => *height *= scale,
+                _ => camera.eye = camera.target + (camera.eye - camera.target) * scale,
+            }
+            let shift = (anchor - camera.target) * (1.0 - scale);
+            camera.eye += shift;
+            camera.target += shift;
         }
+
+        controller.x_delta = 0.0;
+        controller.y_delta = 0.0;
+        controller.cursor_delta = Vector2::zero();
+        controller.scroll_delta = 0.0;
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use cgmath::*;
 
-    use super::{Camera, CameraController, MoveMode};
+    use winit::dpi::PhysicalSize;
+
+    use super::{Camera, CameraController, MoveMode, OrbitMode, PanZoomMode, Projection};
+    use crate::bounds::BoundingSphere;
 
     fn generate_test_camera() -> Camera {
         Camera {
@@ -247,21 +871,23 @@ mod tests {
                 z: 0.0,
             },
             aspect: 1.0,
-            fovy: 45.0,
+            projection: Projection::Perspective { fovy: 45.0 },
             znear: 0.1,
             zfar: 100.0,
         }
     }
 
-    #[test]
+    /// Time between updates in the tests, a frame at 60 fps
+    const FRAME: Duration = Duration::from_micros(16_667);
 
+    #[test]
     pub fn update_x_view_movemode() {
-        let mode = MoveMode {};
+        let mode = MoveMode::new();
         let mut controller = CameraController::new(1.0, &mode);
         let mut camera = generate_test_camera();
 
         controller.x_delta = 90.0;
-        controller.update_camera(&mut camera);
+        controller.update_camera(&mut camera, FRAME);
 
         assert_abs_diff_eq!(
             camera.target,
@@ -269,45 +895,461 @@ mod tests {
                 x: 1.0,
                 y: 1.0,
                 z: -1.0
-            }
+            },
+            epsilon = 1e-5
         );
     }
+
     #[test]
     pub fn update_y_view_movemode() {
-        let mode = MoveMode {};
+        let mode = MoveMode::new();
         let mut controller = CameraController::new(1.0, &mode);
         let mut camera = generate_test_camera();
 
-        controller.y_delta = 90.0;
-        controller.update_camera(&mut camera);
+        // Looking up tilts towards the up vector without changing heading
+        controller.y_delta = 20.0;
+        controller.update_camera(&mut camera, FRAME);
+        let pitch = Rad((1.0f32 / 3.0).sqrt().asin()) + Rad::from(Deg(20.0));
+        let level = Vector3::new(1.0, 0.0, 1.0).normalize();
+        let expected = (level * pitch.cos() + Vector3::unit_y() * pitch.sin()) * 3.0f32.sqrt();
+        assert_abs_diff_eq!(camera.target, Point3::from_vec(expected), epsilon = 1e-5);
 
-        assert_abs_diff_eq!(
-            camera.target,
-            Point3 {
-                x: 1.0,
-                y: -1.0,
-                z: 1.0
-            }
-        );
+        // Stopping short of straight up
+        controller.y_delta = 90.0;
+        controller.update_camera(&mut camera, FRAME);
+        let expected =
+            (level * Deg(89.0).cos() + Vector3::unit_y() * Deg(89.0).sin()) * 3.0f32.sqrt();
+        assert_abs_diff_eq!(camera.target, Point3::from_vec(expected), epsilon = 1e-5);
     }
 
     #[test]
     pub fn update_both_view_movemode() {
-        let mode = MoveMode {};
+        let mode = MoveMode::new();
         let mut controller = CameraController::new(1.0, &mode);
         let mut camera = generate_test_camera();
 
         controller.x_delta = -90.0;
         controller.y_delta = -90.0;
-        controller.update_camera(&mut camera);
+        controller.update_camera(&mut camera, FRAME);
+
+        // Turned right a quarter turn, and tilted down from above the horizon to below
+        let level = Vector3::new(-1.0, 0.0, 1.0).normalize();
+        let pitch = Rad((1.0f32 / 3.0).sqrt().asin()) - Rad::from(Deg(90.0));
+        let expected = (level * pitch.cos() + Vector3::unit_y() * pitch.sin()) * 3.0f32.sqrt();
+        assert_abs_diff_eq!(camera.target, Point3::from_vec(expected), epsilon = 1e-5);
+        assert_eq!(camera.up, Vector3::unit_y());
+    }
+
+    #[test]
+    pub fn mouselook_keeps_the_horizon_level() {
+        let mode = MoveMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+        let mut camera = generate_test_camera();
+
+        // Moving the mouse round in circles comes back to the same view, where rotating
+        // about fixed axes would roll the view a little more every time
+        for _ in 0..10 {
+            for (x, y) in [(30.0, 0.0), (0.0, 30.0), (-30.0, 0.0), (0.0, -30.0)] {
+                controller.x_delta = x;
+                controller.y_delta = y;
+                controller.update_camera(&mut camera, FRAME);
+            }
+        }
+        assert_abs_diff_eq!(camera.target, Point3::new(1.0, 1.0, 1.0), epsilon = 1e-4);
+        assert_abs_diff_eq!(camera.eye, Point3::origin());
+    }
+
+    #[test]
+    pub fn movement_speed() {
+        let mode = MoveMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+
+        // Speed doesn't depend on how far away the target is
+        for distance in [1.0, 50.0] {
+            let mut camera = generate_test_camera();
+            camera.target = Point3::new(0.0, 0.0, -distance);
+
+            controller.is_forward_pressed = true;
+            controller.update_camera(&mut camera, Duration::from_millis(500));
+            assert_abs_diff_eq!(camera.eye, Point3::new(0.0, 0.0, -1.0), epsilon = 1e-5);
+            assert_abs_diff_eq!(camera.target.z, -1.0 - distance, epsilon = 1e-4);
+        }
+
+        // Moving diagonally is no faster, and twice the time goes twice as far
+        let mut camera = generate_test_camera();
+        camera.target = Point3::new(0.0, 0.0, -1.0);
+        controller.is_right_pressed = true;
+        controller.update_camera(&mut camera, Duration::from_secs(1));
+        let diagonal = Vector3::new(1.0, 0.0, -1.0).normalize() * 2.0;
+        assert_abs_diff_eq!(camera.eye, Point3::from_vec(diagonal), epsilon = 1e-5);
+
+        controller.is_forward_pressed = false;
+        controller.is_right_pressed = false;
+        controller.update_camera(&mut camera, Duration::from_secs(1));
+        assert_abs_diff_eq!(camera.eye, Point3::from_vec(diagonal), epsilon = 1e-5);
+    }
+
+    fn orbit_camera() -> Camera {
+        Camera {
+            eye: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::origin(),
+            ..generate_test_camera()
+        }
+    }
+
+    /// Move the cursor by a drag, in a viewport whose arcball is 200 pixels across
+    fn drag(controller: &mut CameraController, from: (f32, f32), to: (f32, f32)) {
+        controller.set_viewport(PhysicalSize::new(400, 200));
+        controller.cursor = Some(Point2::new(to.0, to.1));
+        controller.cursor_delta = Vector2::new(to.0 - from.0, to.1 - from.1);
+    }
+
+    #[test]
+    pub fn orbit_rotation() {
+        let mode = OrbitMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+        let mut camera = orbit_camera();
+
+        // Mouse movement only rotates while dragging
+        drag(&mut controller, (200.0, 100.0), (300.0, 100.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.eye, Point3::new(0.0, 0.0, 5.0));
+
+        // Dragging the middle of the ball to its edge turns the scene a quarter turn
+        // towards the cursor, taking the eye round the other way
+        controller.is_rotate_dragging = true;
+        drag(&mut controller, (200.0, 100.0), (300.0, 100.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.eye, Point3::new(-5.0, 0.0, 0.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(camera.target, Point3::origin());
+
+        // Dragging down tilts the view to look down from above, stopping short of the top
+        let mut camera = orbit_camera();
+        drag(&mut controller, (200.0, 100.0), (200.0, 150.0));
+        controller.update_camera(&mut camera, FRAME);
+        let expected = Point3::new(0.0, Deg(30.0).sin(), Deg(30.0).cos()) * 5.0;
+        assert_abs_diff_eq!(camera.eye, expected, epsilon = 1e-5);
+
+        drag(&mut controller, (200.0, 100.0), (200.0, 200.0));
+        controller.update_camera(&mut camera, FRAME);
+        let expected = Point3::new(0.0, Deg(89.0).sin(), Deg(89.0).cos()) * 5.0;
+        assert_abs_diff_eq!(camera.eye, expected, epsilon = 1e-4);
+    }
+
+    #[test]
+    pub fn arcball_points() {
+        let viewport = PhysicalSize::new(400, 200);
+        let point = |x, y| super::arcball_point(Point2::new(x, y), viewport);
+        assert_abs_diff_eq!(point(200.0, 100.0), Vector3::unit_z());
+        assert_abs_diff_eq!(point(200.0, 0.0), Vector3::unit_y());
+        assert_abs_diff_eq!(point(0.0, 100.0), -Vector3::unit_x());
+        assert_abs_diff_eq!(point(250.0, 100.0), Vector3::new(0.5, 0.0, 0.75f32.sqrt()));
+    }
+
+    #[test]
+    pub fn orbit_dolly_and_pan() {
+        let mode = OrbitMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+        let mut camera = orbit_camera();
+
+        controller.scroll_delta = 1.0;
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.eye, Point3::new(0.0, 0.0, 4.5), epsilon = 1e-5);
+
+        // Scrolling away moves back out, and the eye never reaches the target
+        controller.scroll_delta = -1.0;
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.eye.z, 4.5 / 0.9, epsilon = 1e-4);
+        controller.scroll_delta = 1000.0;
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.eye.z, mode.min_distance, epsilon = 1e-6);
+
+        // Dragging right moves the view left, taking the target with it
+        let mut camera = orbit_camera();
+        controller.is_pan_dragging = true;
+        drag(&mut controller, (0.0, 0.0), (100.0, 0.0));
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.target, Point3::new(-1.0, 0.0, 0.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(camera.eye, Point3::new(-1.0, 0.0, 5.0), epsilon = 1e-5);
+    }
+
+    #[test]
+    pub fn focus_on_bounds() {
+        let mode = OrbitMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+        let mut camera = orbit_camera();
 
+        controller.focus(BoundingSphere {
+            center: Point3::new(10.0, 0.0, 0.0),
+            radius: 1.0,
+        });
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.target, Point3::new(10.0, 0.0, 0.0));
+        assert_abs_diff_eq!((camera.eye - camera.target).normalize(), Vector3::unit_z());
+
+        // Only once
+        camera.target = Point3::origin();
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.target, Point3::origin());
+    }
+
+    #[test]
+    pub fn frame_sphere() {
+        let mut camera = generate_test_camera();
+        camera.projection = Projection::Perspective { fovy: 90.0 };
+        camera.aspect = 2.0;
+
+        let sphere = BoundingSphere {
+            center: Point3::new(1.0, 2.0, 3.0),
+            radius: 2.0,
+        };
+        camera.frame(&sphere);
+
+        assert_abs_diff_eq!(camera.target, sphere.center);
+        let offset = camera.eye - camera.target;
+        assert_abs_diff_eq!(offset.magnitude(), 2.0 * 2.0f32.sqrt(), epsilon = 1e-5);
         assert_abs_diff_eq!(
-            camera.target,
-            Point3 {
-                x: -1.0,
-                y: 1.0,
-                z: -1.0
+            offset.normalize(),
+            Vector3::new(-1.0, -1.0, -1.0).normalize(),
+            epsilon = 1e-5
+        );
+        assert!(camera.znear < offset.magnitude() - sphere.radius);
+        assert!(camera.zfar > offset.magnitude() + sphere.radius);
+    }
+
+    #[test]
+    pub fn screen_rays() {
+        let mut camera = generate_test_camera();
+        camera.target = Point3::new(0.0, 0.0, -1.0);
+        camera.projection = Projection::Perspective { fovy: 90.0 };
+        camera.aspect = 2.0;
+
+        let center = camera.ray(0.0, 0.0);
+        assert_abs_diff_eq!(center.origin, camera.eye);
+        assert_abs_diff_eq!(center.direction, -Vector3::unit_z());
+
+        let corner = camera.ray(1.0, 1.0);
+        assert_abs_diff_eq!(
+            corner.direction,
+            Vector3::new(2.0, 1.0, -1.0).normalize(),
+            epsilon = 1e-6
+        );
+    }
+
+    fn ortho_camera() -> Camera {
+        Camera::orthographic(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::origin(),
+            Vector3::unit_y(),
+            2.0,
+            4.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    const VIEWPORT: PhysicalSize<u32> = PhysicalSize::new(800, 400);
+
+    #[test]
+    pub fn orthographic_projection() {
+        let mut camera = ortho_camera();
+
+        // Sizes don't change with distance, & rays are parallel
+        let near = camera.build_view_projection_matrix() * Vector4::new(4.0, 2.0, 1.0, 1.0);
+        let far = camera.build_view_projection_matrix() * Vector4::new(4.0, 2.0, -50.0, 1.0);
+        assert_abs_diff_eq!(near.truncate().truncate() / near.w, Vector2::new(1.0, 1.0));
+        assert_abs_diff_eq!(far.truncate().truncate() / far.w, Vector2::new(1.0, 1.0));
+        let ray = camera.ray(1.0, -1.0);
+        assert_abs_diff_eq!(ray.origin, Point3::new(4.0, -2.0, 5.0));
+        assert_abs_diff_eq!(ray.direction, -Vector3::unit_z());
+
+        // Framing sizes the view to fit the narrower side
+        camera.frame(&BoundingSphere {
+            center: Point3::new(1.0, 1.0, 0.0),
+            radius: 3.0,
+        });
+        assert_abs_diff_eq!(camera.view_height(), 6.0);
+        assert_abs_diff_eq!(camera.eye, Point3::new(1.0, 1.0, 6.0));
+        camera.aspect = 0.5;
+        camera.frame(&BoundingSphere {
+            center: Point3::origin(),
+            radius: 3.0,
+        });
+        assert_abs_diff_eq!(camera.view_height(), 12.0);
+    }
+
+    #[test]
+    pub fn world_screen_conversion() {
+        let camera = ortho_camera();
+
+        // Pixels count from the top left with y down
+        let corner = camera.world_to_screen(Point3::new(-4.0, 2.0, 0.0), VIEWPORT);
+        assert_abs_diff_eq!(corner.unwrap(), Point2::new(0.0, 0.0));
+        let corner = camera.world_to_screen(Point3::new(4.0, -2.0, 0.0), VIEWPORT);
+        assert_abs_diff_eq!(corner.unwrap(), Point2::new(800.0, 400.0));
+
+        for camera in [camera, generate_test_camera()] {
+            let pixel = Point2::new(123.0, 321.0);
+            let world = camera.screen_to_world(pixel, VIEWPORT);
+            let back = camera.world_to_screen(world, VIEWPORT).unwrap();
+            assert_abs_diff_eq!(back, pixel, epsilon = 1e-3);
+        }
+
+        // Nothing behind a perspective camera is on screen
+        let behind =
+            generate_test_camera().world_to_screen(Point3::new(-1.0, -1.0, -1.0), VIEWPORT);
+        assert!(behind.is_none());
+    }
+
+    #[test]
+    pub fn pan_by_dragging() {
+        let mode = PanZoomMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+        controller.set_viewport(VIEWPORT);
+        let mut camera = ortho_camera();
+
+        // The point grabbed follows the cursor exactly
+        let start = Point2::new(100.0, 100.0);
+        let end = Point2::new(250.0, 40.0);
+        let grabbed = camera.screen_to_world(start, VIEWPORT);
+        controller.is_rotate_dragging = true;
+        controller.cursor = Some(end);
+        controller.cursor_delta = end - start;
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(
+            camera.world_to_screen(grabbed, VIEWPORT).unwrap(),
+            end,
+            epsilon = 1e-3
+        );
+        assert_abs_diff_eq!(camera.eye - camera.target, Vector3::new(0.0, 0.0, 5.0));
+
+        // Moving without a button held does nothing
+        controller.is_rotate_dragging = false;
+        controller.cursor_delta = Vector2::new(50.0, 50.0);
+        let target = camera.target;
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.target, target);
+    }
+
+    #[test]
+    pub fn zoom_towards_cursor() {
+        let mode = PanZoomMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+        controller.set_viewport(VIEWPORT);
+
+        let mut perspective = generate_test_camera();
+        perspective.aspect = 2.0;
+        for mut camera in [ortho_camera(), perspective] {
+            let cursor = Point2::new(600.0, 300.0);
+            let under = camera.screen_to_world(cursor, VIEWPORT);
+            let height = camera.view_height();
+
+            controller.cursor = Some(cursor);
+            controller.scroll_delta = 2.0;
+            controller.update_camera(&mut camera, FRAME);
+            assert_abs_diff_eq!(camera.view_height(), height * 0.81, epsilon = 1e-4);
+            assert_abs_diff_eq!(
+                camera.world_to_screen(under, VIEWPORT).unwrap(),
+                cursor,
+                epsilon = 1e-2
+            );
+        }
+
+        // Zooming stops at the limits
+        let mut camera = ortho_camera();
+        controller.scroll_delta = -1000.0;
+        controller.update_camera(&mut camera, FRAME);
+        assert_abs_diff_eq!(camera.view_height(), mode.max_height, epsilon = 1e-2);
+    }
+
+    #[test]
+    pub fn pan_with_keys() {
+        let mode = PanZoomMode::new();
+        let mut controller = CameraController::new(1.0, &mode);
+        let mut camera = ortho_camera();
+
+        // Half the view height per second, up & to the right
+        controller.is_forward_pressed = true;
+        controller.update_camera(&mut camera, Duration::from_secs(1));
+        assert_abs_diff_eq!(camera.target, Point3::new(0.0, 2.0, 0.0));
+        controller.is_forward_pressed = false;
+        controller.is_right_pressed = true;
+        controller.update_camera(&mut camera, Duration::from_millis(500));
+        assert_abs_diff_eq!(camera.target, Point3::new(1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    pub fn infinite_reversed_perspective() {
+        let mut camera = generate_test_camera();
+        camera.eye = Point3::new(0.0, 0.0, 5.0);
+        camera.target = Point3::origin();
+        let finite = camera.build_view_projection_matrix();
+        camera.projection = Projection::InfinitePerspective { fovy: 45.0 };
+        let infinite = camera.build_view_projection_matrix();
+
+        // Same picture, but depth runs from 1 at the near plane towards 0 at infinity
+        let ndc = |matrix: Matrix4<f32>, point: Vector3<f32>| {
+            let clip = matrix * point.extend(1.0);
+            clip.truncate() / clip.w
+        };
+        let point = Vector3::new(0.3, -0.2, 1.0);
+        assert_abs_diff_eq!(
+            ndc(finite, point).truncate(),
+            ndc(infinite, point).truncate(),
+            epsilon = 1e-5
+        );
+        assert_abs_diff_eq!(
+            ndc(infinite, Vector3::new(0.0, 0.0, 4.9)).z,
+            1.0,
+            epsilon = 1e-5
+        );
+        let far = ndc(infinite, Vector3::new(0.0, 0.0, -1e6)).z;
+        assert!(far > 0.0 && far < 1e-6);
+        assert!(camera.projection.reversed_depth());
+    }
+
+    #[test]
+    pub fn off_axis_frustum() {
+        let mut camera = generate_test_camera();
+        camera.eye = Point3::new(0.0, 0.0, 5.0);
+        camera.target = Point3::origin();
+        camera.projection = Projection::Frustum {
+            left: -0.1,
+            right: 0.3,
+            bottom: -0.2,
+            top: 0.2,
+        };
+
+        // Rays through the edges of the screen follow the edges of the window
+        assert_abs_diff_eq!(
+            camera.ray(1.0, 1.0).direction,
+            Vector3::new(0.3, 0.2, -1.0).normalize()
+        );
+        assert_abs_diff_eq!(
+            camera.ray(-1.0, 0.0).direction,
+            Vector3::new(-0.1, 0.0, -1.0).normalize()
+        );
+        let corner = camera.world_to_screen(Point3::new(1.5, -1.0, 0.0), VIEWPORT);
+        assert_abs_diff_eq!(corner.unwrap(), Point2::new(800.0, 400.0), epsilon = 1e-3);
+        assert_abs_diff_eq!(camera.view_height(), 2.0);
+
+        // A wider window keeps its middle
+        camera.set_aspect(2.0);
+        assert_eq!(
+            camera.projection,
+            Projection::Frustum {
+                left: -0.3,
+                right: 0.5,
+                bottom: -0.2,
+                top: 0.2,
             }
         );
+
+        // Framing fits the sphere in the narrowest part of the view around its direction
+        camera.frame(&BoundingSphere {
+            center: Point3::origin(),
+            radius: 1.0,
+        });
+        assert_abs_diff_eq!(camera.eye.z, 1.0 / 0.2f32.atan().sin(), epsilon = 1e-4);
     }
 }
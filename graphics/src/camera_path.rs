@@ -0,0 +1,274 @@
+//! Camera fly-throughs along curves
+//!
+//! The camera moves along a curve at a constant speed by measuring distance along its
+//! points rather than the curve's parameter, which speeds up & slows down between
+//! control points. The curve's frames give the roll, keeping each binormal up
+
+use std::time::Duration;
+
+use cgmath::*;
+
+use crate::{
+    animation::Track,
+    camera::Camera,
+    curve::{Curve, CurveFrame},
+};
+
+/// Where a camera on a path looks
+#[derive(Debug, Clone)]
+pub enum LookAt {
+    /// Ahead along the curve
+    Tangent,
+
+    Point(Point3<f32>),
+
+    /// A target moving over time, sampled at the seconds since the start of the path
+    Track(Track<Vector3<f32>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    frames: Vec<CurveFrame>,
+
+    /// Distance along the path to each frame
+    distances: Vec<f32>,
+
+    /// Distance moved per second
+    pub speed: f32,
+    pub look_at: LookAt,
+
+    /// Whether to start over at the end, rather than stopping
+    pub looping: bool,
+    distance: f32,
+}
+
+impl CameraPath {
+    /// Path along a curve sampled `steps` times, or at its own points for curves with a
+    /// fixed resolution
+    pub fn new<T: Curve>(curve: &T, steps: u32, speed: f32) -> Self {
+        let frames = curve.frames(steps);
+        let mut distances = Vec::with_capacity(frames.len());
+        let mut total = 0.0;
+        for (i, frame) in frames.iter().enumerate() {
+            if i > 0 {
+                total += (frame.position - frames[i - 1].position).magnitude();
+            }
+            distances.push(total);
+        }
+
+        CameraPath {
+            frames,
+            distances,
+            speed,
+            look_at: LookAt::Tangent,
+            looping: false,
+            distance: 0.0,
+        }
+    }
+
+    /// Length of the path
+    pub fn length(&self) -> f32 {
+        self.distances.last().copied().unwrap_or(0.0)
+    }
+
+    /// Distance travelled from the start
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn seek(&mut self, distance: f32) {
+        let length = self.length();
+        self.distance = if self.looping && length > 0.0 {
+            distance.rem_euclid(length)
+        } else {
+            distance.clamp(0.0, length)
+        };
+    }
+
+    /// Position & frame at a distance along the path, blended between the nearest
+    /// frames. None if the curve had no points
+    pub fn frame_at(&self, distance: f32) -> Option<CurveFrame> {
+        let first = *self.frames.first()?;
+        let index = self
+            .distances
+            .partition_point(|reached| *reached <= distance);
+        if index == 0 {
+            return Some(first);
+        }
+        let (previous, next) = match self.frames.get(index) {
+            Some(frame) => (self.frames[index - 1], *frame),
+            None => return self.frames.last().copied(),
+        };
+
+        let span = next.position - previous.position;
+        let amount = (distance - self.distances[index - 1]) / span.magnitude();
+
+        // Blended frames are no longer orthonormal, so rebuild them about the tangent
+        let tangent = previous.tangent.lerp(next.tangent, amount).normalize();
+        let normal = previous.normal.lerp(next.normal, amount);
+        let normal = (normal - tangent * tangent.dot(normal)).normalize();
+        Some(CurveFrame {
+            position: previous.position + span * amount,
+            tangent,
+            normal,
+            binormal: tangent.cross(normal),
+        })
+    }
+
+    /// Move along the path by a frame delta & place the camera there
+    pub fn update(&mut self, delta: Duration, camera: &mut Camera) {
+        self.seek(self.distance + self.speed * delta.as_secs_f32());
+        self.apply(camera);
+    }
+
+    /// Place the camera at the current distance along the path
+    pub fn apply(&self, camera: &mut Camera) {
+        let frame = match self.frame_at(self.distance) {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        let target = match &self.look_at {
+            LookAt::Tangent => frame.position + frame.tangent,
+            LookAt::Point(point) => *point,
+            LookAt::Track(track) => {
+                let time = if self.speed != 0.0 {
+                    self.distance / self.speed
+                } else {
+                    0.0
+                };
+                match track.sample(time) {
+                    Some(target) => Point3::from_vec(target),
+                    None => frame.position + frame.tangent,
+                }
+            }
+        };
+
+        // Looking along the binormal leaves it useless as up, so fall back to the normal
+        let forward = target - frame.position;
+        let up = if forward.cross(frame.binormal).magnitude2() > 1e-6 * forward.magnitude2() {
+            frame.binormal
+        } else {
+            frame.normal
+        };
+        camera.set_view(frame.position, target, up);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cgmath::*;
+
+    use crate::{
+        animation::{Interpolation, Keyframe, Track},
+        camera::Camera,
+        curve::{BezierCurve, Curve},
+        swp,
+    };
+
+    use super::{CameraPath, LookAt};
+
+    fn camera() -> Camera {
+        Camera::new(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::origin(),
+            Vector3::unit_y(),
+            1.0,
+            45.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    /// Straight line along x whose control points bunch up at the start, so equal steps
+    /// of the parameter cover very different distances
+    fn uneven_line() -> BezierCurve {
+        BezierCurve {
+            control_points: Matrix4::from_cols(
+                Vector4::new(0.0, 0.0, 0.0, 1.0),
+                Vector4::new(0.1, 0.0, 0.0, 1.0),
+                Vector4::new(0.2, 0.0, 0.0, 1.0),
+                Vector4::new(3.0, 0.0, 0.0, 1.0),
+            ),
+        }
+    }
+
+    #[test]
+    pub fn constant_speed() {
+        let mut path = CameraPath::new(&uneven_line(), 100, 1.0);
+        let mut camera = camera();
+        assert_abs_diff_eq!(path.length(), 3.0, epsilon = 1e-4);
+
+        for step in 1..=4 {
+            path.update(Duration::from_millis(500), &mut camera);
+            let expected = Point3::new(step as f32 * 0.5, 0.0, 0.0);
+            assert_abs_diff_eq!(camera.eye(), expected, epsilon = 1e-4);
+            assert_abs_diff_eq!(
+                camera.target(),
+                expected + Vector3::unit_x(),
+                epsilon = 1e-4
+            );
+            assert_abs_diff_eq!(camera.up(), Vector3::unit_y(), epsilon = 1e-4);
+        }
+
+        // Stops at the end, or starts over when looping
+        path.update(Duration::from_secs(5), &mut camera);
+        assert_abs_diff_eq!(camera.eye(), Point3::new(3.0, 0.0, 0.0), epsilon = 1e-4);
+        path.looping = true;
+        path.seek(4.0);
+        assert_abs_diff_eq!(path.distance(), 1.0, epsilon = 1e-4);
+    }
+
+    #[test]
+    pub fn zero_steps_still_span_the_curve() {
+        let frames = uneven_line().frames(0);
+        assert_eq!(frames.len(), 2);
+        assert_abs_diff_eq!(frames[1].position, Point3::new(3.0, 0.0, 0.0));
+
+        let path = CameraPath::new(&uneven_line(), 0, 1.0);
+        assert_abs_diff_eq!(path.length(), 3.0);
+    }
+
+    #[test]
+    pub fn look_at_targets() {
+        let mut path = CameraPath::new(&uneven_line(), 100, 1.0);
+        let mut camera = camera();
+
+        path.look_at = LookAt::Point(Point3::new(1.0, 5.0, 0.0));
+        path.seek(1.0);
+        path.apply(&mut camera);
+        assert_abs_diff_eq!(camera.target(), Point3::new(1.0, 5.0, 0.0));
+
+        // Looking straight up the binormal, the normal is up instead
+        assert_abs_diff_eq!(camera.up(), -Vector3::unit_z(), epsilon = 1e-4);
+
+        // A moving target is sampled at the time taken to get here
+        path.look_at = LookAt::Track(Track::new(
+            vec![
+                Keyframe::new(0.0, Vector3::new(0.0, 0.0, -1.0)),
+                Keyframe::new(4.0, Vector3::new(4.0, 0.0, -1.0)),
+            ],
+            Interpolation::Linear,
+        ));
+        path.update(Duration::from_secs(1), &mut camera);
+        assert_abs_diff_eq!(camera.target(), Point3::new(2.0, 0.0, -1.0), epsilon = 1e-4);
+    }
+
+    #[test]
+    pub fn rolls_with_swept_frames() {
+        // Flying around the inside of a circle in the xy plane keeps +z up
+        let scene = swp::parse_scene("circ ring 40 2").unwrap();
+        let mut path = CameraPath::new(&scene.curves[0], 0, 1.0);
+        let mut camera = camera();
+        while path.distance() < path.length() * 0.9 {
+            path.update(Duration::from_millis(300), &mut camera);
+            let eye = camera.eye().to_vec();
+            assert_abs_diff_eq!(eye.magnitude(), 2.0, epsilon = 0.02);
+            assert_abs_diff_eq!(camera.up(), Vector3::unit_z(), epsilon = 1e-4);
+            let forward = (camera.target() - camera.eye()).normalize();
+            assert_abs_diff_eq!(forward.dot(eye.normalize()), 0.0, epsilon = 0.05);
+        }
+    }
+}
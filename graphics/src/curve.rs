@@ -1,19 +1,92 @@
 use std::ops::Range;
 
-use cgmath::{num_traits::Pow, Matrix4, Vector4};
+use cgmath::{num_traits::Pow, InnerSpace, Matrix4, Point3, Vector3, Vector4};
 use wgpu::*;
 
 use crate::model::Vertex;
 
 pub trait Curve {
     fn to_vertices(&self, range: Range<f32>, steps: u32) -> Vec<CurveVertex>;
+
+    /// Points from the start to the end of the curve, each with a reference frame
+    ///
+    /// By default frames are carried along the points of `to_vertices` without twisting
+    fn frames(&self, steps: u32) -> Vec<CurveFrame> {
+        let points: Vec<Point3<f32>> = self
+            .to_vertices(0.0..1.0, steps)
+            .iter()
+            .map(|vertex| vertex.position.into())
+            .collect();
+        transport_frames(&points, None, Vector3::unit_y())
+    }
+
+    /// Points shaping the curve that can be picked & moved, none by default
+    fn control_points(&self) -> Vec<Point3<f32>> {
+        vec![]
+    }
+}
+
+/// Point on a curve with an orthonormal frame, the binormal is tangent x normal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurveFrame {
+    pub position: Point3<f32>,
+    pub tangent: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub binormal: Vector3<f32>,
+}
+
+/// Frames along a polyline that turn with it without twisting about the tangent,
+/// starting with the binormal as close to `up` as possible
+///
+/// Tangents come from `tangents` where the curve's derivative is known, otherwise from
+/// the neighbouring points. A point with no direction keeps the last tangent
+pub fn transport_frames(
+    points: &[Point3<f32>],
+    tangents: Option<&[Vector3<f32>]>,
+    up: Vector3<f32>,
+) -> Vec<CurveFrame> {
+    let mut frames: Vec<CurveFrame> = Vec::with_capacity(points.len());
+    for (i, position) in points.iter().enumerate() {
+        let direction = match tangents {
+            Some(tangents) => tangents[i],
+            None => points[(i + 1).min(points.len() - 1)] - points[i.saturating_sub(1)],
+        };
+        let tangent = if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            frames
+                .last()
+                .map_or(Vector3::unit_x(), |frame| frame.tangent)
+        };
+
+        // Remove the part of the last binormal along the new tangent
+        let reference = frames.last().map_or(up, |frame| frame.binormal);
+        let mut binormal = reference - tangent * tangent.dot(reference);
+        if binormal.magnitude2() < 1e-8 {
+            // The curve turned onto the last binormal, or started along `up`
+            binormal = if tangent.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+        }
+        let normal = binormal.cross(tangent).normalize();
+        frames.push(CurveFrame {
+            position: *position,
+            tangent,
+            normal,
+            binormal: tangent.cross(normal),
+        });
+    }
+
+    frames
 }
 
 /// Curve Vertex
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CurveVertex {
-    position: [f32; 3],
+    pub position: [f32; 3],
 }
 
 impl Vertex for CurveVertex {
@@ -37,33 +110,44 @@ pub struct BezierCurve {
 
 impl Curve for BezierCurve {
     fn to_vertices(&self, range: Range<f32>, steps: u32) -> Vec<CurveVertex> {
-        let mut curve = vec![];
-
         // Add the control points first
-        curve.push(CurveVertex {
-            position: [self.control_points.x.x, self.control_points.x.y, self.control_points.x.z]
-        });
-        curve.push(CurveVertex {
-            position: [self.control_points.y.x, self.control_points.y.y, self.control_points.y.z]
-        });
-        curve.push(CurveVertex {
-            position: [self.control_points.z.x, self.control_points.z.y, self.control_points.z.z]
-        });
-        curve.push(CurveVertex {
-            position: [self.control_points.w.x, self.control_points.w.y, self.control_points.w.z]
-        });
-
-        // firstly create the cubic function in the canonical basis
-        let canonical = |t: f32| -> Vector4<f32> {
-            self.control_points * BEZIER_SPLINE * Vector4::new(1.0, t, t.pow(2.0), t.pow(3.0))
-        };
+        let mut curve = vec![
+            CurveVertex {
+                position: [
+                    self.control_points.x.x,
+                    self.control_points.x.y,
+                    self.control_points.x.z,
+                ],
+            },
+            CurveVertex {
+                position: [
+                    self.control_points.y.x,
+                    self.control_points.y.y,
+                    self.control_points.y.z,
+                ],
+            },
+            CurveVertex {
+                position: [
+                    self.control_points.z.x,
+                    self.control_points.z.y,
+                    self.control_points.z.z,
+                ],
+            },
+            CurveVertex {
+                position: [
+                    self.control_points.w.x,
+                    self.control_points.w.y,
+                    self.control_points.w.z,
+                ],
+            },
+        ];
 
         for i in 0..steps + 1 {
             let t = (range.end - range.start) / steps as f32 * i as f32;
 
-            let point = canonical(t);
+            let point = self.point(t);
             curve.push(CurveVertex {
-                position: [point.x, point.y, point.z],
+                position: point.into(),
             })
         }
 
@@ -71,9 +155,38 @@ impl Curve for BezierCurve {
         curve
     }
 
+    /// The four points of the control matrix's columns
+    fn control_points(&self) -> Vec<Point3<f32>> {
+        let m = self.control_points;
+        [m.x, m.y, m.z, m.w]
+            .iter()
+            .map(|column| Point3::new(column.x, column.y, column.z))
+            .collect()
+    }
+
+    /// Frames along the curve itself, leaving out the control points, with at least
+    /// one step from the start to the end
+    fn frames(&self, steps: u32) -> Vec<CurveFrame> {
+        let steps = steps.max(1);
+        let points: Vec<Point3<f32>> = (0..steps + 1)
+            .map(|i| self.point(i as f32 / steps as f32))
+            .collect();
+        transport_frames(&points, None, Vector3::unit_y())
+    }
+
     // TODO: Try De Casteljau's algorithm for rendering the points
 }
 
+impl BezierCurve {
+    /// Point at t from 0 to 1 along the curve
+    pub fn point(&self, t: f32) -> Point3<f32> {
+        // firstly create the cubic function in the canonical basis
+        let canonical =
+            self.control_points * BEZIER_SPLINE * Vector4::new(1.0, t, t.pow(2.0), t.pow(3.0));
+        Point3::new(canonical.x, canonical.y, canonical.z)
+    }
+}
+
 const BEZIER_SPLINE: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0, -3.0, 3.0, 0.0, 0.0, 3.0, -6.0, 3.0, 0.0, 1.0, 3.0, -3.0, 1.0,
 );
@@ -0,0 +1,1272 @@
+//! A glTF 2.0 model loading & saving module
+//!
+//! Supports both `.gltf` files (with external or data URI buffers) and binary `.glb` files
+
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Transform as _, Vector3};
+use gltf::{buffer, image, mesh::Mode, texture, Document, Gltf};
+use serde_json::{json, Value};
+
+use crate::{
+    model::{Material, Mesh, Model, ModelVertex},
+    normals::NormalMode,
+    transform::Transform,
+};
+
+/// A glTF scene, keeping the node hierarchy alongside the model
+#[derive(Debug, Default)]
+pub struct GltfScene {
+    /// Untransformed meshes & materials. Each glTF primitive becomes one mesh
+    pub model: Model,
+
+    /// Every node in the file, indexed the same as the glTF node array
+    pub nodes: Vec<GltfNode>,
+
+    /// Indices of the nodes at the root of the scene
+    pub roots: Vec<usize>,
+}
+
+/// A node in a glTF scene
+#[derive(Debug, Clone)]
+pub struct GltfNode {
+    pub name: String,
+
+    /// Transform of the node relative to its parent
+    pub transform: Transform,
+
+    /// Indices of the meshes drawn by this node in the scene model
+    pub meshes: Vec<usize>,
+
+    /// Indices of the child nodes
+    pub children: Vec<usize>,
+}
+
+/// Load a glTF file as a single model, with node transforms baked into the vertices
+pub fn load_model(file: &Path) -> Result<Model, GltfLoadError> {
+    load_scene(file).map(|scene| scene.flatten())
+}
+
+/// Load a glTF file, keeping the node hierarchy
+pub fn load_scene(file: &Path) -> Result<GltfScene, GltfLoadError> {
+    let raw_gltf = match std::fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(GltfLoadError::FileLoadError(err)),
+    };
+
+    parse_scene(&raw_gltf, file.parent().unwrap_or_else(|| Path::new("")))
+}
+
+fn parse_scene(raw_gltf: &[u8], dir: &Path) -> Result<GltfScene, GltfLoadError> {
+    let Gltf { document, blob } = match Gltf::from_slice(raw_gltf) {
+        Ok(gltf) => gltf,
+        Err(err) => return Err(GltfLoadError::InvalidGltf(err)),
+    };
+
+    let mut buffers = vec![];
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            buffer::Source::Bin => match &blob {
+                Some(blob) => blob.clone(),
+                None => return Err(GltfLoadError::MissingBinaryChunk),
+            },
+            buffer::Source::Uri(uri) => load_uri(uri, dir)?,
+        };
+
+        if data.len() < buffer.length() {
+            return Err(GltfLoadError::InvalidBufferLength);
+        }
+        buffers.push(data);
+    }
+
+    let mut scene = GltfScene::default();
+    for material in document.materials() {
+        let material = load_material(&material, &buffers, dir)?;
+        scene.model.materials.push(material);
+    }
+
+    // Primitives are flattened into the mesh list, so remember where each glTF mesh starts
+    let mut mesh_ranges = vec![];
+    for mesh in document.meshes() {
+        let start = scene.model.meshes.len();
+        for primitive in mesh.primitives() {
+            if let Some(loaded) = load_primitive(&mesh, &primitive, &buffers)? {
+                scene.model.meshes.push(loaded);
+            }
+        }
+        mesh_ranges.push(start..scene.model.meshes.len());
+    }
+
+    for node in document.nodes() {
+        scene.nodes.push(GltfNode {
+            name: node.name().unwrap_or_default().to_string(),
+            transform: Transform::from_matrix(Matrix4::from(node.transform().matrix())),
+            meshes: match node.mesh() {
+                Some(mesh) => mesh_ranges[mesh.index()].clone().collect(),
+                None => vec![],
+            },
+            children: node.children().map(|child| child.index()).collect(),
+        });
+    }
+
+    scene.roots = root_nodes(&document);
+
+    Ok(scene)
+}
+
+/// Find the root nodes of the default scene, falling back to the first scene
+/// and then to every node without a parent
+fn root_nodes(document: &Document) -> Vec<usize> {
+    match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => {
+            let mut is_child = vec![false; document.nodes().len()];
+            for node in document.nodes() {
+                for child in node.children() {
+                    is_child[child.index()] = true;
+                }
+            }
+
+            (0..is_child.len()).filter(|i| !is_child[*i]).collect()
+        }
+    }
+}
+
+fn load_primitive(
+    mesh: &gltf::Mesh,
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+) -> Result<Option<Mesh>, GltfLoadError> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| data.as_slice()));
+
+    let positions: Vec<[f32; 3]> = match reader.read_positions() {
+        Some(positions) => positions.collect(),
+        None => return Err(GltfLoadError::MissingPositions),
+    };
+    let texture_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+        Some(coords) => coords.into_f32().collect(),
+        None => vec![[0.0; 2]; positions.len()],
+    };
+    // Missing normals are generated once the mesh is built
+    let (has_normals, normals): (bool, Vec<[f32; 3]>) = match reader.read_normals() {
+        Some(normals) => (true, normals.collect()),
+        None => (false, vec![[0.0; 3]; positions.len()]),
+    };
+
+    if texture_coords.len() != positions.len() || normals.len() != positions.len() {
+        return Err(GltfLoadError::InvalidAttributeCount);
+    }
+
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if indices.iter().any(|i| *i as usize >= positions.len()) {
+        return Err(GltfLoadError::InvalidIndex);
+    }
+
+    let indices = match primitive.mode() {
+        Mode::Triangles => indices,
+        Mode::TriangleStrip => strip_to_list(&indices),
+        Mode::TriangleFan => fan_to_list(&indices),
+        // Ignore points & lines
+        _ => return Ok(None),
+    };
+
+    let mut loaded = Mesh {
+        name: mesh.name().unwrap_or_default().to_string(),
+        vertices: positions
+            .into_iter()
+            .zip(texture_coords)
+            .zip(normals)
+            .map(|((position, texture_coords), normal)| {
+                ModelVertex::new(position, texture_coords, normal)
+            })
+            .collect(),
+        indices,
+        material: primitive.material().index(),
+        ..Default::default()
+    };
+    if !has_normals {
+        loaded.compute_normals(NormalMode::AngleWeighted, None);
+    }
+
+    // Generated normals invalidate any tangents in the file
+    match reader.read_tangents() {
+        Some(tangents) if has_normals && tangents.len() == loaded.vertices.len() => {
+            for (vertex, tangent) in loaded.vertices.iter_mut().zip(tangents) {
+                vertex.tangent = tangent;
+            }
+        }
+        _ => loaded.compute_tangents(),
+    }
+
+    Ok(Some(loaded))
+}
+
+fn strip_to_list(strip: &[u32]) -> Vec<u32> {
+    let mut list = vec![];
+    for i in 2..strip.len() {
+        // Every other triangle in a strip has flipped winding
+        if i % 2 == 0 {
+            list.extend_from_slice(&[strip[i - 2], strip[i - 1], strip[i]]);
+        } else {
+            list.extend_from_slice(&[strip[i - 1], strip[i - 2], strip[i]]);
+        }
+    }
+
+    list
+}
+
+fn fan_to_list(fan: &[u32]) -> Vec<u32> {
+    let mut list = vec![];
+    for i in 2..fan.len() {
+        list.extend_from_slice(&[fan[0], fan[i - 1], fan[i]]);
+    }
+
+    list
+}
+
+fn load_material(
+    material: &gltf::Material,
+    buffers: &[Vec<u8>],
+    dir: &Path,
+) -> Result<Material, GltfLoadError> {
+    let pbr = material.pbr_metallic_roughness();
+    let base_color = pbr.base_color_factor();
+
+    let mut loaded = Material {
+        diffuse_color: [base_color[0], base_color[1], base_color[2]],
+        ambient_color: [base_color[0], base_color[1], base_color[2]],
+        emissive_color: material.emissive_factor(),
+        opacity: base_color[3],
+        // glTF assumes an index of refraction of 1.5 without the KHR_materials_ior extension
+        optical_density: 1.5,
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        // Dielectric F0 of ~4% reflectance & a Blinn-Phong exponent matching the roughness
+        specular_color: [0.04; 3],
+        specular_exponent: (2.0 / pbr.roughness_factor().powi(4).max(1e-4) - 2.0).min(1024.0),
+        ..Default::default()
+    };
+
+    if let Some(info) = pbr.base_color_texture() {
+        (loaded.diffuse_texture_file, loaded.diffuse_texture_data) =
+            load_texture(&info.texture(), buffers, dir)?;
+    }
+    if let Some(info) = pbr.metallic_roughness_texture() {
+        (
+            loaded.metallic_roughness_texture_file,
+            loaded.metallic_roughness_texture_data,
+        ) = load_texture(&info.texture(), buffers, dir)?;
+    }
+    if let Some(normal) = material.normal_texture() {
+        (loaded.bump_map_file, loaded.bump_map_data) =
+            load_texture(&normal.texture(), buffers, dir)?;
+        loaded.bump_multiplier = normal.scale();
+    }
+
+    Ok(loaded)
+}
+
+/// Resolve a texture to either a file path or the encoded image bytes embedded in the file
+fn load_texture(
+    texture: &texture::Texture,
+    buffers: &[Vec<u8>],
+    dir: &Path,
+) -> Result<(PathBuf, Option<Vec<u8>>), GltfLoadError> {
+    match texture.source().source() {
+        image::Source::View { view, .. } => {
+            let buffer = &buffers[view.buffer().index()];
+            match buffer.get(view.offset()..view.offset() + view.length()) {
+                Some(data) => Ok((PathBuf::default(), Some(data.to_vec()))),
+                None => Err(GltfLoadError::InvalidBufferLength),
+            }
+        }
+        image::Source::Uri { uri, .. } => {
+            if uri.starts_with("data:") {
+                Ok((PathBuf::default(), Some(load_uri(uri, dir)?)))
+            } else {
+                Ok((dir.join(percent_decode(uri)), None))
+            }
+        }
+    }
+}
+
+/// Load the data behind a URI, which is either a base64 data URI or a path relative to the file
+fn load_uri(uri: &str, dir: &Path) -> Result<Vec<u8>, GltfLoadError> {
+    if let Some(data_uri) = uri.strip_prefix("data:") {
+        return match data_uri.split_once(";base64,") {
+            Some((_, data)) => match base64::decode(data) {
+                Ok(data) => Ok(data),
+                Err(_) => Err(GltfLoadError::InvalidDataUri),
+            },
+            None => Err(GltfLoadError::InvalidDataUri),
+        };
+    }
+
+    match std::fs::read(dir.join(percent_decode(uri))) {
+        Ok(data) => Ok(data),
+        Err(err) => Err(GltfLoadError::FileLoadError(err)),
+    }
+}
+
+impl GltfScene {
+    /// Compute the world transform matrix of every node
+    ///
+    /// Nodes not reachable from the scene roots are left as the identity
+    pub fn world_matrices(&self) -> Vec<Matrix4<f32>> {
+        let mut world = vec![Matrix4::identity(); self.nodes.len()];
+        for (node, parent) in self.depth_first() {
+            let parent = parent.map_or_else(Matrix4::identity, |parent| world[parent]);
+            world[node] = parent * self.nodes[node].transform.build_transform_matrix();
+        }
+
+        world
+    }
+
+    /// Nodes reachable from the roots with their parents, depth first in file order
+    ///
+    /// Each node is visited once, so a child listing one of its ancestors can't loop forever
+    fn depth_first(&self) -> Vec<(usize, Option<usize>)> {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut order = vec![];
+        let mut stack: Vec<(usize, Option<usize>)> =
+            self.roots.iter().rev().map(|root| (*root, None)).collect();
+        while let Some((node, parent)) = stack.pop() {
+            if std::mem::replace(&mut visited[node], true) {
+                continue;
+            }
+
+            order.push((node, parent));
+            stack.extend(
+                self.nodes[node]
+                    .children
+                    .iter()
+                    .rev()
+                    .map(|child| (*child, Some(node))),
+            );
+        }
+
+        order
+    }
+
+    /// Collapse the scene into a single model, with one copy of a mesh per node that draws it
+    pub fn flatten(self) -> Model {
+        let world = self.world_matrices();
+
+        let mut meshes = vec![];
+        for (node, _) in self.depth_first() {
+            let matrix = world[node];
+            // Normals need the inverse transpose to stay perpendicular under non-uniform scale
+            let normal_matrix = Matrix3::from_cols(
+                matrix.x.truncate(),
+                matrix.y.truncate(),
+                matrix.z.truncate(),
+            )
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or_else(Matrix3::identity);
+
+            for mesh in self.nodes[node].meshes.iter() {
+                let mut mesh = self.model.meshes[*mesh].clone();
+                for vertex in mesh.vertices.iter_mut() {
+                    vertex.position = matrix.transform_point(Point3::from(vertex.position)).into();
+                    let normal = normal_matrix * Vector3::from(vertex.normal);
+                    if normal != Vector3::new(0.0, 0.0, 0.0) {
+                        vertex.normal = normal.normalize().into();
+                    }
+                }
+
+                meshes.push(mesh);
+            }
+        }
+
+        Model {
+            meshes,
+            materials: self.model.materials,
+        }
+    }
+}
+
+/// How textures are written when saving a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureExport {
+    /// Copy the encoded images into the binary buffer
+    Embed,
+
+    /// Refer to texture files relative to the saved model. Textures with no file are
+    /// embedded, & files with no relative path to the model are copied beside it
+    Reference,
+}
+
+/// Save a model as glTF
+///
+/// A `.glb` extension writes a single binary file, anything else writes the JSON document
+/// with the binary buffer in a `.bin` file of the same name alongside it
+pub fn save_model(
+    model: &Model,
+    file: &Path,
+    textures: TextureExport,
+) -> Result<(), GltfSaveError> {
+    let mut writer = GltfWriter::new(file.parent().unwrap_or_else(|| Path::new("")), textures);
+    let mut document = writer.write_model(model)?;
+
+    if file.extension().and_then(|ext| ext.to_str()) == Some("glb") {
+        if !writer.buffer.is_empty() {
+            document["buffers"] = json!([{ "byteLength": writer.buffer.len() }]);
+        }
+
+        write_file(
+            file,
+            &pack_glb(document.to_string().as_bytes(), &writer.buffer),
+        )
+    } else {
+        if !writer.buffer.is_empty() {
+            let bin_file = file.with_extension("bin");
+            document["buffers"] = json!([{
+                "byteLength": writer.buffer.len(),
+                "uri": percent_encode(&bin_file.file_name().unwrap_or_default().to_string_lossy()),
+            }]);
+            write_file(&bin_file, &writer.buffer)?;
+        }
+
+        // Serializing a Value can't fail
+        let raw_gltf = serde_json::to_string_pretty(&document).unwrap();
+        write_file(file, raw_gltf.as_bytes())
+    }
+}
+
+fn write_file(file: &Path, contents: &[u8]) -> Result<(), GltfSaveError> {
+    match std::fs::write(file, contents) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(GltfSaveError::FileSaveError(err)),
+    }
+}
+
+/// Pack a JSON document & binary chunk into a GLB container
+fn pack_glb(json: &[u8], bin: &[u8]) -> Vec<u8> {
+    // Chunks must be 4 byte aligned, JSON is padded with spaces & binary with zeros
+    let mut json = json.to_vec();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    let mut bin = bin.to_vec();
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    let mut glb = vec![];
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&0u32.to_le_bytes()); // Total length, filled in below
+
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+
+    if !bin.is_empty() {
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+    }
+
+    let length = glb.len() as u32;
+    glb[8..12].copy_from_slice(&length.to_le_bytes());
+
+    glb
+}
+
+const COMPONENT_FLOAT: u32 = 5126;
+const COMPONENT_UNSIGNED_INT: u32 = 5125;
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const TARGET_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const MODE_TRIANGLES: u32 = 4;
+
+/// Accumulates the binary buffer & the JSON arrays that point into it
+struct GltfWriter<'a> {
+    dir: &'a Path,
+    textures: TextureExport,
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    images: Vec<Value>,
+
+    /// Images already written, keyed by their source file
+    image_files: HashMap<PathBuf, usize>,
+}
+
+impl<'a> GltfWriter<'a> {
+    fn new(dir: &'a Path, textures: TextureExport) -> Self {
+        GltfWriter {
+            dir,
+            textures,
+            buffer: vec![],
+            buffer_views: vec![],
+            accessors: vec![],
+            images: vec![],
+            image_files: HashMap::new(),
+        }
+    }
+
+    /// Write every mesh & material, returning the document without its buffer
+    fn write_model(&mut self, model: &Model) -> Result<Value, GltfSaveError> {
+        let mut materials = vec![];
+        for material in model.materials.iter() {
+            materials.push(self.write_material(material)?);
+        }
+
+        let mut meshes = vec![];
+        let mut nodes = vec![];
+        for mesh in model.meshes.iter() {
+            // Accessors can't be empty, so neither can meshes
+            if mesh.vertices.is_empty() || mesh.indices.is_empty() {
+                continue;
+            }
+
+            nodes.push(json!({ "name": mesh.name, "mesh": meshes.len() }));
+            meshes.push(self.write_mesh(mesh));
+        }
+
+        // Every image gets its own texture, using the default sampler
+        let textures: Vec<Value> = (0..self.images.len())
+            .map(|image| json!({ "source": image }))
+            .collect();
+
+        let mut document = json!({
+            "asset": { "version": "2.0", "generator": "graphics" },
+            "scene": 0,
+            "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<usize>>() }],
+            "nodes": nodes,
+            "meshes": meshes,
+            "materials": materials,
+            "textures": textures,
+            "images": self.images,
+            "bufferViews": self.buffer_views,
+            "accessors": self.accessors,
+        });
+
+        // glTF doesn't allow empty arrays, leave them out instead
+        if let Some(document) = document.as_object_mut() {
+            document.retain(|_, value| value.as_array().is_none_or(|array| !array.is_empty()));
+        }
+
+        Ok(document)
+    }
+
+    fn write_mesh(&mut self, mesh: &Mesh) -> Value {
+        let positions: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.position).collect();
+        let normals: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.normal).collect();
+        let texture_coords: Vec<[f32; 2]> =
+            mesh.vertices.iter().map(|v| v.texture_coords).collect();
+        let tangents: Vec<[f32; 4]> = mesh.vertices.iter().map(|v| v.tangent).collect();
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for position in positions.iter() {
+            for i in 0..3 {
+                min[i] = min[i].min(position[i]);
+                max[i] = max[i].max(position[i]);
+            }
+        }
+
+        let position = self.write_accessor(
+            bytemuck::cast_slice(&positions),
+            positions.len(),
+            "VEC3",
+            COMPONENT_FLOAT,
+            TARGET_ARRAY_BUFFER,
+        );
+        // Position bounds are required by the spec
+        self.accessors[position]["min"] = json!(min);
+        self.accessors[position]["max"] = json!(max);
+
+        let normal = self.write_accessor(
+            bytemuck::cast_slice(&normals),
+            normals.len(),
+            "VEC3",
+            COMPONENT_FLOAT,
+            TARGET_ARRAY_BUFFER,
+        );
+        let tangent = self.write_accessor(
+            bytemuck::cast_slice(&tangents),
+            tangents.len(),
+            "VEC4",
+            COMPONENT_FLOAT,
+            TARGET_ARRAY_BUFFER,
+        );
+        let texture_coord = self.write_accessor(
+            bytemuck::cast_slice(&texture_coords),
+            texture_coords.len(),
+            "VEC2",
+            COMPONENT_FLOAT,
+            TARGET_ARRAY_BUFFER,
+        );
+        let indices = self.write_accessor(
+            bytemuck::cast_slice(&mesh.indices),
+            mesh.indices.len(),
+            "SCALAR",
+            COMPONENT_UNSIGNED_INT,
+            TARGET_ELEMENT_ARRAY_BUFFER,
+        );
+
+        let mut primitive = json!({
+            "attributes": {
+                "POSITION": position,
+                "NORMAL": normal,
+                "TANGENT": tangent,
+                "TEXCOORD_0": texture_coord,
+            },
+            "indices": indices,
+            "mode": MODE_TRIANGLES,
+        });
+        if let Some(material) = mesh.material {
+            primitive["material"] = json!(material);
+        }
+
+        json!({ "name": mesh.name, "primitives": [primitive] })
+    }
+
+    fn write_material(&mut self, material: &Material) -> Result<Value, GltfSaveError> {
+        let [r, g, b] = material.diffuse_color;
+        let mut pbr = json!({
+            "baseColorFactor": [r, g, b, material.opacity],
+            "metallicFactor": material.metallic,
+            "roughnessFactor": material.roughness,
+        });
+        if let Some(texture) = self.write_texture(
+            &material.diffuse_texture_file,
+            &material.diffuse_texture_data,
+        )? {
+            pbr["baseColorTexture"] = json!({ "index": texture });
+        }
+        if let Some(texture) = self.write_texture(
+            &material.metallic_roughness_texture_file,
+            &material.metallic_roughness_texture_data,
+        )? {
+            pbr["metallicRoughnessTexture"] = json!({ "index": texture });
+        }
+
+        let mut written = json!({
+            "pbrMetallicRoughness": pbr,
+            "emissiveFactor": material.emissive_color,
+        });
+        if let Some(texture) =
+            self.write_texture(&material.bump_map_file, &material.bump_map_data)?
+        {
+            written["normalTexture"] = json!({ "index": texture });
+            if material.bump_multiplier != 1.0 {
+                written["normalTexture"]["scale"] = json!(material.bump_multiplier);
+            }
+        }
+        if material.opacity < 1.0 {
+            written["alphaMode"] = json!("BLEND");
+        }
+
+        Ok(written)
+    }
+
+    /// Write a texture, returning its index or `None` if the material doesn't have one
+    fn write_texture(
+        &mut self,
+        file: &Path,
+        data: &Option<Vec<u8>>,
+    ) -> Result<Option<usize>, GltfSaveError> {
+        if let Some(data) = data {
+            return self.embed_image(data).map(Some);
+        }
+        if file.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        if let Some(image) = self.image_files.get(file) {
+            return Ok(Some(*image));
+        }
+
+        let image = match self.textures {
+            TextureExport::Reference => {
+                // Textures with no path from the model are copied next to it
+                let uri = match relative_uri(self.dir, file) {
+                    Some(uri) => uri,
+                    None => percent_encode(&copy_texture(self.dir, file)?),
+                };
+                self.images.push(json!({ "uri": uri }));
+                self.images.len() - 1
+            }
+            TextureExport::Embed => match std::fs::read(file) {
+                Ok(data) => self.embed_image(&data)?,
+                Err(err) => return Err(GltfSaveError::TextureLoadError(err)),
+            },
+        };
+        self.image_files.insert(file.to_path_buf(), image);
+
+        Ok(Some(image))
+    }
+
+    /// Copy an encoded image into the buffer, converting it to PNG if glTF doesn't support it
+    fn embed_image(&mut self, data: &[u8]) -> Result<usize, GltfSaveError> {
+        let (data, mime_type) = match ::image::guess_format(data) {
+            Ok(::image::ImageFormat::Png) => (data.to_vec(), "image/png"),
+            Ok(::image::ImageFormat::Jpeg) => (data.to_vec(), "image/jpeg"),
+            _ => {
+                let image = match ::image::load_from_memory(data) {
+                    Ok(image) => image,
+                    Err(_) => return Err(GltfSaveError::InvalidTexture),
+                };
+                let mut png = std::io::Cursor::new(vec![]);
+                match image.write_to(&mut png, ::image::ImageOutputFormat::Png) {
+                    Ok(_) => (png.into_inner(), "image/png"),
+                    Err(_) => return Err(GltfSaveError::InvalidTexture),
+                }
+            }
+        };
+
+        let view = self.write_view(&data, None);
+        self.images
+            .push(json!({ "bufferView": view, "mimeType": mime_type }));
+
+        Ok(self.images.len() - 1)
+    }
+
+    fn write_accessor(
+        &mut self,
+        data: &[u8],
+        count: usize,
+        accessor_type: &str,
+        component_type: u32,
+        target: u32,
+    ) -> usize {
+        let view = self.write_view(data, Some(target));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": component_type,
+            "count": count,
+            "type": accessor_type,
+        }));
+
+        self.accessors.len() - 1
+    }
+
+    fn write_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        // Keep every view 4 byte aligned so any component type can be read from it
+        while !self.buffer.len().is_multiple_of(4) {
+            self.buffer.push(0);
+        }
+
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.buffer.extend_from_slice(data);
+        self.buffer_views.push(view);
+
+        self.buffer_views.len() - 1
+    }
+}
+
+/// Percent-encoded URI of a file relative to a directory, or `None` if there's no
+/// relative path from one to the other
+fn relative_uri(dir: &Path, file: &Path) -> Option<String> {
+    if dir.is_absolute() != file.is_absolute() {
+        return None;
+    }
+    let dir: Vec<Component> = dir.components().collect();
+    let file_components: Vec<Component> = file.components().collect();
+
+    let common = dir
+        .iter()
+        .zip(file_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let unshared = &dir[common..];
+    if (file.is_absolute() && common == 0)
+        || unshared
+            .iter()
+            .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let mut parts = vec!["..".to_string(); unshared.len()];
+    for component in file_components[common..].iter() {
+        parts.push(percent_encode(&component.as_os_str().to_string_lossy()));
+    }
+
+    Some(parts.join("/"))
+}
+
+/// Escape everything but unreserved URI characters, as `%` & two hex digits per byte
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    encoded
+}
+
+/// Undo percent-encoding, leaving any malformed escapes as they are
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Copy a texture into the directory a model is saved in, returning the copy's name.
+/// A different file already there with the same name is left alone & the copy renamed
+fn copy_texture(dir: &Path, file: &Path) -> Result<String, GltfSaveError> {
+    let data = std::fs::read(file).map_err(GltfSaveError::TextureLoadError)?;
+    let stem = file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "texture".to_string());
+    let extension = file
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut name = format!("{}{}", stem, extension);
+    let mut copy = 0;
+    loop {
+        let target = dir.join(&name);
+        match std::fs::read(&target) {
+            Ok(existing) if existing == data => return Ok(name),
+            Ok(_) => {
+                copy += 1;
+                name = format!("{}-{}{}", stem, copy, extension);
+            }
+            Err(_) => {
+                write_file(&target, &data)?;
+                return Ok(name);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GltfLoadError {
+    FileLoadError(std::io::Error),
+    InvalidGltf(gltf::Error),
+    InvalidDataUri,
+    InvalidBufferLength,
+    MissingBinaryChunk,
+    MissingPositions,
+    InvalidAttributeCount,
+    InvalidIndex,
+}
+
+#[derive(Debug)]
+pub enum GltfSaveError {
+    FileSaveError(std::io::Error),
+    TextureLoadError(std::io::Error),
+    InvalidTexture,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use cgmath::{assert_abs_diff_eq, InnerSpace, Matrix4, Rad, SquareMatrix, Vector3};
+
+    use super::{
+        load_model, load_scene, parse_scene, percent_decode, relative_uri, save_model,
+        GltfLoadError, TextureExport,
+    };
+    use crate::{model::Model, obj, test_util::output_dir, transform::EulerOrder};
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("data/gltf")
+            .join(name)
+    }
+
+    fn assert_same_geometry(original: &Model, loaded: &Model) {
+        assert_eq!(original.meshes.len(), loaded.meshes.len());
+        for (original, loaded) in original.meshes.iter().zip(loaded.meshes.iter()) {
+            assert_eq!(original.name, loaded.name);
+            assert_eq!(original.indices, loaded.indices);
+            assert_eq!(original.material, loaded.material);
+            assert_eq!(original.vertices.len(), loaded.vertices.len());
+            for (original, loaded) in original.vertices.iter().zip(loaded.vertices.iter()) {
+                assert_eq!(original.position, loaded.position);
+                assert_eq!(original.texture_coords, loaded.texture_coords);
+                assert_eq!(original.tangent, loaded.tangent);
+                assert_abs_diff_eq!(
+                    Vector3::from(original.normal).normalize(),
+                    Vector3::from(loaded.normal),
+                    epsilon = 1e-5
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn load_data_uri_triangle() {
+        let scene = load_scene(&fixture("triangle.gltf")).unwrap();
+
+        assert_eq!(scene.model.meshes.len(), 1);
+        let mesh = &scene.model.meshes[0];
+        assert_eq!(mesh.name, "triangle");
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        assert_eq!(mesh.vertices[1].position, [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.vertices[1].texture_coords, [0.0, 0.0]);
+        assert_eq!(mesh.vertices[1].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(mesh.material, None);
+
+        assert_eq!(scene.roots, vec![0]);
+        assert_eq!(scene.nodes[0].transform.translate, [0.0, 0.0, -5.0]);
+    }
+
+    #[test]
+    pub fn load_external_buffer_and_materials() {
+        let scene = load_scene(&fixture("hierarchy.gltf")).unwrap();
+
+        let mesh = &scene.model.meshes[0];
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.vertices[2].position, [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.vertices[2].texture_coords, [1.0, 0.0]);
+        assert_eq!(mesh.vertices[2].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(mesh.material, Some(0));
+
+        let material = &scene.model.materials[0];
+        assert_eq!(material.diffuse_color, [1.0, 0.5, 0.25]);
+        assert_eq!(material.opacity, 0.5);
+        assert_eq!(material.emissive_color, [0.1, 0.2, 0.3]);
+        assert_eq!(material.metallic, 0.25);
+        assert_eq!(material.roughness, 0.75);
+        assert_eq!(material.diffuse_texture_file, fixture("../green.jpg"));
+        assert_eq!(
+            material.metallic_roughness_texture_file,
+            fixture("../line.jpg")
+        );
+        assert_eq!(material.bump_map_file, fixture("../cube-normal.png"));
+        assert!(material.diffuse_texture_data.is_none());
+    }
+
+    #[test]
+    pub fn load_node_hierarchy() {
+        let scene = load_scene(&fixture("hierarchy.gltf")).unwrap();
+
+        assert_eq!(scene.roots, vec![0]);
+        assert_eq!(scene.nodes[0].name, "root");
+        assert_eq!(scene.nodes[0].children, vec![1]);
+        assert_eq!(scene.nodes[0].meshes, vec![0]);
+        assert_eq!(scene.nodes[1].meshes, vec![0]);
+        assert_abs_diff_eq!(
+            Vector3::from(scene.nodes[1].transform.scale),
+            Vector3::new(2.0, 2.0, 2.0),
+            epsilon = 1e-5
+        );
+        assert_abs_diff_eq!(
+            scene.nodes[1].transform.euler(EulerOrder::XYZ)[1],
+            Rad(std::f32::consts::FRAC_PI_2),
+            epsilon = 1e-5
+        );
+
+        let world = scene.world_matrices();
+        assert_abs_diff_eq!(
+            world[0],
+            Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+        );
+        assert_abs_diff_eq!(
+            world[1],
+            Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+                * Matrix4::from_angle_y(Rad(std::f32::consts::FRAC_PI_2))
+                * Matrix4::from_scale(2.0),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    pub fn flatten_bakes_node_transforms() {
+        let model = load_model(&fixture("hierarchy.gltf")).unwrap();
+
+        assert_eq!(model.meshes.len(), 2);
+        assert_eq!(model.meshes[0].vertices[2].position, [2.0, 3.0, 3.0]);
+
+        // Child is rotated a quarter turn about y & doubled in size
+        let child = &model.meshes[1];
+        assert_abs_diff_eq!(
+            Vector3::from(child.vertices[2].position),
+            Vector3::new(1.0, 4.0, 1.0),
+            epsilon = 1e-5
+        );
+        assert_abs_diff_eq!(
+            Vector3::from(child.vertices[2].normal),
+            Vector3::new(1.0, 0.0, 0.0),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    pub fn cyclic_nodes_are_visited_once() {
+        let mut scene = load_scene(&fixture("hierarchy.gltf")).unwrap();
+
+        // The child lists its parent, & itself, as children
+        scene.nodes[1].children = vec![0, 1];
+        let world = scene.world_matrices();
+        assert_abs_diff_eq!(
+            world[0],
+            Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+        );
+        assert_eq!(scene.flatten().meshes.len(), 2);
+    }
+
+    #[test]
+    pub fn load_glb_with_embedded_texture() {
+        let image = std::fs::read(fixture("../green.jpg")).unwrap();
+
+        let mut bin = vec![];
+        for position in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for component in position {
+                bin.extend_from_slice(&component.to_le_bytes());
+            }
+        }
+        bin.extend_from_slice(&image);
+
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "nodes": [{{ "mesh": 0 }}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "material": 0, "mode": 6 }}] }}],
+                "materials": [{{ "pbrMetallicRoughness": {{ "baseColorTexture": {{ "index": 0 }} }} }}],
+                "textures": [{{ "source": 0 }}],
+                "images": [{{ "bufferView": 1, "mimeType": "image/jpeg" }}],
+                "buffers": [{{ "byteLength": {} }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": {} }}
+                ],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0]
+                }}]
+            }}"#,
+            bin.len(),
+            image.len()
+        );
+
+        let scene = parse_scene(&super::pack_glb(json.as_bytes(), &bin), Path::new("")).unwrap();
+
+        // No scene is given, so every parentless node is a root
+        assert_eq!(scene.roots, vec![0]);
+        assert_eq!(
+            scene.nodes[0].transform.build_transform_matrix(),
+            Matrix4::identity()
+        );
+
+        // Triangle fans are converted to lists
+        assert_eq!(scene.model.meshes[0].indices, vec![0, 1, 2]);
+        assert_eq!(
+            scene.model.materials[0].diffuse_texture_data.as_deref(),
+            Some(image.as_slice())
+        );
+        assert_eq!(scene.model.materials[0].roughness, 1.0);
+    }
+
+    #[test]
+    pub fn strip_and_fan_conversion() {
+        assert_eq!(
+            super::strip_to_list(&[0, 1, 2, 3, 4]),
+            vec![0, 1, 2, 2, 1, 3, 2, 3, 4]
+        );
+        assert_eq!(super::fan_to_list(&[0, 1, 2, 3]), vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    pub fn missing_file_is_an_error() {
+        assert!(matches!(
+            load_scene(&fixture("missing.gltf")),
+            Err(GltfLoadError::FileLoadError(_))
+        ));
+    }
+
+    #[test]
+    pub fn invalid_json_is_an_error() {
+        assert!(matches!(
+            parse_scene(b"{ not gltf", Path::new("")),
+            Err(GltfLoadError::InvalidGltf(_))
+        ));
+    }
+
+    #[test]
+    pub fn round_trip_glb_with_embedded_textures() {
+        let mut model = obj::load_model(&fixture("../cube.obj")).unwrap();
+        model.materials[0].bump_multiplier = 0.5;
+        let file = output_dir("gltf-embedded").join("cube.glb");
+
+        save_model(&model, &file, TextureExport::Embed).unwrap();
+        let loaded = load_model(&file).unwrap();
+
+        assert_same_geometry(&model, &loaded);
+        let material = &loaded.materials[0];
+        assert_eq!(material.diffuse_color, model.materials[0].diffuse_color);
+        assert_eq!(material.opacity, model.materials[0].opacity);
+        assert_eq!(material.bump_multiplier, 0.5);
+        assert_eq!(
+            material.diffuse_texture_data.as_deref(),
+            Some(
+                std::fs::read(fixture("../cube-diffuse.jpg"))
+                    .unwrap()
+                    .as_slice()
+            )
+        );
+        assert_eq!(
+            material.bump_map_data.as_deref(),
+            Some(
+                std::fs::read(fixture("../cube-normal.png"))
+                    .unwrap()
+                    .as_slice()
+            )
+        );
+    }
+
+    #[test]
+    pub fn round_trip_gltf_with_referenced_textures() {
+        let model = obj::load_model(&fixture("../sphere.obj")).unwrap();
+        let file = output_dir("gltf-referenced").join("sphere.gltf");
+
+        save_model(&model, &file, TextureExport::Reference).unwrap();
+        assert!(file.with_extension("bin").exists());
+        let loaded = load_model(&file).unwrap();
+
+        assert_same_geometry(&model, &loaded);
+        let material = &loaded.materials[0];
+        assert!(material.diffuse_texture_data.is_none());
+        assert_eq!(
+            material.diffuse_texture_file.canonicalize().unwrap(),
+            fixture("../cube-diffuse.jpg").canonicalize().unwrap()
+        );
+    }
+
+    #[test]
+    pub fn round_trip_loaded_gltf() {
+        let model = load_model(&fixture("hierarchy.gltf")).unwrap();
+        let file = output_dir("gltf-hierarchy").join("hierarchy.glb");
+
+        save_model(&model, &file, TextureExport::Embed).unwrap();
+        let loaded = load_model(&file).unwrap();
+
+        assert_same_geometry(&model, &loaded);
+        let (original, material) = (&model.materials[0], &loaded.materials[0]);
+        assert_eq!(material.diffuse_color, original.diffuse_color);
+        assert_eq!(material.opacity, original.opacity);
+        assert_eq!(material.emissive_color, original.emissive_color);
+        assert_eq!(material.metallic, original.metallic);
+        assert_eq!(material.roughness, original.roughness);
+        assert!(material.metallic_roughness_texture_data.is_some());
+    }
+
+    #[test]
+    pub fn accessors_have_position_bounds() {
+        let model = load_model(&fixture("hierarchy.gltf")).unwrap();
+        let file = output_dir("gltf-bounds").join("hierarchy.gltf");
+
+        save_model(&model, &file, TextureExport::Reference).unwrap();
+        let gltf = gltf::Gltf::open(&file).unwrap();
+
+        let bounds: Vec<_> = gltf
+            .meshes()
+            .map(|mesh| mesh.primitives().next().unwrap().bounding_box())
+            .collect();
+        assert_eq!(bounds[0].min, [1.0, 2.0, 3.0]);
+        assert_eq!(bounds[0].max, [2.0, 3.0, 3.0]);
+        assert_abs_diff_eq!(
+            Vector3::from(bounds[1].min),
+            Vector3::new(1.0, 2.0, 1.0),
+            epsilon = 1e-5
+        );
+        assert_abs_diff_eq!(
+            Vector3::from(bounds[1].max),
+            Vector3::new(1.0, 4.0, 3.0),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    pub fn relative_texture_uris() {
+        assert_eq!(
+            relative_uri(Path::new("/a/b/out"), Path::new("/a/b/data/x.png")).unwrap(),
+            "../data/x.png"
+        );
+        assert_eq!(
+            relative_uri(Path::new("/a/b"), Path::new("/a/b/x.png")).unwrap(),
+            "x.png"
+        );
+        assert_eq!(
+            relative_uri(Path::new(""), Path::new("data/x.png")).unwrap(),
+            "data/x.png"
+        );
+
+        // Reserved characters are escaped, & paths that can't be made relative aren't
+        assert_eq!(
+            relative_uri(Path::new("/a"), Path::new("/a/my tex#1%.png")).unwrap(),
+            "my%20tex%231%25.png"
+        );
+        assert_eq!(percent_decode("my%20tex%231%25.png"), "my tex#1%.png");
+        assert!(relative_uri(Path::new("out"), Path::new("/a/x.png")).is_none());
+        assert!(relative_uri(Path::new("../out"), Path::new("x.png")).is_none());
+    }
+
+    #[test]
+    pub fn unreachable_textures_are_copied() {
+        let dir = output_dir("gltf-copied");
+        let texture = dir.join("source dir").join("diffuse map.jpg");
+        std::fs::create_dir_all(texture.parent().unwrap()).unwrap();
+        std::fs::copy(fixture("../cube-diffuse.jpg"), &texture).unwrap();
+        let mut model = obj::load_model(&fixture("../cube.obj")).unwrap();
+        model.materials[0].diffuse_texture_file = texture.clone();
+
+        // Saved through a `..`, the output has no path to the texture
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+        std::fs::create_dir_all(dir.join("saved")).unwrap();
+        let file = dir.join("out").join("..").join("saved").join("cube.gltf");
+        save_model(&model, &file, TextureExport::Reference).unwrap();
+
+        let gltf = gltf::Gltf::open(&file).unwrap();
+        let uris: Vec<_> = gltf
+            .images()
+            .filter_map(|image| match image.source() {
+                gltf::image::Source::Uri { uri, .. } => Some(uri.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert!(uris.contains(&"diffuse%20map.jpg".to_string()));
+        let loaded = load_model(&file).unwrap();
+        assert_eq!(
+            std::fs::read(&loaded.materials[0].diffuse_texture_file).unwrap(),
+            std::fs::read(&texture).unwrap()
+        );
+    }
+}
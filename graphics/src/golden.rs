@@ -0,0 +1,244 @@
+//! Golden image regression tests
+//!
+//! Bundled assets are drawn with `SoftwareRenderer` & compared against the reference
+//! images in `data/golden`. Pixels are compared by perceived colour difference, and a
+//! small fraction may differ so edge pixels flipping from rounding don't fail the test.
+//! On failure the expected, actual & diff images are written to a temporary directory
+//!
+//! Run with `UPDATE_GOLDEN=1` to write new references instead of comparing
+
+use std::path::{Path, PathBuf};
+
+use cgmath::*;
+use image::{Rgba, RgbaImage};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    bounds::BoundingSphere,
+    camera::{Camera, CameraController, MoveMode},
+    light::{Light, Lighting, ShadingModel},
+    rasterizer::SoftwareRenderer,
+    render::Renderer,
+    swp,
+    test_util::{load_model, output_dir},
+};
+
+const SIZE: u32 = 128;
+
+static MOVE_MODE: MoveMode = MoveMode::new();
+
+/// Largest perceived difference between two pixels counted as equal, from 0 to 1
+const THRESHOLD: f32 = 0.1;
+
+/// Fraction of pixels allowed to differ
+const ALLOWED_DIFFERENCE: f32 = 0.005;
+
+fn renderer<'a>(eye: Point3<f32>) -> SoftwareRenderer<'a> {
+    let camera = Camera::new(
+        eye,
+        Point3::origin(),
+        Vector3::unit_y(),
+        1.0,
+        45.0,
+        0.1,
+        100.0,
+    );
+    let mut renderer = SoftwareRenderer::new(
+        PhysicalSize::new(SIZE, SIZE),
+        camera,
+        CameraController::new(0.2, &MOVE_MODE),
+    );
+
+    // A key light from the upper left & a dimmer fill light from the right
+    *renderer.lighting_mut() = Lighting {
+        lights: vec![
+            Light::Directional {
+                direction: Vector3::new(1.0, -2.0, -1.5),
+                colour: [1.0; 3],
+                intensity: 0.9,
+            },
+            Light::Directional {
+                direction: Vector3::new(-1.0, 0.0, -0.5),
+                colour: [0.8, 0.9, 1.0],
+                intensity: 0.3,
+            },
+        ],
+        ambient: [0.1; 3],
+        shading_model: ShadingModel::BlinnPhong,
+    };
+
+    renderer
+}
+
+fn render_obj(file: &str, eye: Point3<f32>) -> RgbaImage {
+    let mut renderer = renderer(eye);
+    let node = renderer.add_model(load_model(file));
+    renderer.frame_model(node);
+    renderer.render().unwrap();
+    renderer.image().clone()
+}
+
+/// Render a scene's surfaces, or its curves if it has none
+fn render_swp(file: &str, eye: Point3<f32>) -> RgbaImage {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("swp").join(file);
+    let scene = swp::load_scene(&file).unwrap();
+
+    // Swept surfaces are often open, so both sides should show
+    let mut renderer = renderer(eye);
+    renderer.set_back_face_culling(false);
+    if scene.surfaces.is_empty() {
+        let points = scene
+            .curves
+            .iter()
+            .flat_map(|curve| curve.points.iter().map(|point| point.position));
+        if let Some(sphere) = BoundingSphere::from_points(points.collect::<Vec<_>>()) {
+            renderer.camera_mut().frame(&sphere);
+        }
+        for curve in scene.curves {
+            renderer.add_curve(curve);
+        }
+    } else {
+        let node = renderer.add_model(scene.to_model());
+        renderer.frame_model(node);
+    }
+
+    renderer.render().unwrap();
+    renderer.image().clone()
+}
+
+/// Perceived difference between two colours from 0 to 1, using the YIQ colour space
+/// which weighs brightness above hue
+fn difference(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let yiq = |pixel: &Rgba<u8>| {
+        let [r, g, b, _] = pixel.0.map(|channel| channel as f32 / 255.0);
+        [
+            0.299 * r + 0.587 * g + 0.114 * b,
+            0.596 * r - 0.274 * g - 0.322 * b,
+            0.211 * r - 0.523 * g + 0.312 * b,
+        ]
+    };
+    let ([y1, i1, q1], [y2, i2, q2]) = (yiq(a), yiq(b));
+    let delta = 0.5053 * (y1 - y2).powi(2) + 0.299 * (i1 - i2).powi(2) + 0.1957 * (q1 - q2).powi(2);
+
+    // Black against white is the largest possible difference
+    (delta / 0.5053).sqrt()
+}
+
+/// Image highlighting differing pixels in red over a faded copy of the expected image
+fn diff_image(expected: &RgbaImage, actual: &RgbaImage) -> (RgbaImage, usize) {
+    let mut differing = 0;
+    let diff = RgbaImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let (a, b) = (expected.get_pixel(x, y), actual.get_pixel(x, y));
+        if difference(a, b) > THRESHOLD {
+            differing += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let grey = (a.0[0] as u32 + a.0[1] as u32 + a.0[2] as u32) / 12 + 191;
+            Rgba([grey as u8, grey as u8, grey as u8, 255])
+        }
+    });
+
+    (diff, differing)
+}
+
+/// Compare an image against its reference, describing the mismatch if there is one
+fn check(name: &str, actual: RgbaImage) -> Result<(), String> {
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("data")
+        .join("golden")
+        .join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(reference.parent().unwrap()).unwrap();
+        actual.save(&reference).unwrap();
+        return Ok(());
+    }
+
+    let expected = image::open(&reference)
+        .unwrap_or_else(|error| panic!("missing reference {:?}: {}", reference, error))
+        .to_rgba8();
+    let failure = if expected.dimensions() != actual.dimensions() {
+        Some(format!(
+            "size {:?} doesn't match the reference {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        ))
+    } else {
+        let (diff, differing) = diff_image(&expected, &actual);
+        let allowed = (ALLOWED_DIFFERENCE * (SIZE * SIZE) as f32) as usize;
+        (differing > allowed).then(|| {
+            diff.save(output(name, "diff")).unwrap();
+            format!("{} pixels differ, {} allowed", differing, allowed)
+        })
+    };
+
+    match failure {
+        Some(failure) => {
+            expected.save(output(name, "expected")).unwrap();
+            actual.save(output(name, "actual")).unwrap();
+            Err(format!(
+                "{} doesn't match its reference: {}, see {:?}",
+                name,
+                failure,
+                output(name, "*")
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Fail with every mismatch at once, so one run shows all the images that changed
+fn assert_matches(results: impl IntoIterator<Item = Result<(), String>>) {
+    let failures: Vec<String> = results.into_iter().filter_map(Result::err).collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn output(name: &str, kind: &str) -> PathBuf {
+    output_dir("golden").join(format!("{}-{}.png", name, kind))
+}
+
+#[test]
+pub fn golden_meshes() {
+    assert_matches([
+        check("cube", render_obj("cube.obj", Point3::new(3.0, 2.0, 4.0))),
+        check(
+            "sphere",
+            render_obj("sphere.obj", Point3::new(0.0, 1.0, 4.0)),
+        ),
+        check("torus", render_obj("torus.obj", Point3::new(0.0, 3.0, 4.0))),
+    ]);
+}
+
+#[test]
+pub fn golden_swp_scenes() {
+    let scenes = [
+        "circles",
+        "core",
+        "flircle",
+        "florus",
+        "gentorus",
+        "norm",
+        "tor",
+        "weird",
+        "weirder",
+        "wineglass",
+    ];
+    let eye = Point3::new(1.0, 2.0, 5.0);
+    assert_matches(
+        scenes
+            .into_iter()
+            .map(|scene| check(scene, render_swp(&format!("{}.swp", scene), eye))),
+    );
+}
+
+#[test]
+pub fn differences_are_detected() {
+    let black = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+    let mut changed = black.clone();
+    changed.put_pixel(1, 2, Rgba([255, 255, 255, 255]));
+    changed.put_pixel(2, 2, Rgba([5, 5, 5, 255]));
+
+    let (diff, differing) = diff_image(&black, &changed);
+    assert_eq!(differing, 1);
+    assert_eq!(diff.get_pixel(1, 2), &Rgba([255, 0, 0, 255]));
+    assert_abs_diff_eq!(difference(black.get_pixel(0, 0), &Rgba([255; 4])), 1.0);
+}
@@ -0,0 +1,1074 @@
+//! A half-edge mesh for topology queries & editing
+//!
+//! Every edge is stored as two opposite half-edges, and open boundaries have half-edges
+//! without a face so boundary loops can be walked like faces. Vertices at the same
+//! position are welded, with per corner attributes kept on the half-edges
+
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{InnerSpace, Vector3, VectorSpace};
+
+use crate::model::{Mesh, ModelVertex};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vertex {
+    pub position: [f32; 3],
+
+    /// An outgoing half-edge, the boundary one if the vertex is on a boundary
+    pub half_edge: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HalfEdge {
+    /// Vertex the half-edge starts from
+    pub origin: usize,
+
+    /// The half-edge going the other way along the same edge
+    pub twin: usize,
+
+    /// The next half-edge around the face or boundary loop
+    pub next: usize,
+
+    /// `None` if the half-edge is on the outside of a boundary
+    pub face: Option<usize>,
+
+    /// Attributes of the face corner at the origin, only meaningful with a face
+    pub corner: ModelVertex,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Face {
+    /// Any half-edge around the face
+    pub half_edge: usize,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct HalfEdgeMesh {
+    /// Name of the mesh
+    pub name: String,
+
+    /// Index of material in model material vector
+    pub material: Option<usize>,
+
+    vertices: Vec<Vertex>,
+    half_edges: Vec<HalfEdge>,
+    faces: Vec<Face>,
+}
+
+impl HalfEdgeMesh {
+    /// Build from the faces of a mesh, which must be manifold & consistently wound
+    ///
+    /// Polygons recorded in the mesh's face sizes are rebuilt from their triangles
+    pub fn from_mesh(mesh: &Mesh) -> Result<Self, HalfEdgeError> {
+        let polygons: Vec<Vec<u32>> = if mesh.face_sizes.is_empty() {
+            mesh.indices
+                .chunks_exact(3)
+                .map(|triangle| triangle.to_vec())
+                .collect()
+        } else {
+            let mut triangles = mesh.indices.chunks_exact(3);
+            let mut polygons = Vec::with_capacity(mesh.face_sizes.len());
+            for size in mesh.face_sizes.iter() {
+                // A fan of triangles 0, 1, 2 then 0, 2, 3 etc.
+                let mut polygon = vec![];
+                for _ in 0..size.saturating_sub(2) {
+                    match triangles.next() {
+                        Some(triangle) if polygon.is_empty() => polygon.extend(triangle),
+                        Some(triangle) => polygon.push(triangle[2]),
+                        None => return Err(HalfEdgeError::InvalidIndex),
+                    }
+                }
+                polygons.push(polygon);
+            }
+            polygons
+        };
+        let polygons: Vec<&[u32]> = polygons.iter().map(|polygon| &polygon[..]).collect();
+
+        let mut half_edge_mesh = Self::from_polygons(&mesh.vertices, &polygons)?;
+        half_edge_mesh.name = mesh.name.clone();
+        half_edge_mesh.material = mesh.material;
+
+        Ok(half_edge_mesh)
+    }
+
+    /// Build from polygons indexing into `vertices`, which must be manifold & consistently
+    /// wound
+    pub fn from_polygons(
+        vertices: &[ModelVertex],
+        polygons: &[&[u32]],
+    ) -> Result<Self, HalfEdgeError> {
+        // Weld the vertices used by the polygons, keeping them in their original order
+        let mut used = vec![false; vertices.len()];
+        for index in polygons.iter().flat_map(|polygon| polygon.iter()) {
+            match used.get_mut(*index as usize) {
+                Some(used) => *used = true,
+                None => return Err(HalfEdgeError::InvalidIndex),
+            }
+        }
+        let mut welded: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut positions = vec![];
+        let mut ids = vec![usize::MAX; vertices.len()];
+        for (index, vertex) in vertices.iter().enumerate() {
+            if used[index] {
+                ids[index] = *welded
+                    .entry(vertex.position.map(f32::to_bits))
+                    .or_insert_with(|| {
+                        positions.push(vertex.position);
+                        positions.len() - 1
+                    });
+            }
+        }
+
+        let faces = polygons
+            .iter()
+            .map(|polygon| {
+                polygon
+                    .iter()
+                    .map(|index| (ids[*index as usize], vertices[*index as usize]))
+                    .collect()
+            })
+            .collect();
+
+        Self::from_corners(positions, faces)
+    }
+
+    /// Build from vertex positions & faces made of (vertex, corner attributes) pairs, every
+    /// vertex must be used by a face
+    pub(crate) fn from_corners(
+        positions: Vec<[f32; 3]>,
+        faces: Vec<Vec<(usize, ModelVertex)>>,
+    ) -> Result<Self, HalfEdgeError> {
+        let mut mesh = HalfEdgeMesh {
+            vertices: positions
+                .into_iter()
+                .map(|position| Vertex {
+                    position,
+                    half_edge: usize::MAX,
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let mut directed_edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for corners in faces {
+            let ids: Vec<usize> = corners.iter().map(|(id, _)| *id).collect();
+            if ids.len() < 3 || ids.iter().collect::<HashSet<_>>().len() != ids.len() {
+                return Err(HalfEdgeError::DegenerateFace);
+            }
+
+            let face = mesh.faces.len();
+            let first = mesh.half_edges.len();
+            for (i, (_, corner)) in corners.into_iter().enumerate() {
+                let (a, b) = (ids[i], ids[(i + 1) % ids.len()]);
+
+                // A second half-edge in the same direction means more than two faces meet
+                // at the edge, or its faces are wound inconsistently
+                if directed_edges.insert((a, b), first + i).is_some() {
+                    return Err(HalfEdgeError::NonManifoldEdge);
+                }
+                mesh.half_edges.push(HalfEdge {
+                    origin: a,
+                    twin: usize::MAX,
+                    next: first + (i + 1) % ids.len(),
+                    face: Some(face),
+                    corner,
+                });
+                mesh.vertices[a].half_edge = first + i;
+            }
+            mesh.faces.push(Face { half_edge: first });
+        }
+
+        // Pair up half-edges, adding boundary half-edges where there's no face on the other side
+        let face_half_edges = mesh.half_edges.len();
+        let mut boundary_outgoing: HashMap<usize, usize> = HashMap::new();
+        for half_edge in 0..face_half_edges {
+            let (a, b) = (
+                mesh.half_edges[half_edge].origin,
+                mesh.destination(half_edge),
+            );
+            match directed_edges.get(&(b, a)) {
+                Some(twin) => mesh.half_edges[half_edge].twin = *twin,
+                None => {
+                    let twin = mesh.half_edges.len();
+                    mesh.half_edges.push(HalfEdge {
+                        origin: b,
+                        twin: half_edge,
+                        next: usize::MAX,
+                        face: None,
+                        corner: mesh.half_edges[half_edge].corner,
+                    });
+                    mesh.half_edges[half_edge].twin = twin;
+
+                    // Two boundaries passing through one vertex can't be told apart
+                    if boundary_outgoing.insert(b, twin).is_some() {
+                        return Err(HalfEdgeError::NonManifoldVertex);
+                    }
+                }
+            }
+        }
+        for half_edge in face_half_edges..mesh.half_edges.len() {
+            let end = mesh.half_edges[mesh.half_edges[half_edge].twin].origin;
+            mesh.half_edges[half_edge].next = match boundary_outgoing.get(&end) {
+                Some(next) => *next,
+                None => return Err(HalfEdgeError::NonManifoldVertex),
+            };
+        }
+        for (vertex, half_edge) in boundary_outgoing {
+            mesh.vertices[vertex].half_edge = half_edge;
+        }
+
+        // Each vertex must have a single fan of faces around it
+        let mut outgoing_counts = vec![0; mesh.vertices.len()];
+        for half_edge in mesh.half_edges.iter() {
+            outgoing_counts[half_edge.origin] += 1;
+        }
+        for (vertex, count) in outgoing_counts.into_iter().enumerate() {
+            if mesh.outgoing(vertex).len() != count {
+                return Err(HalfEdgeError::NonManifoldVertex);
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    /// Convert back to a triangle mesh, fan triangulating any larger faces
+    ///
+    /// Corners with identical attributes share a vertex
+    pub fn to_mesh(&self) -> Mesh {
+        let mut mesh = Mesh {
+            name: self.name.clone(),
+            material: self.material,
+            ..Default::default()
+        };
+
+        let mut vertex_map: HashMap<[u32; 12], u32> = HashMap::new();
+        for face in 0..self.faces.len() {
+            let indices: Vec<u32> = self
+                .face_half_edges(face)
+                .into_iter()
+                .map(|half_edge| {
+                    let mut vertex = self.half_edges[half_edge].corner;
+                    vertex.position = self.vertices[self.half_edges[half_edge].origin].position;
+                    *vertex_map.entry(bytemuck::cast(vertex)).or_insert_with(|| {
+                        mesh.vertices.push(vertex);
+                        (mesh.vertices.len() - 1) as u32
+                    })
+                })
+                .collect();
+
+            for i in 1..indices.len() - 1 {
+                mesh.indices
+                    .extend([indices[0], indices[i], indices[i + 1]]);
+            }
+            mesh.face_sizes.push(indices.len() as u32);
+        }
+
+        if mesh.face_sizes.iter().all(|size| *size == 3) {
+            mesh.face_sizes.clear();
+        }
+
+        mesh
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    pub fn half_edges(&self) -> &[HalfEdge] {
+        &self.half_edges
+    }
+
+    pub fn faces(&self) -> &[Face] {
+        &self.faces
+    }
+
+    /// Number of edges, each made of two half-edges
+    pub fn edge_count(&self) -> usize {
+        self.half_edges.len() / 2
+    }
+
+    /// Vertex a half-edge points to
+    pub fn destination(&self, half_edge: usize) -> usize {
+        self.half_edges[self.half_edges[half_edge].next].origin
+    }
+
+    /// The half-edge before this one around its face or boundary loop
+    pub fn prev(&self, half_edge: usize) -> usize {
+        let mut prev = half_edge;
+        while self.half_edges[prev].next != half_edge {
+            prev = self.half_edges[prev].next;
+        }
+        prev
+    }
+
+    /// Half-edges leaving a vertex, in clockwise order seen from outside, starting at
+    /// the boundary for boundary vertices
+    pub fn outgoing(&self, vertex: usize) -> Vec<usize> {
+        let start = self.vertices[vertex].half_edge;
+        let mut outgoing = vec![start];
+        let mut half_edge = self.half_edges[self.half_edges[start].twin].next;
+        while half_edge != start {
+            outgoing.push(half_edge);
+            half_edge = self.half_edges[self.half_edges[half_edge].twin].next;
+        }
+        outgoing
+    }
+
+    /// Vertices connected to a vertex by an edge, in clockwise order
+    pub fn one_ring(&self, vertex: usize) -> Vec<usize> {
+        self.outgoing(vertex)
+            .into_iter()
+            .map(|half_edge| self.destination(half_edge))
+            .collect()
+    }
+
+    /// Faces touching a vertex, in clockwise order
+    pub fn vertex_faces(&self, vertex: usize) -> Vec<usize> {
+        self.outgoing(vertex)
+            .into_iter()
+            .filter_map(|half_edge| self.half_edges[half_edge].face)
+            .collect()
+    }
+
+    /// Half-edges around a face, in winding order
+    pub fn face_half_edges(&self, face: usize) -> Vec<usize> {
+        let start = self.faces[face].half_edge;
+        let mut half_edges = vec![start];
+        let mut half_edge = self.half_edges[start].next;
+        while half_edge != start {
+            half_edges.push(half_edge);
+            half_edge = self.half_edges[half_edge].next;
+        }
+        half_edges
+    }
+
+    /// Vertices around a face, in winding order
+    pub fn face_vertices(&self, face: usize) -> Vec<usize> {
+        self.face_half_edges(face)
+            .into_iter()
+            .map(|half_edge| self.half_edges[half_edge].origin)
+            .collect()
+    }
+
+    /// Faces sharing an edge with a face
+    pub fn face_neighbours(&self, face: usize) -> Vec<usize> {
+        self.face_half_edges(face)
+            .into_iter()
+            .filter_map(|half_edge| self.half_edges[self.half_edges[half_edge].twin].face)
+            .collect()
+    }
+
+    pub fn is_boundary_vertex(&self, vertex: usize) -> bool {
+        self.half_edges[self.vertices[vertex].half_edge]
+            .face
+            .is_none()
+    }
+
+    pub fn is_boundary_edge(&self, half_edge: usize) -> bool {
+        self.half_edges[half_edge].face.is_none()
+            || self.half_edges[self.half_edges[half_edge].twin]
+                .face
+                .is_none()
+    }
+
+    /// The half-edge from one vertex to another, if they share an edge
+    pub fn find_half_edge(&self, from: usize, to: usize) -> Option<usize> {
+        self.outgoing(from)
+            .into_iter()
+            .find(|half_edge| self.destination(*half_edge) == to)
+    }
+
+    /// Vertices around each open boundary
+    pub fn boundary_loops(&self) -> Vec<Vec<usize>> {
+        let mut visited = vec![false; self.half_edges.len()];
+        let mut loops = vec![];
+        for start in 0..self.half_edges.len() {
+            if visited[start] || self.half_edges[start].face.is_some() {
+                continue;
+            }
+
+            let mut boundary = vec![];
+            let mut half_edge = start;
+            while !visited[half_edge] {
+                visited[half_edge] = true;
+                boundary.push(self.half_edges[half_edge].origin);
+                half_edge = self.half_edges[half_edge].next;
+            }
+            loops.push(boundary);
+        }
+        loops
+    }
+
+    /// Replace the edge shared by two triangles with the edge between their other two
+    /// vertices
+    pub fn flip_edge(&mut self, half_edge: usize) -> Result<(), HalfEdgeError> {
+        let twin = self.half_edges[half_edge].twin;
+        let (f, g) = match (self.half_edges[half_edge].face, self.half_edges[twin].face) {
+            (Some(f), Some(g)) => (f, g),
+            _ => return Err(HalfEdgeError::BoundaryEdge),
+        };
+        if self.face_half_edges(f).len() != 3 || self.face_half_edges(g).len() != 3 {
+            return Err(HalfEdgeError::NotTriangle);
+        }
+
+        // f is u → v → c and g is v → u → d
+        let (a1, b1) = (self.half_edges[half_edge].next, self.half_edges[twin].next);
+        let (a2, b2) = (self.half_edges[a1].next, self.half_edges[b1].next);
+        let (u, v) = (
+            self.half_edges[half_edge].origin,
+            self.half_edges[twin].origin,
+        );
+        let (c, d) = (self.half_edges[a2].origin, self.half_edges[b2].origin);
+
+        // The new edge can't already exist, and u & v need to keep at least two faces
+        if c == d || self.find_half_edge(c, d).is_some() {
+            return Err(HalfEdgeError::InvalidFlip);
+        }
+        for vertex in [u, v] {
+            if !self.is_boundary_vertex(vertex) && self.outgoing(vertex).len() <= 3 {
+                return Err(HalfEdgeError::InvalidFlip);
+            }
+        }
+
+        // f becomes d → c → u and g becomes c → d → v
+        let (c_corner, d_corner) = (self.half_edges[a2].corner, self.half_edges[b2].corner);
+        self.half_edges[half_edge] = HalfEdge {
+            origin: d,
+            next: a2,
+            corner: d_corner,
+            ..self.half_edges[half_edge]
+        };
+        self.half_edges[twin] = HalfEdge {
+            origin: c,
+            next: b2,
+            corner: c_corner,
+            ..self.half_edges[twin]
+        };
+        self.half_edges[a2].next = b1;
+        self.half_edges[b1].next = half_edge;
+        self.half_edges[b1].face = Some(f);
+        self.half_edges[b2].next = a1;
+        self.half_edges[a1].next = twin;
+        self.half_edges[a1].face = Some(g);
+        self.faces[f].half_edge = half_edge;
+        self.faces[g].half_edge = twin;
+
+        if self.vertices[u].half_edge == half_edge {
+            self.vertices[u].half_edge = b1;
+        }
+        if self.vertices[v].half_edge == twin {
+            self.vertices[v].half_edge = a1;
+        }
+
+        Ok(())
+    }
+
+    /// Insert a vertex part way along an edge, splitting any triangles on either side in
+    /// two, and return the new vertex
+    pub fn split_edge(&mut self, half_edge: usize, t: f32) -> usize {
+        let twin = self.half_edges[half_edge].twin;
+        let (u, v) = (
+            self.half_edges[half_edge].origin,
+            self.half_edges[twin].origin,
+        );
+        let position = Vector3::from(self.vertices[u].position)
+            .lerp(Vector3::from(self.vertices[v].position), t);
+
+        // Corner attributes are interpolated separately on each side, to keep seams
+        let (after_half_edge, after_twin) =
+            (self.half_edges[half_edge].next, self.half_edges[twin].next);
+        let mut half_edge_corner = lerp_corner(
+            &self.half_edges[half_edge].corner,
+            &self.half_edges[after_half_edge].corner,
+            t,
+        );
+        let mut twin_corner = lerp_corner(
+            &self.half_edges[after_twin].corner,
+            &self.half_edges[twin].corner,
+            t,
+        );
+        half_edge_corner.position = position.into();
+        twin_corner.position = position.into();
+
+        // half_edge becomes u → m → v and twin becomes v → m → u
+        let vertex = self.vertices.len();
+        let (second, twin_second) = (self.half_edges.len(), self.half_edges.len() + 1);
+        self.half_edges.push(HalfEdge {
+            origin: vertex,
+            twin,
+            next: after_half_edge,
+            face: self.half_edges[half_edge].face,
+            corner: half_edge_corner,
+        });
+        self.half_edges.push(HalfEdge {
+            origin: vertex,
+            twin: half_edge,
+            next: after_twin,
+            face: self.half_edges[twin].face,
+            corner: twin_corner,
+        });
+        self.half_edges[half_edge].next = second;
+        self.half_edges[half_edge].twin = twin_second;
+        self.half_edges[twin].next = twin_second;
+        self.half_edges[twin].twin = second;
+        self.vertices.push(Vertex {
+            position: position.into(),
+            half_edge: if self.half_edges[twin_second].face.is_none() {
+                twin_second
+            } else {
+                second
+            },
+        });
+
+        for (first, second) in [(half_edge, second), (twin, twin_second)] {
+            if let Some(face) = self.half_edges[first].face {
+                if self.face_half_edges(face).len() == 4 {
+                    self.split_face(face, first, second);
+                }
+            }
+        }
+
+        vertex
+    }
+
+    /// Split a quad x → m → y → c that was a triangle before m was inserted, connecting m
+    /// to c
+    fn split_face(&mut self, face: usize, first: usize, second: usize) {
+        let after = self.half_edges[second].next;
+        let opposite = self.half_edges[after].next;
+        let new_face = self.faces.len();
+        let (to_m, from_m) = (self.half_edges.len(), self.half_edges.len() + 1);
+
+        // face becomes m → y → c and new_face becomes m → c → x
+        self.half_edges.push(HalfEdge {
+            origin: self.half_edges[opposite].origin,
+            twin: from_m,
+            next: second,
+            face: Some(face),
+            corner: self.half_edges[opposite].corner,
+        });
+        self.half_edges.push(HalfEdge {
+            origin: self.half_edges[second].origin,
+            twin: to_m,
+            next: opposite,
+            face: Some(new_face),
+            corner: self.half_edges[second].corner,
+        });
+        self.half_edges[after].next = to_m;
+        self.half_edges[opposite].face = Some(new_face);
+        self.half_edges[first].next = from_m;
+        self.half_edges[first].face = Some(new_face);
+        self.faces[face].half_edge = second;
+        self.faces.push(Face { half_edge: from_m });
+    }
+
+    /// Merge the two vertices of an edge at its midpoint, removing the triangles either
+    /// side of it, and return the merged vertex
+    ///
+    /// The merged vertex takes the average of the two corners from a face along the edge
+    pub fn collapse_edge(&mut self, half_edge: usize) -> Result<usize, HalfEdgeError> {
+        let twin = self.half_edges[half_edge].twin;
+        let (u, v) = (
+            self.half_edges[half_edge].origin,
+            self.half_edges[twin].origin,
+        );
+
+        let sides: Vec<usize> = [half_edge, twin]
+            .into_iter()
+            .filter(|side| self.half_edges[*side].face.is_some())
+            .collect();
+        let mut opposite = HashSet::new();
+        for side in sides.iter() {
+            let face = self.half_edges[*side].face.unwrap();
+            if self.face_half_edges(face).len() != 3 {
+                return Err(HalfEdgeError::NotTriangle);
+            }
+            opposite.insert(self.half_edges[self.prev(*side)].origin);
+        }
+
+        // Link condition, the only vertices next to both ends can be the ones opposite the
+        // edge, otherwise the surface gets pinched
+        let u_ring: HashSet<usize> = self.one_ring(u).into_iter().collect();
+        let common: HashSet<usize> = self
+            .one_ring(v)
+            .into_iter()
+            .filter(|vertex| u_ring.contains(vertex))
+            .collect();
+        if common != opposite {
+            return Err(HalfEdgeError::InvalidCollapse);
+        }
+
+        // Joining two boundaries through the inside pinches too
+        if !self.is_boundary_edge(half_edge)
+            && self.is_boundary_vertex(u)
+            && self.is_boundary_vertex(v)
+        {
+            return Err(HalfEdgeError::InvalidCollapse);
+        }
+
+        // The opposite vertices lose an edge, so need one to spare
+        for vertex in opposite.iter() {
+            let minimum = if self.is_boundary_vertex(*vertex) {
+                3
+            } else {
+                4
+            };
+            if self.outgoing(*vertex).len() < minimum {
+                return Err(HalfEdgeError::InvalidCollapse);
+            }
+        }
+
+        let position = Vector3::from(self.vertices[u].position)
+            .lerp(Vector3::from(self.vertices[v].position), 0.5);
+        let mut corner = {
+            let side = sides[0];
+            let after = self.half_edges[side].next;
+            lerp_corner(
+                &self.half_edges[side].corner,
+                &self.half_edges[after].corner,
+                0.5,
+            )
+        };
+        corner.position = position.into();
+
+        let v_outgoing = self.outgoing(v);
+        let u_outgoing = self.outgoing(u);
+        let mut removed_half_edges = vec![half_edge, twin];
+        let mut removed_faces = vec![];
+        let mut remaining = vec![];
+        for side in [half_edge, twin] {
+            let after = self.half_edges[side].next;
+            match self.half_edges[side].face {
+                Some(face) => {
+                    // Glue together the outer half-edges of the two remaining sides
+                    let before = self.half_edges[after].next;
+                    let (outer_after, outer_before) =
+                        (self.half_edges[after].twin, self.half_edges[before].twin);
+                    self.half_edges[outer_after].twin = outer_before;
+                    self.half_edges[outer_before].twin = outer_after;
+
+                    let opposite = self.half_edges[before].origin;
+                    self.vertices[opposite].half_edge = outer_after;
+                    remaining.push(outer_before);
+
+                    removed_half_edges.extend([after, before]);
+                    removed_faces.push(face);
+                }
+                None => {
+                    let before = self.prev(side);
+                    self.half_edges[before].next = after;
+                    remaining.push(after);
+                }
+            }
+        }
+
+        for outgoing in v_outgoing.into_iter().chain(u_outgoing) {
+            self.half_edges[outgoing].origin = u;
+            if self.half_edges[outgoing].face.is_some() {
+                self.half_edges[outgoing].corner = corner;
+            }
+        }
+        self.vertices[u].position = position.into();
+        self.vertices[u].half_edge = remaining
+            .into_iter()
+            .find(|half_edge| !removed_half_edges.contains(half_edge))
+            .unwrap();
+
+        for vertex in opposite.into_iter().chain([u]) {
+            self.prefer_boundary(vertex);
+        }
+
+        // Removing v moves the last vertex into its place, which may be u
+        let merged = if u == self.vertices.len() - 1 { v } else { u };
+        self.remove(vec![v], removed_half_edges, removed_faces);
+
+        Ok(merged)
+    }
+
+    /// Point a vertex at its outgoing boundary half-edge, if it has one
+    fn prefer_boundary(&mut self, vertex: usize) {
+        if let Some(boundary) = self
+            .outgoing(vertex)
+            .into_iter()
+            .find(|half_edge| self.half_edges[*half_edge].face.is_none())
+        {
+            self.vertices[vertex].half_edge = boundary;
+        }
+    }
+
+    /// Remove elements that nothing references any more, moving the last elements into
+    /// the gaps and updating what references them
+    fn remove(&mut self, vertices: Vec<usize>, half_edges: Vec<usize>, faces: Vec<usize>) {
+        for removed in sorted_descending(half_edges) {
+            let last = self.half_edges.len() - 1;
+            self.half_edges.swap_remove(removed);
+            if removed == last {
+                continue;
+            }
+
+            let moved = self.half_edges[removed];
+            self.half_edges[moved.twin].twin = removed;
+            let mut before = removed;
+            while self.half_edges[before].next != last {
+                before = self.half_edges[before].next;
+            }
+            self.half_edges[before].next = removed;
+            if let Some(face) = moved.face {
+                if self.faces[face].half_edge == last {
+                    self.faces[face].half_edge = removed;
+                }
+            }
+            if self.vertices[moved.origin].half_edge == last {
+                self.vertices[moved.origin].half_edge = removed;
+            }
+        }
+
+        for removed in sorted_descending(faces) {
+            let last = self.faces.len() - 1;
+            self.faces.swap_remove(removed);
+            if removed == last {
+                continue;
+            }
+
+            for half_edge in self.face_half_edges(removed) {
+                self.half_edges[half_edge].face = Some(removed);
+            }
+        }
+
+        for removed in sorted_descending(vertices) {
+            let last = self.vertices.len() - 1;
+            self.vertices.swap_remove(removed);
+            if removed == last {
+                continue;
+            }
+
+            for half_edge in self.outgoing(removed) {
+                self.half_edges[half_edge].origin = removed;
+            }
+        }
+    }
+}
+
+fn sorted_descending(mut indices: Vec<usize>) -> Vec<usize> {
+    indices.sort_unstable_by(|a, b| b.cmp(a));
+    indices
+}
+
+fn lerp_corner(a: &ModelVertex, b: &ModelVertex, t: f32) -> ModelVertex {
+    let lerp = |a: f32, b: f32| a + (b - a) * t;
+    let mut corner = *a;
+    for i in 0..2 {
+        corner.texture_coords[i] = lerp(a.texture_coords[i], b.texture_coords[i]);
+    }
+
+    let normal = Vector3::from(a.normal).lerp(Vector3::from(b.normal), t);
+    if normal.magnitude2() > 0.0 {
+        corner.normal = normal.normalize().into();
+    }
+    for i in 0..4 {
+        corner.tangent[i] = lerp(a.tangent[i], b.tangent[i]);
+    }
+
+    corner
+}
+
+#[derive(Debug)]
+pub enum HalfEdgeError {
+    InvalidIndex,
+    DegenerateFace,
+    NonManifoldEdge,
+    NonManifoldVertex,
+    BoundaryEdge,
+    NotTriangle,
+    InvalidFlip,
+    InvalidCollapse,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HalfEdgeError, HalfEdgeMesh};
+    use crate::model::{Mesh, ModelVertex};
+    use crate::test_util::{grid, load_mesh};
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex::new(position, [position[0], position[1]], [0.0, 0.0, 1.0])
+    }
+
+    fn tetrahedron() -> Mesh {
+        Mesh {
+            vertices: vec![
+                vertex([0.0, 0.0, 0.0]),
+                vertex([1.0, 0.0, 0.0]),
+                vertex([0.0, 1.0, 0.0]),
+                vertex([0.0, 0.0, 1.0]),
+            ],
+            indices: vec![0, 2, 1, 0, 1, 3, 1, 2, 3, 2, 0, 3],
+            ..Default::default()
+        }
+    }
+
+    /// Check every connection in the mesh points back the right way
+    fn assert_consistent(mesh: &HalfEdgeMesh) {
+        let half_edges = mesh.half_edges();
+        assert!(half_edges.len().is_multiple_of(2));
+        for (index, half_edge) in half_edges.iter().enumerate() {
+            let twin = &half_edges[half_edge.twin];
+            assert_ne!(half_edge.twin, index);
+            assert_eq!(twin.twin, index);
+            assert_eq!(twin.origin, mesh.destination(index));
+            assert_ne!(half_edge.origin, mesh.destination(index));
+            assert!(half_edge.face.is_some() || twin.face.is_some());
+            assert_eq!(half_edges[half_edge.next].face, half_edge.face);
+            assert!(mesh.outgoing(half_edge.origin).contains(&index));
+        }
+
+        for (index, face) in mesh.faces().iter().enumerate() {
+            assert_eq!(half_edges[face.half_edge].face, Some(index));
+            assert!(mesh.face_half_edges(index).len() >= 3);
+        }
+
+        let mut outgoing_total = 0;
+        for (index, vertex) in mesh.vertices().iter().enumerate() {
+            assert_eq!(half_edges[vertex.half_edge].origin, index);
+            let outgoing = mesh.outgoing(index);
+            let on_boundary = outgoing.iter().any(|h| half_edges[*h].face.is_none());
+            assert_eq!(mesh.is_boundary_vertex(index), on_boundary);
+            outgoing_total += outgoing.len();
+        }
+        assert_eq!(outgoing_total, half_edges.len());
+    }
+
+    fn euler_characteristic(mesh: &HalfEdgeMesh) -> isize {
+        mesh.vertices().len() as isize - mesh.edge_count() as isize + mesh.faces().len() as isize
+    }
+
+    #[test]
+    pub fn closed_meshes() {
+        for file in ["cube.obj", "sphere.obj", "torus.obj"] {
+            let mesh = load_mesh(file);
+            let half_edge_mesh = HalfEdgeMesh::from_mesh(&mesh).unwrap();
+
+            assert_consistent(&half_edge_mesh);
+            assert!(half_edge_mesh.boundary_loops().is_empty());
+            let face_count = match mesh.face_sizes.len() {
+                0 => mesh.indices.len() / 3,
+                face_count => face_count,
+            };
+            assert_eq!(half_edge_mesh.faces().len(), face_count);
+            for face in 0..half_edge_mesh.faces().len() {
+                assert_eq!(
+                    half_edge_mesh.face_neighbours(face).len(),
+                    half_edge_mesh.face_vertices(face).len()
+                );
+            }
+
+            // A sphere has no holes and a torus has one
+            let expected = if file == "torus.obj" { 0 } else { 2 };
+            assert_eq!(euler_characteristic(&half_edge_mesh), expected);
+        }
+    }
+
+    #[test]
+    pub fn round_trip_keeps_faces() {
+        let mesh = load_mesh("cube.obj");
+        let round_trip = HalfEdgeMesh::from_mesh(&mesh).unwrap().to_mesh();
+
+        assert_eq!(round_trip.vertices.len(), mesh.vertices.len());
+        assert_eq!(round_trip.indices.len(), mesh.indices.len());
+        assert_eq!(round_trip.face_sizes, mesh.face_sizes);
+        for (a, b) in mesh.indices.iter().zip(round_trip.indices.iter()) {
+            let (a, b) = (mesh.vertices[*a as usize], round_trip.vertices[*b as usize]);
+            assert_eq!(
+                bytemuck::cast::<_, [u32; 12]>(a),
+                bytemuck::cast::<_, [u32; 12]>(b)
+            );
+        }
+    }
+
+    #[test]
+    pub fn grid_adjacency() {
+        let mesh = HalfEdgeMesh::from_mesh(&grid(2)).unwrap();
+        assert_consistent(&mesh);
+        assert_eq!(euler_characteristic(&mesh), 1);
+
+        // The centre vertex is surrounded by six triangles
+        assert!(!mesh.is_boundary_vertex(4));
+        let mut ring = mesh.one_ring(4);
+        ring.sort_unstable();
+        assert_eq!(ring, vec![0, 1, 3, 5, 7, 8]);
+        assert_eq!(mesh.vertex_faces(4).len(), 6);
+
+        // A corner has one triangle, and the boundary goes round the outside
+        assert!(mesh.is_boundary_vertex(2));
+        assert_eq!(mesh.vertex_faces(2).len(), 1);
+        let boundary = mesh.boundary_loops();
+        assert_eq!(boundary.len(), 1);
+        assert_eq!(boundary[0].len(), 8);
+        assert!(!boundary[0].contains(&4));
+
+        let face = mesh.vertex_faces(4)[0];
+        assert!(mesh.face_vertices(face).contains(&4));
+
+        // Every triangle in such a small grid has an edge on the boundary
+        assert_eq!(mesh.face_neighbours(face).len(), 2);
+        assert_eq!(mesh.face_neighbours(mesh.vertex_faces(2)[0]).len(), 1);
+    }
+
+    #[test]
+    pub fn flip_edge() {
+        let mut mesh = HalfEdgeMesh::from_mesh(&grid(1)).unwrap();
+        let diagonal = mesh.find_half_edge(0, 3).unwrap();
+        mesh.flip_edge(diagonal).unwrap();
+
+        assert_consistent(&mesh);
+        assert!(mesh.find_half_edge(0, 3).is_none());
+        assert!(mesh.find_half_edge(1, 2).is_some());
+
+        // Both triangles still face the same way
+        let flipped = mesh.to_mesh();
+        for face in 0..flipped.indices.len() / 3 {
+            let [a, b, c] = flipped.face_positions(face);
+            assert!((b - a).cross(c - a).z > 0.0);
+        }
+
+        let boundary = mesh.find_half_edge(0, 1).unwrap();
+        assert!(matches!(
+            mesh.flip_edge(boundary),
+            Err(HalfEdgeError::BoundaryEdge)
+        ));
+    }
+
+    #[test]
+    pub fn split_edge() {
+        let mut mesh = HalfEdgeMesh::from_mesh(&load_mesh("sphere.obj")).unwrap();
+        let (vertices, edges, faces) =
+            (mesh.vertices().len(), mesh.edge_count(), mesh.faces().len());
+        let vertex = mesh.split_edge(0, 0.5);
+
+        assert_consistent(&mesh);
+        assert_eq!(mesh.vertices().len(), vertices + 1);
+        assert_eq!(mesh.edge_count(), edges + 3);
+        assert_eq!(mesh.faces().len(), faces + 2);
+        assert_eq!(mesh.one_ring(vertex).len(), 4);
+
+        // Splitting a boundary edge only splits the one triangle
+        let mut mesh = HalfEdgeMesh::from_mesh(&grid(1)).unwrap();
+        let vertex = mesh.split_edge(mesh.find_half_edge(0, 1).unwrap(), 0.25);
+
+        assert_consistent(&mesh);
+        assert_eq!(mesh.faces().len(), 3);
+        assert_eq!(mesh.vertices()[vertex].position, [0.25, 0.0, 0.0]);
+        assert!(mesh.is_boundary_vertex(vertex));
+        assert_eq!(mesh.boundary_loops()[0].len(), 5);
+    }
+
+    #[test]
+    pub fn collapse_edge() {
+        let mut mesh = HalfEdgeMesh::from_mesh(&load_mesh("sphere.obj")).unwrap();
+        let (vertices, edges, faces) =
+            (mesh.vertices().len(), mesh.edge_count(), mesh.faces().len());
+        mesh.collapse_edge(0).unwrap();
+
+        assert_consistent(&mesh);
+        assert_eq!(mesh.vertices().len(), vertices - 1);
+        assert_eq!(mesh.edge_count(), edges - 3);
+        assert_eq!(mesh.faces().len(), faces - 2);
+        assert_eq!(euler_characteristic(&mesh), 2);
+
+        // Collapse repeatedly, keeping the mesh valid
+        let mut collapsed = 0;
+        let mut half_edge = 0;
+        while collapsed < 100 && half_edge < mesh.half_edges().len() {
+            if mesh.collapse_edge(half_edge).is_ok() {
+                collapsed += 1;
+            } else {
+                half_edge += 1;
+            }
+        }
+        assert_eq!(collapsed, 100);
+        assert_consistent(&mesh);
+        assert_eq!(euler_characteristic(&mesh), 2);
+    }
+
+    #[test]
+    pub fn collapse_boundary_edge() {
+        let mut mesh = HalfEdgeMesh::from_mesh(&grid(2)).unwrap();
+        let merged = mesh
+            .collapse_edge(mesh.find_half_edge(0, 1).unwrap())
+            .unwrap();
+
+        assert_consistent(&mesh);
+        assert_eq!(mesh.faces().len(), 7);
+        assert_eq!(mesh.vertices()[merged].position, [0.5, 0.0, 0.0]);
+        assert!(mesh.is_boundary_vertex(merged));
+        assert_eq!(mesh.boundary_loops()[0].len(), 7);
+
+        // An inside edge joining two boundary vertices would pinch the mesh
+        let mut mesh = HalfEdgeMesh::from_mesh(&grid(1)).unwrap();
+        let diagonal = mesh.find_half_edge(0, 3).unwrap();
+        assert!(matches!(
+            mesh.collapse_edge(diagonal),
+            Err(HalfEdgeError::InvalidCollapse)
+        ));
+    }
+
+    #[test]
+    pub fn tetrahedron_edges_cant_collapse_or_flip() {
+        let mut mesh = HalfEdgeMesh::from_mesh(&tetrahedron()).unwrap();
+        assert_consistent(&mesh);
+        for half_edge in 0..mesh.half_edges().len() {
+            assert!(matches!(
+                mesh.collapse_edge(half_edge),
+                Err(HalfEdgeError::InvalidCollapse)
+            ));
+            assert!(matches!(
+                mesh.flip_edge(half_edge),
+                Err(HalfEdgeError::InvalidFlip)
+            ));
+        }
+    }
+
+    #[test]
+    pub fn invalid_meshes() {
+        // Three triangles sharing one edge
+        let mut mesh = tetrahedron();
+        mesh.vertices.push(vertex([-1.0, -1.0, -1.0]));
+        mesh.indices.extend([1, 0, 4]);
+        assert!(matches!(
+            HalfEdgeMesh::from_mesh(&mesh),
+            Err(HalfEdgeError::NonManifoldEdge)
+        ));
+
+        // Two triangles touching at a single vertex
+        let mesh = Mesh {
+            vertices: [
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [-1.0, 0.0, 0.0],
+                [-1.0, -1.0, 0.0],
+            ]
+            .map(vertex)
+            .to_vec(),
+            indices: vec![0, 1, 2, 0, 3, 4],
+            ..Default::default()
+        };
+        assert!(matches!(
+            HalfEdgeMesh::from_mesh(&mesh),
+            Err(HalfEdgeError::NonManifoldVertex)
+        ));
+
+        let mut mesh = grid(1);
+        mesh.indices[0] = 10;
+        assert!(matches!(
+            HalfEdgeMesh::from_mesh(&mesh),
+            Err(HalfEdgeError::InvalidIndex)
+        ));
+
+        mesh.indices[0] = mesh.indices[1];
+        assert!(matches!(
+            HalfEdgeMesh::from_mesh(&mesh),
+            Err(HalfEdgeError::DegenerateFace)
+        ));
+    }
+}
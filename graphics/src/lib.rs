@@ -1,9 +1,9 @@
-use std::path::Path;
+use std::time::Instant;
 
-use camera::{Camera, CameraController, MoveMode};
+use camera::{Camera, CameraController, PanZoomMode};
 use cgmath::{Matrix4, Vector3};
 use curve::BezierCurve;
-use render::{ControlEvent, Render2D, Render3D, Renderer};
+use render::{ControlEvent, Render2D, Renderer};
 use wgpu::*;
 use winit::{
     event::*,
@@ -11,42 +11,51 @@ use winit::{
     window::WindowBuilder,
 };
 
-mod camera;
-mod curve;
-mod model;
-mod obj;
-mod render;
-mod texture;
-mod transform;
+pub mod animation;
+pub mod bounds;
+pub mod bvh;
+pub mod camera;
+pub mod camera_path;
+pub mod curve;
+pub mod gltf_io;
+#[cfg(test)]
+mod golden;
+pub mod half_edge;
+pub mod light;
+pub mod model;
+pub mod normals;
+pub mod obj;
+pub mod path_tracer;
+pub mod picking;
+pub mod rasterizer;
+pub mod render;
+pub mod repair;
+pub mod scene;
+pub mod simplify;
+pub mod statistics;
+pub mod subdivision;
+pub mod swp;
+pub mod tangents;
+#[cfg(test)]
+mod test_util;
+pub mod texture;
+pub mod transform;
+
+/// Control mode of the window's camera, which must outlive the event loop
+static PAN_ZOOM_MODE: PanZoomMode = PanZoomMode::new();
 
 pub async fn run() {
     env_logger::init();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
-    window.set_cursor_grab(true).expect("could not grab cursor");
 
-    let camera = Camera::new(
-        (0.0, 10.0, 0.0).into(),
-        (0.0, 0.0, 0.0).into(),
-        Vector3::unit_y(),
-        window.inner_size().width as f32 / window.inner_size().height as f32,
-        45.0,
-        0.1,
-        100.0,
-    );
-    let camera_controller = CameraController::new(0.2, &MoveMode {});
-
-    let mut state = Render3D::new(&window, camera, camera_controller).await;
-    let model = obj::load_model(Path::new("./data/sphere.obj")).expect("model loading failed");
-    state.add_model(model);
-
-    let camera_controller = CameraController::new(0.2, &MoveMode {});
-    let camera = Camera::new(
+    let camera_controller = CameraController::new(0.2, &PAN_ZOOM_MODE);
+    let camera = Camera::orthographic(
         (0.0, 0.0, 5.0).into(),
         (0.0, 0.0, 0.0).into(),
         Vector3::unit_y(),
         window.inner_size().width as f32 / window.inner_size().height as f32,
-        45.0,
+        2.0,
         0.1,
         100.0,
     );
@@ -58,52 +67,57 @@ pub async fn run() {
         ),
     };
     render_2d.add_curve(curve);
+    render_2d.fit_curves();
 
+    let mut last_frame = Instant::now();
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == window.id() => {
-            if !render_2d.input(&ControlEvent::WindowEvent(event)) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        render_2d.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        render_2d.resize(**new_inner_size);
-                    }
-                    WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::R),
-                                ..
-                            },
-                        ..
-                    } => {
-                        let model = obj::load_model(Path::new("./data/cube.obj"))
-                            .expect("model loading failed");
-                        state.add_model(model);
+        } if window_id == window.id() && !render_2d.input(&ControlEvent::WindowEvent(event)) => {
+            match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(physical_size) => {
+                    render_2d.resize(*physical_size);
+                }
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    render_2d.resize(**new_inner_size);
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Right,
+                    ..
+                } => {
+                    let pick = render_2d
+                        .cursor()
+                        .and_then(|cursor| render_2d.pick_control_point(cursor, 8.0));
+                    if let Some(pick) = pick {
+                        log::info!(
+                            "picked control point {} of curve {}",
+                            pick.point,
+                            pick.curve
+                        );
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
         Event::DeviceEvent { event, .. } => {
             render_2d.input(&ControlEvent::DeviceEvent(event));
         }
         Event::RedrawRequested(window_id) if window_id == window.id() => {
-            render_2d.update();
+            let now = Instant::now();
+            render_2d.update(now - last_frame);
+            last_frame = now;
             match render_2d.render() {
                 Ok(_) => {}
                 Err(SurfaceError::Lost) => render_2d.recreate(),
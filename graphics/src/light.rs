@@ -0,0 +1,502 @@
+//! Lights & Phong shading
+//!
+//! Shared by `Render3D`, whose shader mirrors `Lighting::shade`, & `SoftwareRenderer`.
+//! Both renderers draw a scene without lights unlit rather than shading it, showing
+//! each material's diffuse texture or colour as is
+
+use cgmath::*;
+
+use crate::model::{Material, MaterialIllumination, NormalMapConvention};
+
+/// Most lights the GPU shader takes, later lights are ignored
+pub const MAX_LIGHTS: usize = 8;
+
+/// A light source, colours being linear RGB scaled by the intensity
+///
+/// Point & spot lights fall off with the inverse square of distance, smoothly reaching
+/// zero at `range`, or never if it's 0
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Light {
+    /// Light from infinitely far away, shining along `direction`
+    Directional {
+        direction: Vector3<f32>,
+        colour: [f32; 3],
+        intensity: f32,
+    },
+    Point {
+        position: Point3<f32>,
+        colour: [f32; 3],
+        intensity: f32,
+        range: f32,
+    },
+
+    /// Cone of light, full strength within `inner_angle` of `direction` & fading out by
+    /// `outer_angle`
+    Spot {
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        colour: [f32; 3],
+        intensity: f32,
+        range: f32,
+        inner_angle: Deg<f32>,
+        outer_angle: Deg<f32>,
+    },
+}
+
+/// How specular highlights are calculated
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    /// Reflected light direction against the view direction
+    Phong,
+
+    /// Normal against the half way vector of the light & view directions
+    #[default]
+    BlinnPhong,
+}
+
+/// Lights of a scene
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Lighting {
+    pub lights: Vec<Light>,
+
+    /// Light reaching everything, scaled by each material's ambient colour
+    pub ambient: [f32; 3],
+    pub shading_model: ShadingModel,
+}
+
+impl Light {
+    /// Unit vector from the point towards the light, with the light arriving there
+    pub fn incident(&self, point: Point3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        match *self {
+            Light::Directional {
+                direction,
+                colour,
+                intensity,
+            } => (-direction.normalize(), Vector3::from(colour) * intensity),
+            Light::Point {
+                position,
+                colour,
+                intensity,
+                range,
+            } => {
+                let (direction, attenuation) = attenuate(position - point, range);
+                (direction, Vector3::from(colour) * intensity * attenuation)
+            }
+            Light::Spot {
+                position,
+                direction,
+                colour,
+                intensity,
+                range,
+                inner_angle,
+                outer_angle,
+            } => {
+                let (to_light, attenuation) = attenuate(position - point, range);
+                let cone = smoothstep(
+                    outer_angle.cos(),
+                    inner_angle.cos(),
+                    (-to_light).dot(direction.normalize()),
+                );
+                (
+                    to_light,
+                    Vector3::from(colour) * intensity * attenuation * cone,
+                )
+            }
+        }
+    }
+}
+
+impl Lighting {
+    /// Colour of a point, lit by every light with no shadows
+    ///
+    /// `diffuse` is the diffuse texture sample, or white for untextured materials. With no
+    /// lights only emissive & ambient light remain, and materials with ambient off show
+    /// their diffuse colour as is
+    pub fn shade(
+        &self,
+        material: &Material,
+        diffuse: [f32; 3],
+        position: Point3<f32>,
+        normal: Vector3<f32>,
+        eye: Point3<f32>,
+    ) -> [f32; 3] {
+        let diffuse =
+            Vector3::from(diffuse).mul_element_wise(Vector3::from(material.diffuse_color));
+        let (ambient_on, highlight_on) = illumination(material);
+        if !ambient_on {
+            return diffuse.into();
+        }
+
+        let normal = normal.normalize();
+        let view = (eye - position).normalize();
+        let ambient_colour = Vector3::from(material.ambient_color);
+        let mut colour = Vector3::from(material.emissive_color)
+            + ambient_colour
+                .mul_element_wise(Vector3::from(self.ambient))
+                .mul_element_wise(diffuse);
+
+        for light in &self.lights {
+            let (direction, radiance) = light.incident(position);
+            let lambert = normal.dot(direction);
+            if lambert <= 0.0 {
+                continue;
+            }
+
+            let mut reflected = diffuse * lambert;
+            if highlight_on {
+                let alignment = match self.shading_model {
+                    ShadingModel::Phong => (normal * 2.0 * lambert - direction).dot(view),
+                    ShadingModel::BlinnPhong => normal.dot((direction + view).normalize()),
+                };
+                let highlight = alignment.max(0.0).powf(material.specular_exponent.max(1.0));
+                reflected += Vector3::from(material.specular_color) * highlight;
+            }
+            colour += reflected.mul_element_wise(radiance);
+        }
+
+        colour.into()
+    }
+
+    /// Lights packed for the GPU shader
+    pub(crate) fn as_uniform(&self) -> LightingUniform {
+        let mut uniform = LightingUniform {
+            lights: [LightUniform::default(); MAX_LIGHTS],
+            ambient: self.ambient,
+            count: self.lights.len().min(MAX_LIGHTS) as u32,
+            shading_model: match self.shading_model {
+                ShadingModel::Phong => 0,
+                ShadingModel::BlinnPhong => 1,
+            },
+            _padding: [0; 3],
+        };
+        for (uniform, light) in uniform.lights.iter_mut().zip(&self.lights) {
+            *uniform = LightUniform::new(light);
+        }
+
+        uniform
+    }
+}
+
+/// Tilt a surface normal by a sample of the material's tangent space normal map, the
+/// tangent having the bitangent sign in w like `ModelVertex::tangent`
+///
+/// Surfaces without a tangent keep their normal
+pub fn perturb_normal(
+    material: &Material,
+    normal: Vector3<f32>,
+    tangent: Vector4<f32>,
+    mapped: [f32; 3],
+) -> Vector3<f32> {
+    let normal = normal.normalize();
+    let tangent_direction = tangent.truncate();
+    let orthogonal = tangent_direction - normal * normal.dot(tangent_direction);
+    if orthogonal.magnitude2() < 1e-8 {
+        return normal;
+    }
+
+    let tangent_direction = orthogonal.normalize();
+    let bitangent = normal.cross(tangent_direction) * tangent.w;
+    let green = match material.normal_map_convention {
+        NormalMapConvention::OpenGl => 1.0,
+        NormalMapConvention::DirectX => -1.0,
+    };
+    let [x, y, z] = mapped.map(|channel| channel * 2.0 - 1.0);
+
+    (tangent_direction * x * material.bump_multiplier
+        + bitangent * y * material.bump_multiplier * green
+        + normal * z)
+        .normalize()
+}
+
+/// Whether a material's illumination mode takes ambient & direct light, then specular
+/// highlights, being on for both if unset
+pub(crate) fn illumination(material: &Material) -> (bool, bool) {
+    match material.illumination_mode {
+        Some(MaterialIllumination::ColorAmbientOff) => (false, false),
+        Some(MaterialIllumination::ColorAmbientOn) => (true, false),
+        _ => (true, true),
+    }
+}
+
+/// Direction of the offset & the inverse square falloff over it, windowed to `range`
+fn attenuate(offset: Vector3<f32>, range: f32) -> (Vector3<f32>, f32) {
+    let distance2 = offset.magnitude2().max(1e-8);
+    let window = if range > 0.0 {
+        (1.0 - (distance2 / (range * range)).powi(2))
+            .clamp(0.0, 1.0)
+            .powi(2)
+    } else {
+        1.0
+    };
+
+    (offset / distance2.sqrt(), window / distance2)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0).max(1e-6)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// A light as laid out in `shader.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightUniform {
+    position: [f32; 3],
+
+    /// 0 for directional, 1 for point & 2 for spot lights
+    kind: u32,
+    direction: [f32; 3],
+    range: f32,
+
+    /// Colour scaled by intensity
+    colour: [f32; 3],
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [f32; 3],
+}
+
+impl LightUniform {
+    fn new(light: &Light) -> LightUniform {
+        match *light {
+            Light::Directional {
+                direction,
+                colour,
+                intensity,
+            } => LightUniform {
+                kind: 0,
+                direction: direction.normalize().into(),
+                colour: (Vector3::from(colour) * intensity).into(),
+                ..Default::default()
+            },
+            Light::Point {
+                position,
+                colour,
+                intensity,
+                range,
+            } => LightUniform {
+                position: position.into(),
+                kind: 1,
+                range,
+                colour: (Vector3::from(colour) * intensity).into(),
+                ..Default::default()
+            },
+            Light::Spot {
+                position,
+                direction,
+                colour,
+                intensity,
+                range,
+                inner_angle,
+                outer_angle,
+            } => LightUniform {
+                position: position.into(),
+                kind: 2,
+                direction: direction.normalize().into(),
+                range,
+                colour: (Vector3::from(colour) * intensity).into(),
+                cos_inner: inner_angle.cos(),
+                cos_outer: outer_angle.cos(),
+                _padding: [0.0; 3],
+            },
+        }
+    }
+}
+
+/// Every light as laid out in `shader.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightingUniform {
+    lights: [LightUniform; MAX_LIGHTS],
+    ambient: [f32; 3],
+    count: u32,
+    shading_model: u32,
+    _padding: [u32; 3],
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use crate::model::{Material, MaterialIllumination};
+
+    use super::*;
+
+    fn matte() -> Material {
+        Material {
+            diffuse_color: [0.5, 0.25, 1.0],
+            ..Default::default()
+        }
+    }
+
+    fn shiny() -> Material {
+        Material {
+            diffuse_color: [0.0; 3],
+            specular_color: [1.0; 3],
+            specular_exponent: 20.0,
+            ..Default::default()
+        }
+    }
+
+    /// Just the given lights, with no ambient light
+    fn lights_only(lights: Vec<Light>) -> Lighting {
+        Lighting {
+            lights,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn directional_light_follows_lambert() {
+        let lighting = lights_only(vec![Light::Directional {
+            direction: -Vector3::unit_y(),
+            colour: [1.0; 3],
+            intensity: 2.0,
+        }]);
+        let eye = Point3::new(0.0, 5.0, 5.0);
+        let shade = |normal: Vector3<f32>| {
+            lighting.shade(&matte(), [1.0; 3], Point3::origin(), normal, eye)
+        };
+
+        assert_abs_diff_eq!(
+            Vector3::from(shade(Vector3::unit_y())),
+            Vector3::new(1.0, 0.5, 2.0)
+        );
+        let tilted = shade(Vector3::new(0.0, 1.0, 1.0));
+        assert_abs_diff_eq!(tilted[0], 1.0 / 2.0f32.sqrt(), epsilon = 1e-5);
+        assert_eq!(shade(-Vector3::unit_y()), [0.0; 3]);
+    }
+
+    #[test]
+    pub fn point_lights_fall_off_with_distance() {
+        let light = |range| Light::Point {
+            position: Point3::new(0.0, 2.0, 0.0),
+            colour: [1.0; 3],
+            intensity: 4.0,
+            range,
+        };
+        let (direction, radiance) = light(0.0).incident(Point3::origin());
+        assert_abs_diff_eq!(direction, Vector3::unit_y());
+        assert_abs_diff_eq!(radiance, Vector3::new(1.0, 1.0, 1.0));
+
+        let (_, near) = light(10.0).incident(Point3::new(0.0, 1.0, 0.0));
+        let (_, far) = light(10.0).incident(Point3::new(0.0, -7.0, 0.0));
+        let (_, outside) = light(10.0).incident(Point3::new(0.0, -9.0, 0.0));
+        assert!(near.x > far.x && far.x > 0.0);
+        assert_eq!(outside.x, 0.0);
+    }
+
+    #[test]
+    pub fn spot_light_cone() {
+        let light = Light::Spot {
+            position: Point3::new(0.0, 1.0, 0.0),
+            direction: -Vector3::unit_y(),
+            colour: [1.0; 3],
+            intensity: 1.0,
+            range: 0.0,
+            inner_angle: Deg(20.0),
+            outer_angle: Deg(30.0),
+        };
+        let radiance = |x: f32| {
+            let point = Point3::new(x, 0.0, 0.0);
+            light.incident(point).1.x * (point - Point3::new(0.0, 1.0, 0.0)).magnitude2()
+        };
+
+        assert_abs_diff_eq!(radiance(0.0), 1.0);
+        assert_abs_diff_eq!(radiance(Deg(19.0).tan()), 1.0, epsilon = 1e-5);
+        let edge = radiance(Deg(25.0).tan());
+        assert!(0.0 < edge && edge < 1.0);
+        assert_eq!(radiance(Deg(31.0).tan()), 0.0);
+    }
+
+    #[test]
+    pub fn specular_highlights() {
+        let light = Light::Directional {
+            direction: Vector3::new(1.0, -1.0, 0.0),
+            colour: [1.0; 3],
+            intensity: 1.0,
+        };
+        let highlight = |shading_model, eye: Point3<f32>| {
+            let lighting = Lighting {
+                lights: vec![light],
+                ambient: [0.0; 3],
+                shading_model,
+            };
+            lighting.shade(&shiny(), [1.0; 3], Point3::origin(), Vector3::unit_y(), eye)[0]
+        };
+
+        // Both peak at the mirror direction, where Phong falls off faster
+        let mirror = Point3::new(1.0, 1.0, 0.0);
+        let off_mirror = Point3::new(1.0, 1.5, 0.0);
+        assert_abs_diff_eq!(highlight(ShadingModel::Phong, mirror), 1.0, epsilon = 1e-5);
+        assert_abs_diff_eq!(
+            highlight(ShadingModel::BlinnPhong, mirror),
+            1.0,
+            epsilon = 1e-5
+        );
+        assert!(
+            highlight(ShadingModel::Phong, off_mirror)
+                < highlight(ShadingModel::BlinnPhong, off_mirror)
+        );
+    }
+
+    #[test]
+    pub fn normal_map_conventions() {
+        let normal = Vector3::unit_z();
+        let tangent = Vector4::new(1.0, 0.0, 0.0, 1.0);
+
+        // A flat texel leaves the normal, a green one tilts it up or down by convention
+        let mut material = Material::default();
+        let flat = perturb_normal(&material, normal, tangent, [0.5, 0.5, 1.0]);
+        assert_abs_diff_eq!(flat, normal);
+        let up = Vector3::new(0.0, 1.0, 1.0).normalize();
+        let tilted = perturb_normal(&material, normal, tangent, [0.5, 1.0, 1.0]);
+        assert_abs_diff_eq!(tilted, up);
+
+        material.normal_map_convention = NormalMapConvention::DirectX;
+        let tilted = perturb_normal(&material, normal, tangent, [0.5, 1.0, 1.0]);
+        assert_abs_diff_eq!(tilted, Vector3::new(0.0, -1.0, 1.0).normalize());
+
+        // A mirrored texture flips the bitangent, & the multiplier scales the tilt
+        material.normal_map_convention = NormalMapConvention::OpenGl;
+        material.bump_multiplier = 0.5;
+        let mirrored = Vector4::new(1.0, 0.0, 0.0, -1.0);
+        let tilted = perturb_normal(&material, normal, mirrored, [1.0, 1.0, 1.0]);
+        assert_abs_diff_eq!(tilted, Vector3::new(0.5, -0.5, 1.0).normalize());
+
+        let untangented = perturb_normal(&material, normal, Vector4::zero(), [1.0, 1.0, 1.0]);
+        assert_abs_diff_eq!(untangented, normal);
+    }
+
+    #[test]
+    pub fn material_terms() {
+        let lighting = Lighting {
+            lights: vec![],
+            ambient: [0.5; 3],
+            shading_model: ShadingModel::BlinnPhong,
+        };
+        let material = Material {
+            ambient_color: [1.0, 0.0, 0.0],
+            emissive_color: [0.0, 0.0, 0.25],
+            ..matte()
+        };
+        let shade = |material: &Material| {
+            let colour = lighting.shade(
+                material,
+                [0.5; 3],
+                Point3::origin(),
+                Vector3::unit_y(),
+                Point3::new(0.0, 1.0, 0.0),
+            );
+            Vector3::from(colour)
+        };
+
+        assert_abs_diff_eq!(shade(&material), Vector3::new(0.125, 0.0, 0.25));
+
+        // Illumination mode 0 shows the diffuse colour with no lighting
+        let unlit = Material {
+            illumination_mode: Some(MaterialIllumination::ColorAmbientOff),
+            ..material
+        };
+        assert_abs_diff_eq!(shade(&unlit), Vector3::new(0.25, 0.125, 0.5));
+    }
+}
@@ -15,17 +15,22 @@ pub trait Vertex {
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
-    position: [f32; 3],
-    texture_coords: [f32; 2],
-    normal: [f32; 3],
+    pub position: [f32; 3],
+    pub texture_coords: [f32; 2],
+    pub normal: [f32; 3],
+
+    /// Tangent in xyz & bitangent sign in w, the bitangent is `w * cross(normal, tangent)`
+    pub tangent: [f32; 4],
 }
 
 impl ModelVertex {
+    /// Create a vertex with no tangent, see `Mesh::compute_tangents`
     pub fn new(position: [f32; 3], texture_coords: [f32; 2], normal: [f32; 3]) -> ModelVertex {
         ModelVertex {
             position,
             texture_coords,
             normal,
+            tangent: [0.0; 4],
         }
     }
 }
@@ -51,6 +56,11 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: VertexFormat::Float32x3,
                 },
+                VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as BufferAddress,
+                    shader_location: 3,
+                    format: VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -70,7 +80,7 @@ pub struct Model {
 ///
 /// This is not necessarily in a form ready for consumption by the GPU
 /// but is a more direct representation of the original mesh data
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Mesh {
     /// Name of the mesh
     pub name: String,
@@ -81,14 +91,20 @@ pub struct Mesh {
     /// Vector of vertex indices
     pub indices: Vec<u32>,
 
+    /// Number of corners of each original polygon, when the mesh was built from polygons
+    /// fan triangulated into consecutive triangles of `indices`. Empty if every face is a
+    /// triangle
+    pub face_sizes: Vec<u32>,
+
     /// Index of material in model material vector
     pub material: Option<usize>,
 }
 
 /// A generalized material to be applied to a mesh
 ///
-/// This is not necessarily in a form ready for consumption by the GPU
-#[derive(Debug, Default, Clone)]
+/// This is not necessarily in a form ready for consumption by the GPU. The default
+/// material is black, opaque, doesn't refract & leaves normal maps unscaled
+#[derive(Debug, Clone)]
 pub struct Material {
     /// Specular exponent of the material, controlling object glossiness
     pub specular_exponent: f32,
@@ -114,11 +130,62 @@ pub struct Material {
     /// Illumintation mode of the material. Often now not specified
     pub illumination_mode: Option<MaterialIllumination>,
 
-    /// Absolute path to normal map file
+    /// Metalness of the material, used by PBR materials
+    pub metallic: f32,
+
+    /// Roughness of the material, used by PBR materials
+    pub roughness: f32,
+
+    /// Absolute path to tangent space normal map file, from the MTL `norm` or `map_Bump`
+    /// statement. Height maps, which MTL's `bump` traditionally names, aren't supported
     pub bump_map_file: PathBuf,
 
+    /// Encoded normal map image, if it is embedded in the model file
+    pub bump_map_data: Option<Vec<u8>>,
+
+    /// Scale of the normal map's tilt, from the MTL `-bm` option or glTF normal texture
+    /// scale
+    pub bump_multiplier: f32,
+
+    /// Which way the normal map's green channel points
+    pub normal_map_convention: NormalMapConvention,
+
     /// Absolute path to diffuse texutre file
     pub diffuse_texture_file: PathBuf,
+
+    /// Encoded diffuse texture image, if it is embedded in the model file
+    pub diffuse_texture_data: Option<Vec<u8>>,
+
+    /// Absolute path to metallic (blue) & roughness (green) texture file
+    pub metallic_roughness_texture_file: PathBuf,
+
+    /// Encoded metallic & roughness texture image, if it is embedded in the model file
+    pub metallic_roughness_texture_data: Option<Vec<u8>>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            specular_exponent: 0.0,
+            specular_color: [0.0; 3],
+            ambient_color: [0.0; 3],
+            diffuse_color: [0.0; 3],
+            emissive_color: [0.0; 3],
+            optical_density: 1.0,
+            opacity: 1.0,
+            illumination_mode: None,
+            metallic: 0.0,
+            roughness: 0.0,
+            bump_map_file: PathBuf::new(),
+            bump_map_data: None,
+            bump_multiplier: 1.0,
+            normal_map_convention: NormalMapConvention::default(),
+            diffuse_texture_file: PathBuf::new(),
+            diffuse_texture_data: None,
+            metallic_roughness_texture_file: PathBuf::new(),
+            metallic_roughness_texture_data: None,
+        }
+    }
 }
 
 /// Material Illumintaion Modes
@@ -140,6 +207,17 @@ pub enum MaterialIllumination {
     CastShadows = 10,
 }
 
+/// Direction of a tangent space normal map's green channel, which tools disagree on
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum NormalMapConvention {
+    /// Green points along the bitangent, as used by glTF, Blender & Maya
+    #[default]
+    OpenGl,
+
+    /// Green points against the bitangent, as used by DirectX, Unreal & 3ds Max
+    DirectX,
+}
+
 // TODO: Pass through errors better
 #[derive(Debug)]
 pub enum ModelLoadError {
@@ -165,12 +243,12 @@ pub struct GpuMesh {
 impl GpuMesh {
     fn from_mesh(mesh: Mesh, device: &Device) -> Self {
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some(&format!("Vertex Buffer")),
+            label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(&mesh.vertices),
             usage: BufferUsages::VERTEX,
         });
         let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some(&format!("Index Buffer")),
+            label: Some("Index Buffer"),
             contents: bytemuck::cast_slice(&mesh.indices),
             usage: BufferUsages::INDEX,
         });
@@ -190,26 +268,79 @@ pub struct GpuMaterial {
     pub texture_bind_group: BindGroup,
 }
 
+/// Material values as laid out in `shader.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    ambient: [f32; 3],
+    specular_exponent: f32,
+    diffuse: [f32; 3],
+    textured: u32,
+    specular: [f32; 3],
+    illumination: u32,
+    emissive: [f32; 3],
+    bump_multiplier: f32,
+
+    /// 1 if the normal map's green channel points along the bitangent, -1 if against it
+    normal_green: f32,
+    _padding: [f32; 3],
+}
+
 impl GpuMaterial {
-    fn from_material(
+    /// Upload a material, using a white texture if it has no diffuse texture & a flat
+    /// one if it has no normal map
+    pub(crate) fn from_material(
         material: Material,
         device: &Device,
         queue: &Queue,
         layout: &BindGroupLayout,
     ) -> GpuMaterial {
-        // TODO - Handle the error properly
-        let diffuse_texture = texture::Texture::from_file(
-            device,
-            queue,
-            &material.diffuse_texture_file.as_path(),
-            "yeah",
-        )
-        .unwrap();
-
+        let decode = |data: &Option<Vec<u8>>, file: &Path| match data {
+            Some(data) => image::load_from_memory(data).ok(),
+            None => image::open(file).ok(),
+        };
+        let solid = |colour: [u8; 4]| {
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, colour.into()))
+        };
+
+        let diffuse_image = decode(
+            &material.diffuse_texture_data,
+            &material.diffuse_texture_file,
+        );
+        let textured = diffuse_image.is_some();
+        let diffuse_image = diffuse_image.unwrap_or_else(|| solid([255; 4]));
+        let diffuse_texture =
+            texture::Texture::from_image(device, queue, &diffuse_image, Some("diffuse")).unwrap();
+
+        // Normal maps hold directions, so mustn't be decoded as sRGB
+        let normal_image = decode(&material.bump_map_data, &material.bump_map_file)
+            .unwrap_or_else(|| solid([128, 128, 255, 255]));
         let normal_texture =
-            texture::Texture::from_file(device, queue, &material.bump_map_file.as_path(), "normal")
+            texture::Texture::from_image_linear(device, queue, &normal_image, Some("normal"))
                 .unwrap();
 
+        let (ambient_on, highlight_on) = crate::light::illumination(&material);
+        let uniform = MaterialUniform {
+            ambient: material.ambient_color,
+            specular_exponent: material.specular_exponent,
+            diffuse: material.diffuse_color,
+            textured: textured as u32,
+            specular: material.specular_color,
+            illumination: ambient_on as u32 + highlight_on as u32,
+            emissive: material.emissive_color,
+            bump_multiplier: material.bump_multiplier,
+            normal_green: match material.normal_map_convention {
+                NormalMapConvention::OpenGl => 1.0,
+                NormalMapConvention::DirectX => -1.0,
+            },
+            _padding: [0.0; 3],
+        };
+        let material_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Material buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM,
+        });
+
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             layout,
             entries: &[
@@ -229,6 +360,10 @@ impl GpuMaterial {
                     binding: 3,
                     resource: BindingResource::Sampler(&diffuse_texture.sampler),
                 },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: material_buffer.as_entire_binding(),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
@@ -247,9 +382,14 @@ impl<'a> GpuModel<'a> {
         layout: &BindGroupLayout,
         label: Option<&'a str>,
     ) -> Result<GpuModel<'a>, ModelLoadError> {
-        match crate::obj::load_model(model_path) {
-            Ok(model) => Ok(GpuModel::from_model(model, device, queue, layout, label)),
-            Err(_) => Err(ModelLoadError::InvalidModel),
+        let model = match model_path.extension().and_then(|ext| ext.to_str()) {
+            Some("gltf") | Some("glb") => crate::gltf_io::load_model(model_path).ok(),
+            _ => crate::obj::load_model(model_path).ok(),
+        };
+
+        match model {
+            Some(model) => Ok(GpuModel::from_model(model, device, queue, layout, label)),
+            None => Err(ModelLoadError::InvalidModel),
         }
     }
 
@@ -0,0 +1,279 @@
+//! Vertex normal generation for meshes
+
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Rad, Vector3, Zero};
+
+use crate::model::{Mesh, ModelVertex};
+
+/// How face normals are combined into vertex normals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalMode {
+    /// Every triangle uses its own face normal, splitting vertices shared by faces at an angle
+    Flat,
+
+    /// Face normals are weighted by the area of each face
+    AreaWeighted,
+
+    /// Face normals are weighted by the angle of each face at the vertex
+    AngleWeighted,
+}
+
+impl Mesh {
+    /// Replace the vertex normals with ones generated from the triangles
+    ///
+    /// Vertices at the same position are smoothed together even if they were split for
+    /// texture seams. With a crease angle, faces meeting at a sharper angle than it
+    /// are not smoothed together, which splits the vertices along that edge
+    pub fn compute_normals(&mut self, mode: NormalMode, crease_angle: Option<Rad<f32>>) {
+        let face_count = self.indices.len() / 3;
+        let position = |i: u32| Vector3::from(self.vertices[i as usize].position);
+
+        // Unnormalized face normals, their magnitude is twice the face area
+        let face_normals: Vec<Vector3<f32>> = (0..face_count)
+            .map(|face| {
+                let [a, b, c] = self.face_positions(face);
+                (b - a).cross(c - a)
+            })
+            .collect();
+
+        // Faces touching each position, regardless of which vertex copy they use
+        let mut position_faces: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (corner, index) in self.indices.iter().enumerate() {
+            position_faces
+                .entry(position_key(&self.vertices[*index as usize]))
+                .or_default()
+                .push(corner);
+        }
+
+        let unit = |normal: Vector3<f32>| {
+            if normal.is_zero() {
+                normal
+            } else {
+                normal.normalize()
+            }
+        };
+        let crease_cos = crease_angle.map(|angle| angle.0.cos());
+
+        let mut corner_normals = Vec::with_capacity(self.indices.len());
+        for (corner, index) in self.indices.iter().take(face_count * 3).enumerate() {
+            let face = corner / 3;
+            let face_normal = unit(face_normals[face]);
+            if mode == NormalMode::Flat {
+                corner_normals.push(face_normal);
+                continue;
+            }
+
+            let mut normal = Vector3::zero();
+            for other in position_faces[&position_key(&self.vertices[*index as usize])].iter() {
+                let other_face = other / 3;
+                if let Some(crease_cos) = crease_cos {
+                    if unit(face_normals[other_face]).dot(face_normal) < crease_cos {
+                        continue;
+                    }
+                }
+
+                normal += match mode {
+                    NormalMode::AreaWeighted => face_normals[other_face],
+                    _ => {
+                        // Angle between the two edges leaving the corner
+                        let corner_position = position(self.indices[*other]);
+                        let next = position(self.indices[other_face * 3 + (other + 1) % 3]);
+                        let prev = position(self.indices[other_face * 3 + (other + 2) % 3]);
+                        let (to_next, to_prev) = (next - corner_position, prev - corner_position);
+                        if to_next.is_zero() || to_prev.is_zero() {
+                            continue;
+                        }
+
+                        unit(face_normals[other_face]) * to_next.angle(to_prev).0
+                    }
+                };
+            }
+
+            // Fall back to the face normal if everything cancelled out
+            corner_normals.push(if normal.is_zero() {
+                face_normal
+            } else {
+                normal.normalize()
+            });
+        }
+
+        // Rebuild the vertices, only splitting a vertex if its corners now disagree
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut vertex_map: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+        let mut indices = Vec::with_capacity(corner_normals.len());
+        for (index, normal) in self.indices.iter().zip(corner_normals) {
+            let normal: [f32; 3] = normal.into();
+            let key = (*index, normal.map(f32::to_bits));
+            let new_index = *vertex_map.entry(key).or_insert_with(|| {
+                let mut vertex = self.vertices[*index as usize];
+                vertex.normal = normal;
+                vertices.push(vertex);
+                (vertices.len() - 1) as u32
+            });
+            indices.push(new_index);
+        }
+
+        self.vertices = vertices;
+        self.indices = indices;
+    }
+
+    /// Positions of the three corners of a triangle
+    pub fn face_positions(&self, face: usize) -> [Vector3<f32>; 3] {
+        [0, 1, 2].map(|corner| {
+            Vector3::from(self.vertices[self.indices[face * 3 + corner] as usize].position)
+        })
+    }
+}
+
+fn position_key(vertex: &ModelVertex) -> [u32; 3] {
+    vertex.position.map(f32::to_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{assert_abs_diff_eq, Angle, Deg, InnerSpace, Vector3};
+
+    use super::NormalMode;
+    use crate::model::{Mesh, ModelVertex};
+    use crate::test_util::load_mesh;
+
+    fn face_normal(mesh: &Mesh, face: usize) -> Vector3<f32> {
+        let [a, b, c] = mesh.face_positions(face);
+        (b - a).cross(c - a).normalize()
+    }
+
+    /// Two triangles folded along the x axis, meeting at a right angle
+    fn folded_quad() -> Mesh {
+        Mesh {
+            vertices: vec![
+                ModelVertex::new([0.0, 0.0, 0.0], [0.0; 2], [0.0; 3]),
+                ModelVertex::new([1.0, 0.0, 0.0], [0.0; 2], [0.0; 3]),
+                ModelVertex::new([0.0, 1.0, 0.0], [0.0; 2], [0.0; 3]),
+                ModelVertex::new([0.0, 0.0, 1.0], [0.0; 2], [0.0; 3]),
+            ],
+            indices: vec![0, 1, 2, 0, 3, 1],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn flat_normals_cube() {
+        let mut mesh = load_mesh("cube.obj");
+        let vertex_count = mesh.vertices.len();
+        mesh.compute_normals(NormalMode::Flat, None);
+
+        assert!(mesh.vertices.len() > vertex_count);
+        for face in 0..mesh.indices.len() / 3 {
+            for corner in 0..3 {
+                let vertex = mesh.vertices[mesh.indices[face * 3 + corner] as usize];
+                assert_abs_diff_eq!(
+                    Vector3::from(vertex.normal),
+                    face_normal(&mesh, face),
+                    epsilon = 1e-5
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn smooth_normals_cube_point_outwards() {
+        for mode in [NormalMode::AreaWeighted, NormalMode::AngleWeighted] {
+            let mut mesh = load_mesh("cube.obj");
+            let vertex_count = mesh.vertices.len();
+            mesh.compute_normals(mode, None);
+
+            // Without a crease angle no vertices need to be split
+            assert_eq!(mesh.vertices.len(), vertex_count);
+            for vertex in mesh.vertices.iter() {
+                let normal = Vector3::from(vertex.normal);
+                assert_abs_diff_eq!(normal.magnitude(), 1.0, epsilon = 1e-5);
+                assert!(normal.dot(Vector3::from(vertex.position)) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    pub fn small_crease_angle_matches_flat_cube() {
+        let mut mesh = load_mesh("cube.obj");
+        let vertex_count = mesh.vertices.len();
+        mesh.compute_normals(NormalMode::AngleWeighted, Some(Deg(1.0).into()));
+
+        // Only (nearly) coplanar faces are smoothed together, so normals stay within the crease
+        assert!(mesh.vertices.len() > vertex_count);
+        for face in 0..mesh.indices.len() / 3 {
+            for corner in 0..3 {
+                let vertex = mesh.vertices[mesh.indices[face * 3 + corner] as usize];
+                let normal = Vector3::from(vertex.normal);
+                assert!(normal.dot(face_normal(&mesh, face)) >= Deg(1.0).cos() - 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    pub fn smooth_normals_sphere() {
+        for mode in [NormalMode::AreaWeighted, NormalMode::AngleWeighted] {
+            let mut mesh = load_mesh("sphere.obj");
+            let original = mesh.clone();
+            mesh.compute_normals(mode, Some(Deg(60.0).into()));
+
+            // A sphere has no sharp edges, and is centred on the origin
+            assert_eq!(mesh.vertices.len(), original.vertices.len());
+            for (vertex, original) in mesh.vertices.iter().zip(original.vertices.iter()) {
+                let normal = Vector3::from(vertex.normal);
+                assert!(normal.dot(Vector3::from(vertex.position).normalize()) > 0.99);
+                assert!(normal.dot(Vector3::from(original.normal).normalize()) > 0.99);
+            }
+        }
+    }
+
+    #[test]
+    pub fn crease_angle_splits_sharp_edges() {
+        let mut mesh = folded_quad();
+        mesh.compute_normals(NormalMode::AngleWeighted, None);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_abs_diff_eq!(
+            Vector3::from(mesh.vertices[0].normal),
+            Vector3::new(0.0, 1.0, 1.0).normalize()
+        );
+
+        let mut mesh = folded_quad();
+        mesh.compute_normals(NormalMode::AngleWeighted, Some(Deg(45.0).into()));
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.vertices[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(
+            mesh.vertices[mesh.indices[3] as usize].normal,
+            [0.0, 1.0, 0.0]
+        );
+    }
+
+    #[test]
+    pub fn angle_and_area_weights_differ() {
+        // A big and a small triangle sharing vertex 0 at a right angle
+        let mut area = Mesh {
+            vertices: vec![
+                ModelVertex::new([0.0, 0.0, 0.0], [0.0; 2], [0.0; 3]),
+                ModelVertex::new([4.0, 0.0, 0.0], [0.0; 2], [0.0; 3]),
+                ModelVertex::new([0.0, 4.0, 0.0], [0.0; 2], [0.0; 3]),
+                ModelVertex::new([0.0, 0.0, 1.0], [0.0; 2], [0.0; 3]),
+                ModelVertex::new([1.0, 0.0, 0.0], [0.0; 2], [0.0; 3]),
+            ],
+            indices: vec![0, 1, 2, 0, 3, 4],
+            ..Default::default()
+        };
+        let mut angle = area.clone();
+
+        area.compute_normals(NormalMode::AreaWeighted, None);
+        angle.compute_normals(NormalMode::AngleWeighted, None);
+
+        // Both faces have a right angle at vertex 0, but the first is 16 times the area
+        assert_abs_diff_eq!(
+            Vector3::from(angle.vertices[0].normal),
+            Vector3::new(0.0, 1.0, 1.0).normalize()
+        );
+        assert_abs_diff_eq!(
+            Vector3::from(area.vertices[0].normal),
+            Vector3::new(0.0, 1.0, 16.0).normalize()
+        );
+    }
+}
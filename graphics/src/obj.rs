@@ -7,7 +7,10 @@ use std::{
     str::{FromStr, Split},
 };
 
-use crate::model::{Material, MaterialIllumination, Mesh, Model, ModelVertex};
+use crate::{
+    model::{Material, MaterialIllumination, Mesh, Model, ModelVertex},
+    normals::NormalMode,
+};
 
 pub fn load_model(file: &Path) -> Result<Model, ObjLoadError> {
     let raw_model = match std::fs::read_to_string(file) {
@@ -29,13 +32,14 @@ pub fn load_model(file: &Path) -> Result<Model, ObjLoadError> {
                         match std::fs::read_to_string(
                             file.parent().unwrap().join(Path::new(mtl_file)),
                         ) {
-                            Ok(raw_mtl) => match loader.load_mtl(
-                                raw_mtl.as_str(),
-                                file.parent().expect("file does not have parent"),
-                            ) {
-                                Some(err) => return Err(err),
-                                None => {}
-                            },
+                            Ok(raw_mtl) => {
+                                if let Some(err) = loader.load_mtl(
+                                    raw_mtl.as_str(),
+                                    file.parent().expect("file does not have parent"),
+                                ) {
+                                    return Err(err);
+                                }
+                            }
                             Err(err) => return Err(ObjLoadError::FileLoadError(err)),
                         }
                     }
@@ -46,9 +50,8 @@ pub fn load_model(file: &Path) -> Result<Model, ObjLoadError> {
                         // First mesh encountered
                         prev = i;
                     } else {
-                        match loader.load_mesh(&lines[prev..i]) {
-                            Some(err) => return Err(err),
-                            None => {}
+                        if let Some(err) = loader.load_mesh(&lines[prev..i]) {
+                            return Err(err);
                         };
                         prev = i;
                         loader.current_material = String::default();
@@ -62,9 +65,8 @@ pub fn load_model(file: &Path) -> Result<Model, ObjLoadError> {
     }
 
     // Load final mesh
-    match loader.load_mesh(&lines[prev..]) {
-        Some(err) => return Err(err),
-        None => {}
+    if let Some(err) = loader.load_mesh(&lines[prev..]) {
+        return Err(err);
     }
 
     Ok(Model {
@@ -89,8 +91,8 @@ impl ModelLoader {
     fn load_mesh(&mut self, raw_mesh: &[&str]) -> Option<ObjLoadError> {
         for line in raw_mesh.iter() {
             let mut elements = line.split(" ");
-            match elements.next() {
-                Some(key) => match key {
+            if let Some(key) = elements.next() {
+                match key {
                     "v" => match self.load_vertex(elements) {
                         Ok(_) => {}
                         Err(err) => return Some(err),
@@ -112,14 +114,16 @@ impl ModelLoader {
                         None => return Some(ObjLoadError::InvalidMaterialName),
                     },
                     _ => {} // Just ignore any unrecognised key
-                },
-                None => {}
+                }
             }
         }
 
         // Groups/Objects can be defined with no faces, in which case there is no mesh
-        if self.current_faces.len() > 0 {
-            self.meshes.push(self.export_mesh());
+        if !self.current_faces.is_empty() {
+            match self.export_mesh() {
+                Ok(mesh) => self.meshes.push(mesh),
+                Err(err) => return Some(err),
+            }
         }
 
         None
@@ -132,16 +136,17 @@ impl ModelLoader {
             let mut indices = VertexIndices::default();
             for (i, index) in group.split("/").enumerate() {
                 // Value could be missing - If it is skip to next value
-                if index == "" {
+                if index.is_empty() {
                     continue;
                 }
 
+                // OBJ indices are 1 based, adjust to 0 based. Some exporters write a 0
+                // texture coord or normal index to mean it is missing
                 match index.parse::<usize>() {
-                    Ok(index) => match i {
-                        // OBJ indices are 1 based, adjust to 0 based
-                        0 => indices.position = index - 1,
-                        1 => indices.texture_coord = index - 1,
-                        2 => indices.normal = index - 1,
+                    Ok(index) => match (i, index.checked_sub(1)) {
+                        (0, Some(index)) => indices.position = index,
+                        (1, index) => indices.texture_coord = index,
+                        (2, index) => indices.normal = index,
                         _ => return Err(ObjLoadError::InvalidFaceValue),
                     },
                     Err(_) => return Err(ObjLoadError::InvalidFaceValue),
@@ -152,15 +157,13 @@ impl ModelLoader {
         }
 
         match face.len() {
+            0 => return Err(ObjLoadError::InvalidFaceValue),
             1 => self.current_faces.push(Face::Point([face[0]])),
             2 => self.current_faces.push(Face::Line([face[0], face[1]])),
             3 => self
                 .current_faces
                 .push(Face::Triangle([face[0], face[1], face[2]])),
-            4 => self
-                .current_faces
-                .push(Face::Quad([face[0], face[1], face[2], face[3]])),
-            _ => return Err(ObjLoadError::InvalidFaceValue),
+            _ => self.current_faces.push(Face::Polygon(face)),
         }
 
         Ok(())
@@ -212,17 +215,27 @@ impl ModelLoader {
         Ok(())
     }
 
-    fn export_mesh(&self) -> Mesh {
+    fn export_mesh(&self) -> Result<Mesh, ObjLoadError> {
         let mut mesh = Mesh::default();
         let mut vertex_map = Default::default();
 
         for face in self.current_faces.iter() {
-            self.export_face(face, &mut mesh, &mut vertex_map);
+            self.export_face(face, &mut mesh, &mut vertex_map)?;
         }
 
         mesh.material = self.material_map.get(&self.current_material).cloned();
 
-        mesh
+        // Only keep the polygon sizes if there was something other than triangles
+        if mesh.face_sizes.iter().all(|size| *size == 3) {
+            mesh.face_sizes.clear();
+        }
+
+        if vertex_map.keys().any(|indices| indices.normal.is_none()) {
+            mesh.compute_normals(NormalMode::AngleWeighted, None);
+        }
+        mesh.compute_tangents();
+
+        Ok(mesh)
     }
 
     fn export_face(
@@ -230,7 +243,7 @@ impl ModelLoader {
         face: &Face,
         mesh: &mut Mesh,
         vertex_map: &mut HashMap<VertexIndices, usize>,
-    ) {
+    ) -> Result<(), ObjLoadError> {
         match face {
             // Ignore points
             Face::Point(_) => {}
@@ -238,21 +251,22 @@ impl ModelLoader {
             Face::Line(_) => {}
             Face::Triangle(vertex_indices) => {
                 for vi in vertex_indices {
-                    self.export_vertex(vi, mesh, vertex_map);
+                    self.export_vertex(vi, mesh, vertex_map)?;
                 }
+                mesh.face_sizes.push(3);
             }
-            Face::Quad(vertex_indices) => {
-                // Split the quad into 2 triangles - With vertices 0, 1, 2
-                for vi in &vertex_indices[0..3] {
-                    self.export_vertex(vi, mesh, vertex_map);
+            Face::Polygon(vertex_indices) => {
+                // Fan triangulate the polygon - With vertices 0, 1, 2 then 0, 2, 3 etc.
+                for i in 1..vertex_indices.len() - 1 {
+                    self.export_vertex(&vertex_indices[0], mesh, vertex_map)?;
+                    self.export_vertex(&vertex_indices[i], mesh, vertex_map)?;
+                    self.export_vertex(&vertex_indices[i + 1], mesh, vertex_map)?;
                 }
-
-                // & vertices 1, 2, 3
-                self.export_vertex(&vertex_indices[0], mesh, vertex_map);
-                self.export_vertex(&vertex_indices[2], mesh, vertex_map);
-                self.export_vertex(&vertex_indices[3], mesh, vertex_map);
+                mesh.face_sizes.push(vertex_indices.len() as u32);
             }
         }
+
+        Ok(())
     }
 
     fn export_vertex(
@@ -260,15 +274,31 @@ impl ModelLoader {
         indices: &VertexIndices,
         mesh: &mut Mesh,
         vertex_map: &mut HashMap<VertexIndices, usize>,
-    ) {
-        let index = vertex_map.get(&indices);
+    ) -> Result<(), ObjLoadError> {
+        let index = vertex_map.get(indices);
         match index {
             Some(index) => mesh.indices.push(*index as u32),
             None => {
+                // Missing normals are generated once the whole mesh is loaded
                 let vertex = ModelVertex::new(
-                    self.positions[indices.position],
-                    self.texture_coords[indices.texture_coord],
-                    self.normals[indices.normal],
+                    match self.positions.get(indices.position) {
+                        Some(position) => *position,
+                        None => return Err(ObjLoadError::InvalidFaceIndex),
+                    },
+                    match indices.texture_coord {
+                        Some(texture_coord) => match self.texture_coords.get(texture_coord) {
+                            Some(texture_coord) => *texture_coord,
+                            None => return Err(ObjLoadError::InvalidFaceIndex),
+                        },
+                        None => [0.0; 2],
+                    },
+                    match indices.normal {
+                        Some(normal) => match self.normals.get(normal) {
+                            Some(normal) => *normal,
+                            None => return Err(ObjLoadError::InvalidFaceIndex),
+                        },
+                        None => [0.0; 3],
+                    },
                 );
 
                 let index = mesh.vertices.len();
@@ -278,6 +308,8 @@ impl ModelLoader {
                 vertex_map.insert(*indices, index);
             }
         }
+
+        Ok(())
     }
 
     // TODO - Swap load material into the impl and this to the top level
@@ -288,27 +320,21 @@ impl ModelLoader {
         let lines: Vec<&str> = raw_mtl.lines().collect();
         for i in 0..lines.len() {
             let mut elements = lines[i].split(" ");
-            match elements.next() {
-                Some(key) => {
-                    match key {
-                        "newmtl" => {
-                            if prev == 0 {
-                                // First mtl encountered
-                                current_material_name = elements.next().unwrap().to_string();
-                                prev = i;
-                            } else {
-                                match load_material(&lines[prev..i], dir) {
-                                    Ok(mat) => self.push_material(mat, current_material_name),
-                                    Err(_) => return Some(ObjLoadError::InvalidMaterialLib),
-                                };
-                                prev = i;
-                                current_material_name = elements.next().unwrap().to_string();
-                            }
-                        }
-                        _ => {}
-                    };
-                }
-                None => {}
+            if let Some(key) = elements.next() {
+                if key == "newmtl" {
+                    if prev == 0 {
+                        // First mtl encountered
+                        current_material_name = elements.next().unwrap().to_string();
+                        prev = i;
+                    } else {
+                        match load_material(&lines[prev..i], dir) {
+                            Ok(mat) => self.push_material(mat, current_material_name),
+                            Err(_) => return Some(ObjLoadError::InvalidMaterialLib),
+                        };
+                        prev = i;
+                        current_material_name = elements.next().unwrap().to_string();
+                    }
+                };
             }
         }
 
@@ -333,8 +359,8 @@ fn load_material(raw_material: &[&str], dir: &Path) -> Result<Material, ()> {
 
     for line in raw_material.iter() {
         let mut elements = line.split(" ");
-        match elements.next() {
-            Some(key) => match key {
+        if let Some(key) = elements.next() {
+            match key {
                 "Ns" => match load_num(elements.next()) {
                     Ok(f) => material.specular_exponent = f,
                     Err(_) => todo!(),
@@ -359,25 +385,59 @@ fn load_material(raw_material: &[&str], dir: &Path) -> Result<Material, ()> {
                     Ok(i) => material.illumination_mode = load_illumination_mode(i),
                     Err(_) => return Err(()),
                 },
-                "map_Bump" => match elements.next() {
-                    Some(file) => material.bump_map_file = dir.join(file.to_string()).to_path_buf(),
+                "map_Bump" | "norm" => match load_texture_statement(elements) {
+                    Some((file, bump_multiplier)) => {
+                        material.bump_map_file = dir.join(file);
+                        material.bump_multiplier = bump_multiplier.unwrap_or(1.0);
+                    }
                     None => return Err(()),
                 },
-                "map_Kd" => match elements.next() {
-                    Some(file) => {
-                        material.diffuse_texture_file = dir.join(file.to_string()).to_path_buf()
-                    }
+                "map_Kd" => match load_texture_statement(elements) {
+                    Some((file, _)) => material.diffuse_texture_file = dir.join(file),
                     None => return Err(()),
                 },
                 _ => {} // Just ignore any unrecognised key
-            },
-            None => {}
+            }
         }
     }
 
     Ok(material)
 }
 
+/// File name & bump multiplier of a texture statement like `map_Bump -bm 0.5 a.png`,
+/// skipping any other options
+fn load_texture_statement<'a>(
+    elements: impl Iterator<Item = &'a str>,
+) -> Option<(String, Option<f32>)> {
+    let tokens: Vec<&str> = elements.filter(|element| !element.is_empty()).collect();
+    let mut bump_multiplier = None;
+    let mut i = 0;
+    while i + 1 < tokens.len() && tokens[i].starts_with('-') {
+        let option = tokens[i];
+        i += 1;
+
+        // Offsets, scales & turbulence take one to three numbers, the rest a fixed number
+        // of values, always leaving the file name
+        let (min, max) = match option {
+            "-o" | "-s" | "-t" => (1, 3),
+            "-mm" => (2, 2),
+            _ => (1, 1),
+        };
+        for taken in 0..max {
+            if i + 1 >= tokens.len() || (taken >= min && tokens[i].parse::<f32>().is_err()) {
+                break;
+            }
+            if option == "-bm" {
+                bump_multiplier = tokens[i].parse().ok();
+            }
+            i += 1;
+        }
+    }
+
+    let file = tokens[i..].join(" ");
+    (!file.is_empty()).then_some((file, bump_multiplier))
+}
+
 fn load_num<T: FromStr>(raw_num: Option<&str>) -> Result<T, ()> {
     match raw_num {
         Some(raw_num) => {
@@ -394,10 +454,10 @@ fn load_num<T: FromStr>(raw_num: Option<&str>) -> Result<T, ()> {
 fn load_n_float<const N: usize>(raw_n_float: &mut Split<&str>) -> [f32; N] {
     let mut n_float = [0.0; N];
 
-    for i in 0..N {
+    for float in n_float.iter_mut() {
         let raw_float = raw_n_float.next();
         match raw_float {
-            Some(raw_float) => n_float[i] = raw_float.parse::<f32>().unwrap(), // TODO: Handle the result here,
+            Some(raw_float) => *float = raw_float.parse::<f32>().unwrap(), // TODO: Handle the result here,
             None => todo!(), // TODO: This should be a result
         }
     }
@@ -422,29 +482,21 @@ fn load_illumination_mode(mode: u32) -> Option<MaterialIllumination> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 struct VertexIndices {
     position: usize,
-    texture_coord: usize,
-    normal: usize,
-}
-
-impl Default for VertexIndices {
-    fn default() -> Self {
-        Self {
-            position: Default::default(),
-            texture_coord: Default::default(),
-            normal: Default::default(),
-        }
-    }
+    texture_coord: Option<usize>,
+    normal: Option<usize>,
 }
 
+// Points and lines are parsed but never exported to a mesh
+#[allow(dead_code)]
 #[derive(Debug)]
 enum Face {
     Point([VertexIndices; 1]),
     Line([VertexIndices; 2]),
     Triangle([VertexIndices; 3]),
-    Quad([VertexIndices; 4]),
+    Polygon(Vec<VertexIndices>),
 }
 
 #[derive(Debug)]
@@ -454,8 +506,49 @@ pub enum ObjLoadError {
     InvalidTextureCoordValue,
     InvalidNormalValue,
     InvalidFaceValue,
+    InvalidFaceIndex,
     InvalidMaterialName,
     InvalidMaterialLib,
 }
 
-// TODO: Add some tests here
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn out_of_range_face_index() {
+        let file = std::env::temp_dir().join("graphics-obj-out-of-range.obj");
+        std::fs::write(&file, "o broken\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n").unwrap();
+
+        let result = load_model(&file);
+        let _ = std::fs::remove_file(&file);
+        assert!(matches!(result, Err(ObjLoadError::InvalidFaceIndex)));
+    }
+
+    #[test]
+    pub fn texture_options() {
+        let dir = Path::new("/textures");
+        let material = load_material(
+            &[
+                "newmtl options",
+                "map_Kd -s 2 2 1 -o 0.5 -clamp on diffuse map.png",
+                "map_Bump -bm 0.25 -imfchan l normal.png",
+            ],
+            dir,
+        )
+        .unwrap();
+        assert_eq!(material.diffuse_texture_file, dir.join("diffuse map.png"));
+        assert_eq!(material.bump_map_file, dir.join("normal.png"));
+        assert_eq!(material.bump_multiplier, 0.25);
+
+        let material = load_material(&["newmtl plain", "norm normal.png"], dir).unwrap();
+        assert_eq!(material.bump_map_file, dir.join("normal.png"));
+        assert_eq!(material.bump_multiplier, 1.0);
+        assert_eq!((material.opacity, material.optical_density), (1.0, 1.0));
+
+        // `bump` names a height map, which can't be used as a normal map
+        let material = load_material(&["newmtl height", "bump height.png"], dir).unwrap();
+        assert_eq!(material.bump_map_file, Path::new(""));
+        assert!(load_material(&["newmtl missing", "map_Kd"], dir).is_err());
+    }
+}
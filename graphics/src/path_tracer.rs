@@ -0,0 +1,560 @@
+//! Offline CPU path tracer
+//!
+//! Renders a model's materials including the ray traced illumination modes the
+//! rasterizer can't, lit only by emissive materials & a constant background
+
+use std::{path::Path, sync::Mutex};
+
+use cgmath::*;
+use image::{DynamicImage, ImageResult, Rgb32FImage};
+use wgpu::AddressMode;
+
+use crate::{
+    bvh::{Bvh, Ray},
+    camera::Camera,
+    model::{Material, MaterialIllumination, Model},
+    texture::{linear_to_srgb, CpuTexture},
+};
+
+/// Settings for a path traced render
+#[derive(Debug, Clone)]
+pub struct PathTracerOptions {
+    pub width: u32,
+    pub height: u32,
+
+    /// Paths traced through each pixel
+    pub samples_per_pixel: u32,
+
+    /// Most surfaces a path can bounce off before it's cut short
+    pub max_depth: u32,
+
+    /// Linear radiance of rays that escape the model
+    pub background: [f32; 3],
+
+    /// Number of threads to render on, zero to use every available core
+    pub threads: usize,
+
+    /// Seed for the random numbers, the same seed always gives the same image
+    pub seed: u64,
+}
+
+impl Default for PathTracerOptions {
+    fn default() -> Self {
+        PathTracerOptions {
+            width: 640,
+            height: 480,
+            samples_per_pixel: 64,
+            max_depth: 8,
+            background: [1.0; 3],
+            threads: 0,
+            seed: 0,
+        }
+    }
+}
+
+/// How light reflects off the shiny part of a surface
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Specular {
+    None,
+    Glossy,
+    Mirror,
+}
+
+/// Material converted to what the path tracer needs
+struct Surface {
+    diffuse: Vector3<f32>,
+    diffuse_texture: Option<CpuTexture>,
+    specular: Vector3<f32>,
+    specular_exponent: f32,
+    emissive: Vector3<f32>,
+    transparency: f32,
+    optical_density: f32,
+    specular_mode: Specular,
+    refracts: bool,
+}
+
+impl Surface {
+    fn new(material: &Material) -> Surface {
+        use MaterialIllumination::*;
+
+        let (specular_mode, refracts) = match material.illumination_mode {
+            Some(ColorAmbientOff) | Some(ColorAmbientOn) => (Specular::None, false),
+            Some(ReflectionRayTrace) | Some(ReflectionFresnelRayTrace) | Some(Reflection) => {
+                (Specular::Mirror, false)
+            }
+            Some(TransparencyGlassRayTrace) | Some(TransparencyGlass) => (Specular::Mirror, false),
+            Some(TransparencyRefractionRayTrace) | Some(TransparencyFresnelRayTrace) => {
+                (Specular::Mirror, true)
+            }
+            Some(Highlight) | Some(CastShadows) | None => (Specular::Glossy, false),
+        };
+
+        let diffuse_texture = CpuTexture::load(
+            material.diffuse_texture_data.as_deref(),
+            &material.diffuse_texture_file,
+            true,
+        );
+
+        Surface {
+            diffuse: material.diffuse_color.into(),
+            diffuse_texture,
+            specular: material.specular_color.into(),
+            specular_exponent: material.specular_exponent.max(0.0),
+            emissive: material.emissive_color.into(),
+            transparency: (1.0 - material.opacity).clamp(0.0, 1.0),
+            optical_density: material.optical_density.max(f32::EPSILON),
+            specular_mode,
+            refracts,
+        }
+    }
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Surface {
+            diffuse: Vector3::new(0.8, 0.8, 0.8),
+            diffuse_texture: None,
+            specular: Vector3::zero(),
+            specular_exponent: 0.0,
+            emissive: Vector3::zero(),
+            transparency: 0.0,
+            optical_density: 1.0,
+            specular_mode: Specular::None,
+            refracts: false,
+        }
+    }
+}
+
+/// Path tracer holding a model's hierarchy & materials, ready to render views of it
+pub struct PathTracer<'a> {
+    model: &'a Model,
+    bvh: Bvh<'a>,
+    surfaces: Vec<Surface>,
+    default_surface: Surface,
+
+    /// Distance rays start off a surface to avoid hitting it again
+    offset: f32,
+}
+
+impl<'a> PathTracer<'a> {
+    pub fn new(model: &'a Model) -> PathTracer<'a> {
+        let bvh = Bvh::new(model);
+        let offset = bvh
+            .aabb()
+            .map_or(1e-4, |aabb| aabb.size().magnitude().max(1.0) * 1e-4);
+
+        PathTracer {
+            model,
+            bvh,
+            surfaces: model.materials.iter().map(Surface::new).collect(),
+            default_surface: Surface::default(),
+            offset,
+        }
+    }
+
+    /// Render the model from the camera, returning linear radiance
+    pub fn render(&self, camera: &Camera, options: &PathTracerOptions) -> Rgb32FImage {
+        let mut image = Rgb32FImage::new(options.width, options.height);
+        let threads = match options.threads {
+            0 => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            threads => threads,
+        };
+
+        // Threads take rows from a shared queue until there are none left
+        let row_length = options.width as usize * 3;
+        let rows = Mutex::new(image.chunks_mut(row_length.max(1)).enumerate());
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let next = rows.lock().unwrap().next();
+                    let (y, row) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    for (x, pixel) in row.chunks_exact_mut(3).enumerate() {
+                        let colour: [f32; 3] = self
+                            .render_pixel(camera, options, x as u32, y as u32)
+                            .into();
+                        pixel.copy_from_slice(&colour);
+                    }
+                });
+            }
+        });
+
+        image
+    }
+
+    fn render_pixel(
+        &self,
+        camera: &Camera,
+        options: &PathTracerOptions,
+        x: u32,
+        y: u32,
+    ) -> Vector3<f32> {
+        let pixel = y as u64 * options.width as u64 + x as u64;
+        let mut rng = Rng::new(options.seed ^ pixel.wrapping_mul(0x9e3779b97f4a7c15));
+
+        let samples = options.samples_per_pixel.max(1);
+        let mut total = Vector3::zero();
+        for _ in 0..samples {
+            let u = (x as f32 + rng.next()) / options.width as f32;
+            let v = (y as f32 + rng.next()) / options.height as f32;
+            let ray = camera.ray(u * 2.0 - 1.0, 1.0 - v * 2.0);
+            total += self.trace(ray, options, &mut rng);
+        }
+
+        total / samples as f32
+    }
+
+    /// Radiance arriving back along the ray
+    fn trace(&self, mut ray: Ray, options: &PathTracerOptions, rng: &mut Rng) -> Vector3<f32> {
+        let mut radiance = Vector3::zero();
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+
+        for depth in 0..options.max_depth {
+            let hit = match self.bvh.closest_hit(&ray, 0.0, f32::INFINITY) {
+                Some(hit) => hit,
+                None => {
+                    radiance += throughput.mul_element_wise(Vector3::from(options.background));
+                    break;
+                }
+            };
+
+            let surface = self.model.meshes[hit.mesh]
+                .material
+                .and_then(|material| self.surfaces.get(material))
+                .unwrap_or(&self.default_surface);
+            radiance += throughput.mul_element_wise(surface.emissive);
+
+            // Both normals face the side of the surface the ray came from
+            let direction = ray.direction.normalize();
+            let entering = direction.dot(hit.face_normal) < 0.0;
+            let face_normal = if entering {
+                hit.face_normal
+            } else {
+                -hit.face_normal
+            };
+            let mut normal = Vector3::from(hit.vertex.normal);
+            if normal.magnitude2() == 0.0 {
+                normal = face_normal;
+            } else if normal.dot(face_normal) < 0.0 {
+                normal = -normal;
+            }
+
+            let new_direction = if rng.next() < surface.transparency {
+                if surface.refracts {
+                    let eta = if entering {
+                        1.0 / surface.optical_density
+                    } else {
+                        surface.optical_density
+                    };
+                    refract(direction, normal, eta, rng)
+                } else {
+                    direction
+                }
+            } else {
+                let diffuse = match &surface.diffuse_texture {
+                    Some(texture) => {
+                        let [r, g, b, _] =
+                            texture.sample(hit.vertex.texture_coords, AddressMode::Repeat);
+                        surface.diffuse.mul_element_wise(Vector3::new(r, g, b))
+                    }
+                    None => surface.diffuse,
+                };
+                let specular = match surface.specular_mode {
+                    Specular::None => Vector3::zero(),
+                    _ => surface.specular,
+                };
+
+                // Pick one lobe in proportion to how much light it reflects
+                let diffuse_weight = luminance(diffuse);
+                let specular_weight = luminance(specular);
+                if diffuse_weight + specular_weight <= 0.0 {
+                    break;
+                }
+                let specular_chance = specular_weight / (diffuse_weight + specular_weight);
+
+                if rng.next() < specular_chance {
+                    throughput = throughput.mul_element_wise(specular) / specular_chance;
+                    let reflected = reflect(direction, normal);
+                    match surface.specular_mode {
+                        Specular::Mirror => reflected,
+                        _ => sample_phong(reflected, surface.specular_exponent, rng),
+                    }
+                } else {
+                    throughput = throughput.mul_element_wise(diffuse) / (1.0 - specular_chance);
+                    sample_cosine(normal, rng)
+                }
+            };
+
+            // Glossy lobes can dip below the surface they reflect off
+            let transmitted = new_direction.dot(face_normal) < 0.0;
+            if transmitted && new_direction != direction && !surface.refracts {
+                break;
+            }
+
+            let side = if transmitted {
+                -face_normal
+            } else {
+                face_normal
+            };
+            ray = Ray::new(ray.at(hit.t) + side * self.offset, new_direction);
+
+            // Randomly end dim paths, boosting the survivors to keep the average right
+            if depth >= 3 {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+                if rng.next() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        radiance
+    }
+}
+
+/// Save an image, linear for EXR files & sRGB encoded for anything else
+pub fn save_image(image: &Rgb32FImage, path: &Path) -> ImageResult<()> {
+    let is_exr = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+    if is_exr {
+        return DynamicImage::ImageRgb32F(image.clone()).save(path);
+    }
+
+    let mut encoded = image.clone();
+    for channel in encoded.iter_mut() {
+        *channel = linear_to_srgb(channel.clamp(0.0, 1.0));
+    }
+    DynamicImage::ImageRgb32F(encoded).to_rgb8().save(path)
+}
+
+fn luminance(colour: Vector3<f32>) -> f32 {
+    colour.dot(Vector3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn reflect(direction: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    direction - normal * 2.0 * direction.dot(normal)
+}
+
+/// Refract through a surface with relative index `eta`, or reflect as often as the
+/// Fresnel equations say, always reflecting past the critical angle
+fn refract(direction: Vector3<f32>, normal: Vector3<f32>, eta: f32, rng: &mut Rng) -> Vector3<f32> {
+    let cos_incident = -direction.dot(normal);
+    let sin2_transmitted = eta * eta * (1.0 - cos_incident * cos_incident);
+    if sin2_transmitted >= 1.0 {
+        return reflect(direction, normal);
+    }
+
+    // Schlick's approximation, using the angle on the denser side
+    let cos_transmitted = (1.0 - sin2_transmitted).sqrt();
+    let cos = if eta > 1.0 {
+        cos_transmitted
+    } else {
+        cos_incident
+    };
+    let r_0 = ((1.0 - eta) / (1.0 + eta)).powi(2);
+    let reflectance = r_0 + (1.0 - r_0) * (1.0 - cos).powi(5);
+    if rng.next() < reflectance {
+        return reflect(direction, normal);
+    }
+
+    direction * eta + normal * (eta * cos_incident - cos_transmitted)
+}
+
+/// Direction around `normal` with probability proportional to the cosine of the angle
+fn sample_cosine(normal: Vector3<f32>, rng: &mut Rng) -> Vector3<f32> {
+    let (r, phi) = (rng.next().sqrt(), rng.next() * std::f32::consts::TAU);
+    let z = (1.0 - r * r).max(0.0).sqrt();
+    to_world(Vector3::new(r * phi.cos(), r * phi.sin(), z), normal)
+}
+
+/// Direction around `axis` with probability proportional to the Phong lobe
+fn sample_phong(axis: Vector3<f32>, exponent: f32, rng: &mut Rng) -> Vector3<f32> {
+    let cos_theta = rng.next().powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = rng.next() * std::f32::consts::TAU;
+    to_world(
+        Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+        axis,
+    )
+}
+
+/// Rotate a direction around +z to the same place around `axis`
+fn to_world(local: Vector3<f32>, axis: Vector3<f32>) -> Vector3<f32> {
+    let helper = if axis.x.abs() > 0.9 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let tangent = axis.cross(helper).normalize();
+    let bitangent = axis.cross(tangent);
+    tangent * local.x + bitangent * local.y + axis * local.z
+}
+
+/// Small, fast random number generator (SplitMix64)
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    /// Uniform number in [0, 1)
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use crate::{
+        camera::Camera,
+        model::{Material, MaterialIllumination, Model},
+        test_util::{load_model, output_dir},
+    };
+
+    use super::*;
+
+    fn sphere_with(material: Material) -> Model {
+        let mut model = load_model("sphere.obj");
+        model.meshes.truncate(1);
+        model.meshes[0].material = Some(0);
+        model.materials = vec![material];
+        model
+    }
+
+    fn test_camera() -> Camera {
+        Camera::new(
+            (0.0, 0.0, 4.0).into(),
+            (0.0, 0.0, 0.0).into(),
+            Vector3::unit_y(),
+            1.0,
+            45.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    fn options() -> PathTracerOptions {
+        PathTracerOptions {
+            width: 16,
+            height: 16,
+            samples_per_pixel: 8,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn emissive_sphere() {
+        let model = sphere_with(Material {
+            emissive_color: [0.5, 1.0, 2.0],
+            illumination_mode: Some(MaterialIllumination::ColorAmbientOff),
+            ..Default::default()
+        });
+        let options = PathTracerOptions {
+            background: [0.0; 3],
+            ..options()
+        };
+
+        let image = PathTracer::new(&model).render(&test_camera(), &options);
+        assert_eq!(image.dimensions(), (16, 16));
+        assert_eq!(image.get_pixel(8, 8).0, [0.5, 1.0, 2.0]);
+        assert_eq!(image.get_pixel(0, 0).0, [0.0; 3]);
+    }
+
+    #[test]
+    pub fn diffuse_sphere_is_darker_than_background() {
+        let model = sphere_with(Material {
+            diffuse_color: [0.5; 3],
+            illumination_mode: Some(MaterialIllumination::ColorAmbientOn),
+            ..Default::default()
+        });
+
+        let image = PathTracer::new(&model).render(&test_camera(), &options());
+        let center = image.get_pixel(8, 8).0;
+        assert!(center
+            .iter()
+            .all(|channel| *channel > 0.2 && *channel < 0.8));
+        assert_eq!(image.get_pixel(0, 0).0, [1.0; 3]);
+    }
+
+    #[test]
+    pub fn clear_glass_keeps_energy() {
+        let model = sphere_with(Material {
+            opacity: 0.0,
+            optical_density: 1.5,
+            illumination_mode: Some(MaterialIllumination::TransparencyRefractionRayTrace),
+            ..Default::default()
+        });
+        let options = PathTracerOptions {
+            max_depth: 32,
+            ..options()
+        };
+
+        // Every path through clear glass eventually escapes to the background
+        let image = PathTracer::new(&model).render(&test_camera(), &options);
+        for channel in image.get_pixel(8, 8).0 {
+            assert_abs_diff_eq!(channel, 1.0, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    pub fn same_image_on_any_number_of_threads() {
+        let model = load_model("cube.obj");
+        let tracer = PathTracer::new(&model);
+        let camera = Camera::new(
+            (3.0, 2.0, 4.0).into(),
+            (0.0, 0.0, 0.0).into(),
+            Vector3::unit_y(),
+            1.0,
+            45.0,
+            0.1,
+            100.0,
+        );
+
+        let single = tracer.render(
+            &camera,
+            &PathTracerOptions {
+                threads: 1,
+                ..options()
+            },
+        );
+        let multiple = tracer.render(
+            &camera,
+            &PathTracerOptions {
+                threads: 4,
+                ..options()
+            },
+        );
+        assert_eq!(single, multiple);
+    }
+
+    #[test]
+    pub fn saves_png_and_exr() {
+        let dir = output_dir("path-tracer");
+
+        let mut image = Rgb32FImage::new(4, 2);
+        image.put_pixel(1, 1, image::Rgb([0.5, 2.0, 0.0]));
+
+        let exr = dir.join("image.exr");
+        save_image(&image, &exr).unwrap();
+        let loaded = image::open(&exr).unwrap().to_rgb32f();
+        assert_eq!(loaded.get_pixel(1, 1).0, [0.5, 2.0, 0.0]);
+
+        let png = dir.join("image.png");
+        save_image(&image, &png).unwrap();
+        let loaded = image::open(&png).unwrap().to_rgb8();
+        assert_eq!(loaded.dimensions(), (4, 2));
+        assert_eq!(loaded.get_pixel(1, 1).0, [188, 255, 0]);
+    }
+}
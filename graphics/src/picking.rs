@@ -0,0 +1,193 @@
+//! Finding what's under the cursor
+//!
+//! Models are hit-tested along the ray through the cursor with a hierarchy built once per
+//! model, each in its own space so the scene's transforms needn't be applied to every
+//! vertex. Points such as curve control points are picked on screen, within a radius in
+//! pixels
+
+use cgmath::*;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    bvh::{Ray, TriangleBvh},
+    camera::Camera,
+    scene::SceneGraph,
+};
+
+/// Triangle of a model hit by a picking ray
+#[derive(Debug, Copy, Clone)]
+pub struct ModelPick {
+    /// Scene node drawing the model
+    pub node: usize,
+    pub model: usize,
+
+    /// Index of the mesh in the model & the triangle in the mesh
+    pub mesh: usize,
+    pub triangle: usize,
+
+    /// Ray parameter of the hit, in lengths of the ray direction
+    pub t: f32,
+
+    /// Hit point in world space
+    pub position: Point3<f32>,
+}
+
+/// Control point of a curve picked on screen
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ControlPointPick {
+    pub curve: usize,
+
+    /// Index of the point in the curve's `control_points`
+    pub point: usize,
+}
+
+/// Nearest triangle hit by the ray of any model drawn by the scene's nodes, given the
+/// hierarchy of each model
+pub fn pick_model(scene: &SceneGraph, hierarchies: &[TriangleBvh], ray: &Ray) -> Option<ModelPick> {
+    let mut closest: Option<ModelPick> = None;
+
+    for (node, world) in scene.world_matrices().into_iter().enumerate() {
+        let model = match scene.node(node).model {
+            Some(model) if model < hierarchies.len() => model,
+            _ => continue,
+        };
+        let inverse = match world.invert() {
+            Some(inverse) => inverse,
+            None => continue,
+        };
+
+        // Without normalizing the direction, t is the same in model & world space
+        let local = Ray::new(
+            Point3::from_homogeneous(inverse * ray.origin.to_homogeneous()),
+            (inverse * ray.direction.extend(0.0)).truncate(),
+        );
+        let t_max = closest.map_or(f32::INFINITY, |pick| pick.t);
+        if let Some(hit) = hierarchies[model].closest_hit(&local, 0.0, t_max) {
+            closest = Some(ModelPick {
+                node,
+                model,
+                mesh: hit.mesh,
+                triangle: hit.triangle,
+                t: hit.t,
+                position: ray.at(hit.t),
+            });
+        }
+    }
+
+    closest
+}
+
+/// Index of the point nearest a pixel on screen, if it's within `radius` pixels
+pub fn pick_point(
+    points: &[Point3<f32>],
+    camera: &Camera,
+    pixel: Point2<f32>,
+    viewport: PhysicalSize<u32>,
+    radius: f32,
+) -> Option<usize> {
+    points
+        .iter()
+        .enumerate()
+        .filter_map(|(i, point)| {
+            let distance = camera.world_to_screen(*point, viewport)?.distance(pixel);
+            (distance <= radius).then_some((i, distance))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+    use winit::dpi::PhysicalSize;
+
+    use crate::{
+        bvh::{Ray, TriangleBvh},
+        camera::Camera,
+        model::{Mesh, Model, ModelVertex},
+        scene::SceneGraph,
+        transform::Transform,
+    };
+
+    use super::{pick_model, pick_point};
+
+    /// Two triangle square in the xy plane from -1 to 1, facing +z
+    fn square() -> Model {
+        let vertex = |x: f32, y: f32| ModelVertex::new([x, y, 0.0], [0.0; 2], [0.0, 0.0, 1.0]);
+        Model {
+            meshes: vec![Mesh {
+                name: "square".to_string(),
+                vertices: vec![
+                    vertex(-1.0, -1.0),
+                    vertex(1.0, -1.0),
+                    vertex(1.0, 1.0),
+                    vertex(-1.0, 1.0),
+                ],
+                indices: vec![0, 1, 2, 0, 2, 3],
+                face_sizes: vec![],
+                material: None,
+            }],
+            materials: vec![],
+        }
+    }
+
+    #[test]
+    pub fn nearest_model_triangle() {
+        let hierarchies = [TriangleBvh::new(&square())];
+        let mut scene = SceneGraph::new();
+        let back = scene.add_node("back", Transform::new(), None);
+        scene.node_mut(back).model = Some(0);
+        let front = scene.add_node(
+            "front",
+            Transform {
+                translate: [3.0, 0.0, 2.0],
+                scale: [2.0, 2.0, 2.0],
+                ..Transform::new()
+            },
+            None,
+        );
+        scene.node_mut(front).model = Some(0);
+
+        // Through the overlap, the scaled square in front is hit first
+        let ray = Ray::new(Point3::new(1.5, 0.5, 10.0), -Vector3::unit_z());
+        let pick = pick_model(&scene, &hierarchies, &ray).unwrap();
+        assert_eq!((pick.node, pick.model, pick.mesh), (front, 0, 0));
+        assert_eq!(pick.triangle, 1);
+        assert_abs_diff_eq!(pick.t, 8.0, epsilon = 1e-5);
+        assert_abs_diff_eq!(pick.position, Point3::new(1.5, 0.5, 2.0), epsilon = 1e-5);
+
+        // Only the back square is under this point, in its first triangle
+        let ray = Ray::new(Point3::new(0.5, -0.9, 10.0), -Vector3::unit_z());
+        let pick = pick_model(&scene, &hierarchies, &ray).unwrap();
+        assert_eq!((pick.node, pick.triangle), (back, 0));
+
+        let ray = Ray::new(Point3::new(0.0, 5.0, 10.0), -Vector3::unit_z());
+        assert!(pick_model(&scene, &hierarchies, &ray).is_none());
+    }
+
+    #[test]
+    pub fn nearest_point_on_screen() {
+        let camera = Camera::orthographic(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::origin(),
+            Vector3::unit_y(),
+            1.0,
+            2.0,
+            0.1,
+            100.0,
+        );
+        let viewport = PhysicalSize::new(100, 100);
+        let points = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.1, 0.0, 0.0),
+            Point3::new(-1.0, 1.0, 0.0),
+        ];
+
+        // Points are 50 pixels per unit apart, the top left one is at the corner
+        let pick = |x, y| pick_point(&points, &camera, Point2::new(x, y), viewport, 4.0);
+        assert_eq!(pick(56.0, 50.0), Some(1));
+        assert_eq!(pick(51.0, 51.0), Some(0));
+        assert_eq!(pick(2.0, 2.0), Some(2));
+        assert_eq!(pick(80.0, 80.0), None);
+    }
+}
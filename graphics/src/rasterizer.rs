@@ -0,0 +1,879 @@
+//! Software rasterizer
+//!
+//! Draws the same models as `Render3D` & curves as `Render2D` on the CPU, so render
+//! output can be checked without a GPU. Like the GPU pipeline it shades each fragment
+//! with the scene's lights, or shows the diffuse texture unlit if there are none,
+//! culling clockwise faces & keeping the nearest fragment with a depth buffer
+
+use std::time::Duration;
+
+use cgmath::*;
+use image::RgbaImage;
+use wgpu::{AddressMode, SurfaceError};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    animation::Animation,
+    bvh::TriangleBvh,
+    camera::{Camera, CameraController},
+    camera_path::CameraPath,
+    curve::{Curve, CurveVertex},
+    light::{perturb_normal, Lighting},
+    model::{Material, Model},
+    picking::{self, ModelPick},
+    render::{ControlEvent, Renderer},
+    scene::SceneGraph,
+    texture::{linear_to_srgb, CpuTexture},
+    transform::{normal_matrix, Transform},
+};
+
+/// Model with its textures decoded for sampling
+struct SoftwareModel {
+    model: Model,
+    textures: Vec<Option<CpuTexture>>,
+    normal_maps: Vec<Option<CpuTexture>>,
+}
+
+/// Vertex after the vertex stage, in clip space
+#[derive(Debug, Copy, Clone)]
+struct ClipVertex {
+    position: Vector4<f32>,
+    texture_coords: Vector2<f32>,
+    world_position: Vector3<f32>,
+    normal: Vector3<f32>,
+    tangent: Vector4<f32>,
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            position: self.position.lerp(other.position, t),
+            texture_coords: self.texture_coords.lerp(other.texture_coords, t),
+            world_position: self.world_position.lerp(other.world_position, t),
+            normal: self.normal.lerp(other.normal, t),
+            tangent: self.tangent.lerp(other.tangent, t),
+        }
+    }
+}
+
+/// Vertex in screen space, with attributes divided by w for perspective correction
+#[derive(Debug, Copy, Clone)]
+struct ScreenVertex {
+    position: Point2<f32>,
+    depth: f32,
+    inverse_w: f32,
+    texture_coords: Vector2<f32>,
+    world_position: Vector3<f32>,
+    normal: Vector3<f32>,
+    tangent: Vector4<f32>,
+}
+
+/// Interpolated attributes of a pixel being shaded
+struct Fragment {
+    texture_coords: [f32; 2],
+    position: Point3<f32>,
+    normal: Vector3<f32>,
+    tangent: Vector4<f32>,
+}
+
+/// Renderer drawing into an RGBA image in memory
+pub struct SoftwareRenderer<'a> {
+    size: PhysicalSize<u32>,
+    camera_controller: CameraController<'a>,
+    camera: Camera,
+    scene: SceneGraph,
+    animations: Vec<Animation>,
+    camera_path: Option<CameraPath>,
+    models: Vec<SoftwareModel>,
+
+    /// Hierarchy over each model's triangles for picking
+    hierarchies: Vec<TriangleBvh>,
+    curves: Vec<Vec<CurveVertex>>,
+    lighting: Lighting,
+    back_face_culling: bool,
+    colour: RgbaImage,
+    depth: Vec<f32>,
+}
+
+impl<'a> SoftwareRenderer<'a> {
+    pub fn new(
+        size: PhysicalSize<u32>,
+        camera: Camera,
+        mut camera_controller: CameraController<'a>,
+    ) -> SoftwareRenderer<'a> {
+        camera_controller.set_viewport(size);
+        SoftwareRenderer {
+            size,
+            camera_controller,
+            camera,
+            scene: SceneGraph::new(),
+            animations: vec![],
+            camera_path: None,
+            models: vec![],
+            hierarchies: vec![],
+            curves: vec![],
+            lighting: Lighting::default(),
+            back_face_culling: true,
+            colour: RgbaImage::new(size.width, size.height),
+            depth: vec![1.0; size.width as usize * size.height as usize],
+        }
+    }
+
+    /// Load a model into a new root node of the scene
+    ///
+    /// Returns the node, whose transform places the model
+    pub fn add_model(&mut self, model: Model) -> usize {
+        let textures = model
+            .materials
+            .iter()
+            .map(|material| {
+                CpuTexture::load(
+                    material.diffuse_texture_data.as_deref(),
+                    &material.diffuse_texture_file,
+                    true,
+                )
+            })
+            .collect();
+        let normal_maps = model
+            .materials
+            .iter()
+            .map(|material| {
+                CpuTexture::load(
+                    material.bump_map_data.as_deref(),
+                    &material.bump_map_file,
+                    false,
+                )
+            })
+            .collect();
+        let node = self.scene.add_node("model", Transform::new(), None);
+        self.scene.node_mut(node).model = Some(self.models.len());
+        self.hierarchies.push(TriangleBvh::new(&model));
+        self.models.push(SoftwareModel {
+            model,
+            textures,
+            normal_maps,
+        });
+
+        node
+    }
+
+    /// Move the camera to show all of a node's model where the scene places it, keeping
+    /// the view direction
+    pub fn frame_model(&mut self, node: usize) {
+        let sphere = self
+            .scene
+            .node(node)
+            .model
+            .and_then(|model| self.models[model].model.bounding_sphere());
+        if let Some(sphere) = sphere {
+            self.camera
+                .frame(&sphere.transform(self.scene.world_matrix(node)));
+        }
+    }
+
+    /// Add a curve to the scene, drawn as white lines like `Render2D`
+    pub fn add_curve<T: Curve>(&mut self, curve: T) {
+        self.curves.push(curve.to_vertices(0.0..1.0, 50));
+    }
+
+    /// Lights of the scene, with none the models are drawn unlit
+    pub fn lighting(&self) -> &Lighting {
+        &self.lighting
+    }
+
+    pub fn lighting_mut(&mut self) -> &mut Lighting {
+        &mut self.lighting
+    }
+
+    /// Nodes placing the models
+    pub fn scene(&self) -> &SceneGraph {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut SceneGraph {
+        &mut self.scene
+    }
+
+    /// Play an animation of the scene's nodes & camera, advanced on every update
+    pub fn add_animation(&mut self, animation: Animation) {
+        self.animations.push(animation);
+    }
+
+    pub fn animations_mut(&mut self) -> &mut [Animation] {
+        &mut self.animations
+    }
+
+    /// Fly the camera along a path on every update, or stop with None
+    pub fn set_camera_path(&mut self, camera_path: Option<CameraPath>) {
+        self.camera_path = camera_path;
+    }
+
+    pub fn camera_path_mut(&mut self) -> Option<&mut CameraPath> {
+        self.camera_path.as_mut()
+    }
+
+    /// Whether clockwise faces are skipped, on by default like the GPU pipeline
+    pub fn set_back_face_culling(&mut self, back_face_culling: bool) {
+        self.back_face_culling = back_face_culling;
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Nearest triangle of the models under a pixel, from the top left of the image
+    pub fn pick(&self, pixel: Point2<f32>) -> Option<ModelPick> {
+        let ray = self.camera.pixel_ray(pixel, self.size);
+        picking::pick_model(&self.scene, &self.hierarchies, &ray)
+    }
+
+    /// Colour output of the last render, sRGB encoded
+    pub fn image(&self) -> &RgbaImage {
+        &self.colour
+    }
+
+    /// Depth output of the last render, row by row from 0 (near) to 1 (far)
+    pub fn depth(&self) -> &[f32] {
+        &self.depth
+    }
+
+    fn clear(&mut self) {
+        self.colour =
+            RgbaImage::from_pixel(self.size.width, self.size.height, [0, 0, 0, 255].into());
+        self.depth = vec![1.0; self.size.width as usize * self.size.height as usize];
+    }
+
+    fn draw_models(&mut self) {
+        let view_projection = self.camera.build_view_projection_matrix();
+        let eye = self.camera.eye();
+
+        // Meshes without a material are white, like the GPU pipeline's default material
+        let default_material = Material {
+            diffuse_color: [1.0; 3],
+            ..Default::default()
+        };
+
+        let models = std::mem::take(&mut self.models);
+        let lighting = std::mem::take(&mut self.lighting);
+        for (model, model_matrix) in self.scene.draws() {
+            let model = &models[model];
+            let matrix = view_projection * model_matrix;
+            let normal_matrix = normal_matrix(model_matrix);
+            for mesh in &model.model.meshes {
+                let material = mesh
+                    .material
+                    .and_then(|material| model.model.materials.get(material))
+                    .unwrap_or(&default_material);
+                let texture = mesh
+                    .material
+                    .and_then(|material| model.textures.get(material))
+                    .and_then(Option::as_ref);
+                let normal_map = mesh
+                    .material
+                    .and_then(|material| model.normal_maps.get(material))
+                    .and_then(Option::as_ref);
+
+                let vertices: Vec<ClipVertex> = mesh
+                    .vertices
+                    .iter()
+                    .map(|vertex| {
+                        let world_position =
+                            model_matrix * Point3::from(vertex.position).to_homogeneous();
+                        let direction = |vector: [f32; 3]| {
+                            (model_matrix * Vector3::from(vector).extend(0.0)).truncate()
+                        };
+                        let [x, y, z, sign] = vertex.tangent;
+                        ClipVertex {
+                            position: matrix * Point3::from(vertex.position).to_homogeneous(),
+                            texture_coords: vertex.texture_coords.into(),
+                            world_position: world_position.truncate(),
+                            normal: normal_matrix * Vector3::from(vertex.normal),
+                            tangent: direction([x, y, z]).extend(sign),
+                        }
+                    })
+                    .collect();
+
+                let shade = |fragment: &Fragment| {
+                    let sample = texture.map_or([1.0; 4], |texture| {
+                        texture.sample(fragment.texture_coords, AddressMode::ClampToEdge)
+                    });
+                    if lighting.lights.is_empty() {
+                        let [red, green, blue] = material.diffuse_color;
+                        return texture.map_or([red, green, blue, 1.0], |_| sample);
+                    }
+
+                    let normal = match normal_map {
+                        Some(normal_map) => {
+                            let [x, y, z, _] = normal_map
+                                .sample(fragment.texture_coords, AddressMode::ClampToEdge);
+                            perturb_normal(material, fragment.normal, fragment.tangent, [x, y, z])
+                        }
+                        None => fragment.normal,
+                    };
+                    let [red, green, blue] = lighting.shade(
+                        material,
+                        [sample[0], sample[1], sample[2]],
+                        fragment.position,
+                        normal,
+                        eye,
+                    );
+                    [red, green, blue, sample[3]]
+                };
+
+                for triangle in mesh.indices.chunks_exact(3) {
+                    let corners = [0, 1, 2].map(|i| vertices.get(triangle[i] as usize).copied());
+                    if let [Some(a), Some(b), Some(c)] = corners {
+                        self.draw_triangle([a, b, c], &shade);
+                    }
+                }
+            }
+        }
+        self.models = models;
+        self.lighting = lighting;
+    }
+
+    fn draw_curves(&mut self) {
+        let matrix = self.camera.build_view_projection_matrix();
+
+        let curves = std::mem::take(&mut self.curves);
+        for curve in &curves {
+            for line in curve.windows(2) {
+                let [a, b] = [line[0], line[1]]
+                    .map(|vertex| matrix * Point3::from(vertex.position).to_homogeneous());
+                self.draw_line(a, b);
+            }
+        }
+        self.curves = curves;
+    }
+
+    /// Clip a line to the near & far planes, then step along it a pixel at a time
+    fn draw_line(&mut self, a: Vector4<f32>, b: Vector4<f32>) {
+        let (mut start, mut end) = (0.0f32, 1.0f32);
+        for distance in [|v: Vector4<f32>| v.z, |v: Vector4<f32>| v.w - v.z] {
+            let (a_distance, b_distance) = (distance(a), distance(b));
+            if a_distance < 0.0 && b_distance < 0.0 {
+                return;
+            }
+            if a_distance < 0.0 {
+                start = start.max(a_distance / (a_distance - b_distance));
+            } else if b_distance < 0.0 {
+                end = end.min(a_distance / (a_distance - b_distance));
+            }
+        }
+        if start > end {
+            return;
+        }
+
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        let reversed_depth = self.camera.projection().reversed_depth();
+        let [a, b] = [a.lerp(b, start), a.lerp(b, end)].map(|vertex| {
+            let ndc = vertex.truncate() / vertex.w;
+            Vector3::new(
+                (ndc.x + 1.0) / 2.0 * width,
+                (1.0 - ndc.y) / 2.0 * height,
+                depth(ndc.z, reversed_depth),
+            )
+        });
+
+        let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.0) as u32;
+        for step in 0..=steps {
+            let point = a.lerp(b, step as f32 / steps as f32);
+            if point.x < 0.0 || point.y < 0.0 || point.x >= width || point.y >= height {
+                continue;
+            }
+
+            let (x, y) = (point.x as u32, point.y as u32);
+            let index = y as usize * self.size.width as usize + x as usize;
+            if point.z < self.depth[index] {
+                self.colour.put_pixel(x, y, [255; 4].into());
+                self.depth[index] = point.z;
+            }
+        }
+    }
+
+    /// Clip, cull & fill a triangle, shading each fragment
+    fn draw_triangle(&mut self, triangle: [ClipVertex; 3], shade: &impl Fn(&Fragment) -> [f32; 4]) {
+        let polygon = clip_depth(&triangle);
+        if polygon.len() < 3 {
+            return;
+        }
+
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        let reversed_depth = self.camera.projection().reversed_depth();
+        let screen: Vec<ScreenVertex> = polygon
+            .iter()
+            .map(|vertex| {
+                let inverse_w = 1.0 / vertex.position.w;
+                let ndc = vertex.position.truncate() * inverse_w;
+                ScreenVertex {
+                    position: Point2::new(
+                        (ndc.x + 1.0) / 2.0 * width,
+                        (1.0 - ndc.y) / 2.0 * height,
+                    ),
+                    depth: depth(ndc.z, reversed_depth),
+                    inverse_w,
+                    texture_coords: vertex.texture_coords * inverse_w,
+                    world_position: vertex.world_position * inverse_w,
+                    normal: vertex.normal * inverse_w,
+                    tangent: vertex.tangent * inverse_w,
+                }
+            })
+            .collect();
+
+        for i in 1..screen.len() - 1 {
+            self.fill([screen[0], screen[i], screen[i + 1]], shade);
+        }
+    }
+
+    fn fill(&mut self, [a, b, c]: [ScreenVertex; 3], shade: &impl Fn(&Fragment) -> [f32; 4]) {
+        // Screen space has y down, so counter-clockwise front faces have negative area
+        let area = edge(a.position, b.position, c.position);
+        if area == 0.0 || (self.back_face_culling && area > 0.0) {
+            return;
+        }
+
+        // Pixels whose centres could be inside the triangle
+        let xs = [a.position.x, b.position.x, c.position.x];
+        let ys = [a.position.y, b.position.y, c.position.y];
+        let min_x = xs
+            .into_iter()
+            .fold(f32::INFINITY, f32::min)
+            .floor()
+            .max(0.0) as u32;
+        let min_y = ys
+            .into_iter()
+            .fold(f32::INFINITY, f32::min)
+            .floor()
+            .max(0.0) as u32;
+        let max_x = (xs.into_iter().fold(0.0, f32::max).ceil() as u32).min(self.size.width);
+        let max_y = (ys.into_iter().fold(0.0, f32::max).ceil() as u32).min(self.size.height);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let point = Point2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let weights = [
+                    edge(b.position, c.position, point) / area,
+                    edge(c.position, a.position, point) / area,
+                    edge(a.position, b.position, point) / area,
+                ];
+                if weights.iter().any(|weight| *weight < 0.0) {
+                    continue;
+                }
+
+                // Depth is linear in screen space, attributes need the perspective divide
+                let depth = a.depth * weights[0] + b.depth * weights[1] + c.depth * weights[2];
+                let index = y as usize * self.size.width as usize + x as usize;
+                if !(0.0..=1.0).contains(&depth) || depth >= self.depth[index] {
+                    continue;
+                }
+
+                let inverse_w =
+                    a.inverse_w * weights[0] + b.inverse_w * weights[1] + c.inverse_w * weights[2];
+                let interpolate = |a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>| {
+                    (a * weights[0] + b * weights[1] + c * weights[2]) / inverse_w
+                };
+                let texture_coords = interpolate(
+                    a.texture_coords.extend(0.0),
+                    b.texture_coords.extend(0.0),
+                    c.texture_coords.extend(0.0),
+                );
+                let fragment = Fragment {
+                    texture_coords: texture_coords.truncate().into(),
+                    position: Point3::from_vec(interpolate(
+                        a.world_position,
+                        b.world_position,
+                        c.world_position,
+                    )),
+                    normal: interpolate(a.normal, b.normal, c.normal),
+                    tangent: (a.tangent * weights[0]
+                        + b.tangent * weights[1]
+                        + c.tangent * weights[2])
+                        / inverse_w,
+                };
+
+                let [red, green, blue, alpha] = shade(&fragment);
+                let encode = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+                self.colour.put_pixel(
+                    x,
+                    y,
+                    [
+                        encode(linear_to_srgb(red)),
+                        encode(linear_to_srgb(green)),
+                        encode(linear_to_srgb(blue)),
+                        encode(alpha),
+                    ]
+                    .into(),
+                );
+                self.depth[index] = depth;
+            }
+        }
+    }
+}
+
+impl<'a> Renderer for SoftwareRenderer<'a> {
+    /// Recreate the buffers with the current size
+    fn recreate(&mut self) {
+        self.resize(self.size);
+    }
+
+    /// Resize the buffers to new_size
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.camera
+                .set_aspect(new_size.width as f32 / new_size.height as f32);
+            self.camera_controller.set_viewport(new_size);
+            self.clear();
+        }
+    }
+
+    /// Process control input, returning true if the event was consumed
+    fn input(&mut self, event: &ControlEvent) -> bool {
+        self.camera_controller.process_events(event)
+    }
+
+    fn update(&mut self, delta: Duration) {
+        self.camera_controller
+            .update_camera(&mut self.camera, delta);
+        for animation in self.animations.iter_mut() {
+            animation.update(delta, &mut self.scene, &mut self.camera);
+        }
+        if let Some(camera_path) = self.camera_path.as_mut() {
+            camera_path.update(delta, &mut self.camera);
+        }
+    }
+
+    fn render(&mut self) -> Result<(), SurfaceError> {
+        self.clear();
+        self.draw_models();
+        self.draw_curves();
+
+        Ok(())
+    }
+}
+
+/// Twice the signed area of the triangle, positive when clockwise on screen
+fn edge(a: Point2<f32>, b: Point2<f32>, point: Point2<f32>) -> f32 {
+    (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+}
+
+/// Depth buffer value of a normalized device depth, from 0 near to 1 far even when the
+/// projection reverses depth
+fn depth(ndc_z: f32, reversed_depth: bool) -> f32 {
+    if reversed_depth {
+        1.0 - ndc_z
+    } else {
+        ndc_z
+    }
+}
+
+/// Clip a triangle to the near & far planes, `0 <= z <= w` in wgpu clip space
+fn clip_depth(triangle: &[ClipVertex; 3]) -> Vec<ClipVertex> {
+    let near = |vertex: &ClipVertex| vertex.position.z;
+    let far = |vertex: &ClipVertex| vertex.position.w - vertex.position.z;
+
+    let polygon = clip_plane(triangle.to_vec(), near);
+    clip_plane(polygon, far)
+}
+
+/// Sutherland–Hodgman clipping, keeping the part of the polygon where `distance >= 0`
+fn clip_plane(polygon: Vec<ClipVertex>, distance: impl Fn(&ClipVertex) -> f32) -> Vec<ClipVertex> {
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, current) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        let (current_distance, next_distance) = (distance(current), distance(next));
+
+        if current_distance >= 0.0 {
+            clipped.push(*current);
+        }
+        if (current_distance >= 0.0) != (next_distance >= 0.0) {
+            let t = current_distance / (current_distance - next_distance);
+            clipped.push(current.lerp(next, t));
+        }
+    }
+
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use cgmath::*;
+    use winit::dpi::PhysicalSize;
+
+    use crate::{
+        camera::{Camera, CameraController, MoveMode, Projection},
+        light::Light,
+        model::{Material, Mesh, Model, ModelVertex},
+        render::Renderer,
+        test_util::load_model,
+        transform::Transform,
+    };
+
+    use super::SoftwareRenderer;
+
+    static MOVE_MODE: MoveMode = MoveMode::new();
+
+    /// Square renderer `size` pixels across, with a camera that stays put
+    fn renderer(size: u32, camera: Camera) -> SoftwareRenderer<'static> {
+        SoftwareRenderer::new(
+            PhysicalSize::new(size, size),
+            camera,
+            CameraController::new(0.0, &MOVE_MODE),
+        )
+    }
+
+    fn test_camera(eye: Point3<f32>) -> Camera {
+        Camera::new(
+            eye,
+            Point3::origin(),
+            Vector3::unit_y(),
+            1.0,
+            90.0,
+            0.1,
+            100.0,
+        )
+    }
+
+    /// Square in the xy plane facing +z, stretching from -size to size
+    fn square(z: f32, size: f32, colour: [f32; 3]) -> Model {
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+        Model {
+            meshes: vec![Mesh {
+                vertices: corners
+                    .iter()
+                    .map(|[x, y]| {
+                        ModelVertex::new(
+                            [x * size, y * size, z],
+                            [(x + 1.0) / 2.0, (1.0 - y) / 2.0],
+                            [0.0, 0.0, 1.0],
+                        )
+                    })
+                    .collect(),
+                indices: vec![0, 1, 2, 0, 2, 3],
+                material: Some(0),
+                ..Default::default()
+            }],
+            materials: vec![Material {
+                diffuse_color: colour,
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    pub fn textured_cube() {
+        let mut renderer = renderer(32, test_camera((0.0, 0.0, 3.0).into()));
+        renderer.add_model(load_model("cube.obj"));
+        renderer.render().unwrap();
+
+        // The middle of the front face shows the middle of its texture
+        let model = load_model("cube.obj");
+        let texture = image::open(&model.materials[0].diffuse_texture_file)
+            .unwrap()
+            .to_rgba8();
+        let image = renderer.image();
+        let centre = image.get_pixel(16, 16).0;
+        assert_ne!(centre, [0, 0, 0, 255]);
+        assert!(texture.pixels().any(|texel| texel
+            .0
+            .iter()
+            .zip(centre)
+            .all(|(a, b)| (*a as i32 - b as i32).abs() <= 2)));
+
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert!(renderer.depth()[16 * 32 + 16] < 1.0);
+        assert_eq!(renderer.depth()[0], 1.0);
+    }
+
+    #[test]
+    pub fn nearest_surface_wins() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, 5.0).into()));
+        // Drawn far to near then near to far, the near square covers the middle either way
+        renderer.add_model(square(-1.0, 10.0, [1.0, 0.0, 0.0]));
+        renderer.add_model(square(1.0, 1.0, [0.0, 1.0, 0.0]));
+        renderer.add_model(square(-1.0, 10.0, [1.0, 0.0, 0.0]));
+        renderer.render().unwrap();
+
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [0, 255, 0, 255]);
+        assert_eq!(renderer.image().get_pixel(1, 1).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    pub fn reversed_depth() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, 5.0).into()));
+        renderer.add_model(square(1.0, 1.0, [0.0, 1.0, 0.0]));
+        renderer.add_model(square(-1.0, 10.0, [1.0, 0.0, 0.0]));
+        let mut camera = test_camera((0.0, 0.0, 5.0).into());
+        camera.set_projection(Projection::InfinitePerspective { fovy: 90.0 });
+        *renderer.camera_mut() = camera;
+        renderer.render().unwrap();
+
+        // The depth buffer still runs from near to far, so the same surface wins
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [0, 255, 0, 255]);
+        assert_eq!(renderer.image().get_pixel(1, 1).0, [255, 0, 0, 255]);
+        assert!(renderer.depth()[8 * 16 + 8] < renderer.depth()[16 + 1]);
+    }
+
+    #[test]
+    pub fn resize_keeps_the_aspect() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, 5.0).into()));
+        renderer.add_model(square(0.0, 1.0, [0.0, 1.0, 0.0]));
+        renderer.resize(PhysicalSize::new(32, 16));
+        renderer.render().unwrap();
+
+        // The view widens rather than stretching, so the square stays square
+        assert_abs_diff_eq!(renderer.camera().aspect(), 2.0);
+        let image = renderer.image();
+        let green = |x, y| image.get_pixel(x, y).0 == [0, 255, 0, 255];
+        let width = (0..32).filter(|x| green(*x, 8)).count();
+        let height = (0..16).filter(|y| green(16, *y)).count();
+        assert_eq!(width, height);
+    }
+
+    #[test]
+    pub fn pick_under_the_cursor() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, 5.0).into()));
+        let far = renderer.add_model(square(-1.0, 10.0, [1.0, 0.0, 0.0]));
+        let near = renderer.add_model(square(1.0, 1.0, [0.0, 1.0, 0.0]));
+
+        // The same surfaces are picked as drawn
+        let pick = renderer.pick(Point2::new(8.5, 8.5)).unwrap();
+        assert_eq!((pick.node, pick.model), (near, 1));
+        assert_abs_diff_eq!(pick.position.z, 1.0, epsilon = 1e-5);
+        let pick = renderer.pick(Point2::new(1.5, 1.5)).unwrap();
+        assert_eq!((pick.node, pick.model, pick.mesh), (far, 0, 0));
+    }
+
+    #[test]
+    pub fn models_are_placed_by_their_nodes() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, 5.0).into()));
+        let left = renderer.add_model(square(0.0, 1.0, [1.0, 0.0, 0.0]));
+        let right = renderer.add_model(square(0.0, 1.0, [0.0, 1.0, 0.0]));
+
+        // The right square follows a parent node
+        let scene = renderer.scene_mut();
+        scene.node_mut(left).transform.translate = [-2.5, 0.0, 0.0];
+        let group = scene.add_node(
+            "group",
+            Transform {
+                translate: [2.5, 0.0, 0.0],
+                ..Transform::new()
+            },
+            None,
+        );
+        scene.set_parent(right, Some(group)).unwrap();
+        renderer.render().unwrap();
+
+        let image = renderer.image();
+        assert_eq!(image.get_pixel(4, 8).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(8, 8).0, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(12, 8).0, [0, 255, 0, 255]);
+    }
+
+    #[test]
+    pub fn framing_follows_the_node() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, 5.0).into()));
+
+        // Adding a model leaves the camera where it was
+        let node = renderer.add_model(square(0.0, 1.0, [1.0; 3]));
+        assert_eq!(renderer.camera().eye(), Point3::new(0.0, 0.0, 5.0));
+
+        let transform = &mut renderer.scene_mut().node_mut(node).transform;
+        transform.translate = [10.0, 0.0, 0.0];
+        transform.scale = [2.0; 3];
+        renderer.frame_model(node);
+        assert_abs_diff_eq!(renderer.camera().target(), Point3::new(10.0, 0.0, 0.0));
+        renderer.render().unwrap();
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [255; 4]);
+        assert_eq!(renderer.image().get_pixel(0, 0).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    pub fn lights_shade_untextured_surfaces() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, 5.0).into()));
+        renderer.add_model(square(0.0, 1.0, [1.0, 0.5, 0.0]));
+
+        // Light straight on, then at 60 degrees for half as much light
+        let light = |direction| Light::Directional {
+            direction,
+            colour: [1.0; 3],
+            intensity: 1.0,
+        };
+        renderer.lighting_mut().lights = vec![light(-Vector3::unit_z())];
+        renderer.render().unwrap();
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [255, 188, 0, 255]);
+
+        let angled = Vector3::new(Deg(60.0).sin(), 0.0, -Deg(60.0).cos());
+        renderer.lighting_mut().lights = vec![light(angled)];
+        renderer.render().unwrap();
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [188, 137, 0, 255]);
+
+        // Lit from behind the front stays dark
+        renderer.lighting_mut().lights = vec![light(Vector3::unit_z())];
+        renderer.render().unwrap();
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    pub fn back_faces_are_culled() {
+        let mut renderer = renderer(16, test_camera((0.0, 0.0, -5.0).into()));
+        renderer.add_model(square(0.0, 1.0, [1.0; 3]));
+
+        renderer.render().unwrap();
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [0, 0, 0, 255]);
+
+        renderer.set_back_face_culling(false);
+        renderer.render().unwrap();
+        assert_eq!(renderer.image().get_pixel(8, 8).0, [255; 4]);
+    }
+
+    #[test]
+    pub fn perspective_correct_texturing() {
+        // Texture whose red channel is the u texture coordinate
+        let gradient = image::RgbaImage::from_fn(256, 1, |x, _| [x as u8, 0, 0, 255].into());
+        let mut data = vec![];
+        image::DynamicImage::ImageRgba8(gradient)
+            .write_to(&mut Cursor::new(&mut data), image::ImageOutputFormat::Png)
+            .unwrap();
+        let mut model = square(0.0, 4.0, [1.0; 3]);
+        model.materials[0].diffuse_texture_data = Some(data);
+
+        // Looking across the square at a shallow angle
+        let camera = || {
+            Camera::new(
+                (-5.0, 0.0, 1.5).into(),
+                (0.0, 0.0, 0.0).into(),
+                Vector3::unit_y(),
+                1.0,
+                60.0,
+                0.1,
+                100.0,
+            )
+        };
+        let mut renderer = renderer(64, camera());
+        renderer.add_model(model);
+        renderer.render().unwrap();
+
+        let camera = camera();
+        for x in (4..60).step_by(8) {
+            let y = 32;
+            let ray = camera.ray((x as f32 + 0.5) / 32.0 - 1.0, 1.0 - (y as f32 + 0.5) / 32.0);
+            let t = -ray.origin.z / ray.direction.z;
+            let hit = ray.at(t);
+            if t <= 0.0 || hit.x.abs() >= 4.0 {
+                continue;
+            }
+
+            let expected = (hit.x / 4.0 + 1.0) / 2.0;
+            let [red, _, _, _] = renderer.image().get_pixel(x, y).0;
+            let u = red as f32 / 255.0;
+            assert!((u - expected).abs() < 0.02, "{} {} {}", x, u, expected);
+        }
+    }
+}
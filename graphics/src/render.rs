@@ -1,17 +1,31 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::animation::Animation;
+use crate::bounds::BoundingSphere;
+use crate::bvh::TriangleBvh;
+use crate::camera_path::CameraPath;
 use crate::curve::{Curve, CurveVertex};
-use crate::model::{GpuModel, Model, ModelVertex, Vertex};
+use crate::light::Lighting;
+use crate::model::{GpuMaterial, GpuModel, Material, Model, ModelVertex, Vertex};
+use crate::picking::{self, ControlPointPick, ModelPick};
+use crate::scene::SceneGraph;
+use crate::transform::TransformUniform;
 use crate::{camera::*, texture, transform};
 use cgmath::*;
+use image::{ImageFormat, RgbaImage};
 use wgpu::util::*;
 use wgpu::*;
-use winit::event::{DeviceEvent, WindowEvent};
+use winit::dpi::PhysicalSize;
+use winit::event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::window::Window;
 
 pub trait Renderer {
     fn recreate(&mut self);
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>);
     fn input(&mut self, event: &ControlEvent) -> bool;
-    fn update(&mut self);
+    /// Advance animations by the time since the last frame
+    fn update(&mut self, delta: Duration);
     fn render(&mut self) -> Result<(), SurfaceError>;
 }
 
@@ -24,37 +38,393 @@ pub enum ControlEvent<'a> {
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
     view_proj: [[f32; 4]; 4],
+    view_position: [f32; 4],
 }
 
 impl CameraUniform {
     fn new() -> Self {
         Self {
             view_proj: Matrix4::identity().into(),
+            view_position: [0.0; 4],
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
         self.view_proj = camera.build_view_projection_matrix().into();
+        self.view_position = camera.eye().to_homogeneous().into();
+    }
+}
+
+/// Format of offscreen render targets
+const OFFSCREEN_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+#[derive(Debug)]
+pub enum RenderError {
+    /// No GPU or software adapter could be found
+    NoAdapter,
+    RequestDevice(RequestDeviceError),
+    BufferMap(BufferAsyncError),
+
+    /// Only headless renderers can be read back
+    NotOffscreen,
+    Image(image::ImageError),
+}
+
+/// Where a renderer draws its frames
+enum RenderTarget {
+    Surface(Surface),
+    Offscreen {
+        texture: wgpu::Texture,
+        size: Extent3d,
+    },
+}
+
+impl RenderTarget {
+    fn offscreen(device: &Device, config: &SurfaceConfiguration) -> RenderTarget {
+        let size = Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen target"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: config.format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        });
+
+        RenderTarget::Offscreen { texture, size }
+    }
+
+    fn configure(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        match self {
+            RenderTarget::Surface(surface) => surface.configure(device, config),
+            RenderTarget::Offscreen { .. } => *self = RenderTarget::offscreen(device, config),
+        }
+    }
+
+    /// View to draw the next frame into, with the surface texture to present afterwards
+    fn view(&self) -> Result<(Option<SurfaceTexture>, TextureView), SurfaceError> {
+        match self {
+            RenderTarget::Surface(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&TextureViewDescriptor::default());
+                Ok((Some(output), view))
+            }
+            RenderTarget::Offscreen { texture, .. } => {
+                Ok((None, texture.create_view(&TextureViewDescriptor::default())))
+            }
+        }
+    }
+
+    /// Copy an offscreen target back from the GPU
+    fn read_image(&self, device: &Device, queue: &Queue) -> Result<RgbaImage, RenderError> {
+        let (texture, size) = match self {
+            RenderTarget::Offscreen { texture, size } => (texture, *size),
+            RenderTarget::Surface(_) => return Err(RenderError::NotOffscreen),
+        };
+
+        // Rows of a buffer copy must be aligned, so are padded & trimmed afterwards
+        let row_bytes = size.width * 4;
+        let alignment = COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = row_bytes.div_ceil(alignment) * alignment;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_row_bytes * size.height) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: std::num::NonZeroU32::new(size.height),
+                },
+            },
+            size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(MapMode::Read);
+        device.poll(Maintain::Wait);
+        pollster::block_on(mapping).map_err(RenderError::BufferMap)?;
+
+        let data = slice.get_mapped_range();
+        let pixels = data
+            .chunks_exact(padded_row_bytes as usize)
+            .flat_map(|row| &row[..row_bytes as usize])
+            .copied()
+            .collect();
+        drop(data);
+        buffer.unmap();
+
+        Ok(RgbaImage::from_raw(size.width, size.height, pixels).unwrap())
+    }
+}
+
+/// Depth test & clear value keeping the nearest fragment, whichever way depth runs
+fn depth_test(reversed_depth: bool) -> (CompareFunction, f32) {
+    if reversed_depth {
+        (CompareFunction::Greater, 0.0)
+    } else {
+        (CompareFunction::Less, 1.0)
+    }
+}
+
+/// Pipeline drawing models as triangles
+fn create_model_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    format: TextureFormat,
+    reversed_depth: bool,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Render pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[ColorTargetState {
+                format,
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            }],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: Some(Face::Back),
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: depth_test(reversed_depth).0,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// Pipeline drawing curves as line strips
+fn create_curve_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    format: TextureFormat,
+    reversed_depth: bool,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Render pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[CurveVertex::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[ColorTargetState {
+                format,
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            }],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::LineStrip,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: Some(Face::Back),
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: depth_test(reversed_depth).0,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// Size of one draw's matrices in the transform buffer
+const TRANSFORM_SIZE: u64 = std::mem::size_of::<TransformUniform>() as u64;
+
+fn create_transform_buffer(device: &Device, stride: u64, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Transform buffer"),
+        size: stride * capacity as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Bind the camera & a single model matrix of the transform buffer, picked by the
+/// dynamic offset of each draw
+fn create_camera_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    camera_buffer: &wgpu::Buffer,
+    transform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: transform_buffer,
+                    offset: 0,
+                    size: BufferSize::new(TRANSFORM_SIZE),
+                }),
+            },
+        ],
+        label: Some("camera_bind_group"),
+    })
+}
+
+/// Instance on the primary backend of the platform, or the ones listed in `WGPU_BACKEND`
+fn create_instance() -> Instance {
+    Instance::new(backend_bits_from_env().unwrap_or(Backends::PRIMARY))
+}
+
+/// Find an adapter & open a device on it, falling back to a software adapter if there's
+/// no GPU
+async fn request_device(
+    instance: &Instance,
+    compatible_surface: Option<&Surface>,
+) -> Result<(Adapter, Device, Queue), RenderError> {
+    let mut adapter = None;
+    for force_fallback_adapter in [false, true] {
+        adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::default(),
+                compatible_surface,
+                force_fallback_adapter,
+            })
+            .await;
+        if adapter.is_some() {
+            break;
+        }
     }
+    let adapter = adapter.ok_or(RenderError::NoAdapter)?;
+
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                features: Features::empty(),
+                limits: Limits::default(),
+                label: None,
+            },
+            None,
+        )
+        .await
+        .map_err(RenderError::RequestDevice)?;
+
+    Ok((adapter, device, queue))
+}
+
+/// Device & offscreen target for rendering without a window
+async fn headless_target(
+    size: PhysicalSize<u32>,
+) -> Result<(Device, Queue, SurfaceConfiguration, RenderTarget), RenderError> {
+    let instance = create_instance();
+    let (_, device, queue) = request_device(&instance, None).await?;
+
+    let config = SurfaceConfiguration {
+        usage: TextureUsages::RENDER_ATTACHMENT,
+        format: OFFSCREEN_FORMAT,
+        width: size.width.max(1),
+        height: size.height.max(1),
+        present_mode: PresentMode::Fifo,
+    };
+    let target = RenderTarget::offscreen(&device, &config);
+
+    Ok((device, queue, config, target))
 }
 
 pub struct Render3D<'a> {
-    surface: Surface,
+    target: RenderTarget,
     device: Device,
     queue: Queue,
     config: SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     render_pipeline: RenderPipeline,
+
+    /// Layout & shader of the pipeline, rebuilt when the camera's depth reverses
+    render_pipeline_layout: PipelineLayout,
+    shader: ShaderModule,
+    reversed_depth: bool,
     depth_texture: texture::Texture,
     texture_bind_group_layout: BindGroupLayout,
     camera_controller: CameraController<'a>,
     camera: Camera,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
+    camera_bind_group_layout: BindGroupLayout,
     camera_bind_group: wgpu::BindGroup,
-    transform: transform::Transform,
+    scene: SceneGraph,
+    animations: Vec<Animation>,
+    camera_path: Option<CameraPath>,
+
+    /// Model & normal matrices of every draw, `transform_stride` bytes apart
     transform_buffer: wgpu::Buffer,
+    transform_stride: u64,
+    transform_capacity: usize,
+    lighting: Lighting,
+    lighting_buffer: wgpu::Buffer,
+    lighting_bind_group: wgpu::BindGroup,
     models: Vec<GpuModel<'a>>,
+
+    /// Bounds of each model in its own space, for framing the camera on it
+    model_bounds: Vec<Option<BoundingSphere>>,
+
+    /// Hierarchy over each model's triangles for picking
+    hierarchies: Vec<TriangleBvh>,
+
+    /// Material of meshes without one
+    default_material: GpuMaterial,
 }
 
 impl<'a> Render3D<'a> {
@@ -65,28 +435,9 @@ impl<'a> Render3D<'a> {
     ) -> Render3D<'a> {
         let size = window.inner_size();
 
-        let instance = Instance::new(Backends::VULKAN);
+        let instance = create_instance();
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &DeviceDescriptor {
-                    features: Features::empty(),
-                    limits: Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (adapter, device, queue) = request_device(&instance, Some(&surface)).await.unwrap();
 
         let config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
@@ -97,6 +448,40 @@ impl<'a> Render3D<'a> {
         };
         surface.configure(&device, &config);
 
+        let target = RenderTarget::Surface(surface);
+        Self::with_target(target, device, queue, config, camera, camera_controller)
+    }
+
+    /// Create a renderer drawing into an offscreen texture instead of a window, see
+    /// `read_image`
+    pub async fn new_headless(
+        size: PhysicalSize<u32>,
+        camera: Camera,
+        camera_controller: CameraController<'a>,
+    ) -> Result<Render3D<'a>, RenderError> {
+        let (device, queue, config, target) = headless_target(size).await?;
+
+        Ok(Self::with_target(
+            target,
+            device,
+            queue,
+            config,
+            camera,
+            camera_controller,
+        ))
+    }
+
+    fn with_target(
+        target: RenderTarget,
+        device: Device,
+        queue: Queue,
+        config: SurfaceConfiguration,
+        camera: Camera,
+        mut camera_controller: CameraController<'a>,
+    ) -> Render3D<'a> {
+        let size = PhysicalSize::new(config.width, config.height);
+        camera_controller.set_viewport(size);
+
         let texture_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("texture_bind_group_layout"),
@@ -135,6 +520,16 @@ impl<'a> Render3D<'a> {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -146,12 +541,12 @@ impl<'a> Render3D<'a> {
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let transform = transform::Transform::new();
-        let transform_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Transform buffer"),
-            contents: bytemuck::cast_slice(&[transform.as_uniform()]),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        });
+        // Each draw binds its model matrix at a dynamic offset into one buffer
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let transform_stride = TRANSFORM_SIZE.div_ceil(alignment) * alignment;
+        let transform_capacity = 16;
+        let transform_buffer =
+            create_transform_buffer(&device, transform_stride, transform_capacity);
 
         let camera_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -159,7 +554,7 @@ impl<'a> Render3D<'a> {
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: ShaderStages::VERTEX,
+                        visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                         ty: BindingType::Buffer {
                             ty: BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -172,80 +567,83 @@ impl<'a> Render3D<'a> {
                         visibility: ShaderStages::VERTEX,
                         ty: BindingType::Buffer {
                             ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
+                            has_dynamic_offset: true,
+                            min_binding_size: BufferSize::new(TRANSFORM_SIZE),
                         },
                         count: None,
                     },
                 ],
             });
-        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
+        let camera_bind_group = create_camera_bind_group(
+            &device,
+            &camera_bind_group_layout,
+            &camera_buffer,
+            &transform_buffer,
+        );
+
+        let lighting = Lighting::default();
+        let lighting_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Lighting buffer"),
+            contents: bytemuck::cast_slice(&[lighting.as_uniform()]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let lighting_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("lighting_bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
                     binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: transform_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("camera_bind_group"),
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let lighting_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &lighting_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: lighting_buffer.as_entire_binding(),
+            }],
+            label: Some("lighting_bind_group"),
         });
 
+        let default_material = GpuMaterial::from_material(
+            Material {
+                diffuse_color: [1.0; 3],
+                ..Default::default()
+            },
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+        );
+
         let depth_texture =
             texture::Texture::create_depth_texture(&device, &config, "depth_texture");
 
         let shader = device.create_shader_module(&include_wgsl!("shader.wgsl"));
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Render pipeline layout"),
-            bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &lighting_bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[ModelVertex::desc()],
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[ColorTargetState {
-                    format: config.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                }],
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-            }),
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let reversed_depth = camera.projection().reversed_depth();
+        let render_pipeline = create_model_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            reversed_depth,
+        );
 
         Self {
-            surface,
+            target,
             device,
             queue,
             config,
@@ -253,19 +651,58 @@ impl<'a> Render3D<'a> {
             depth_texture,
             texture_bind_group_layout,
             render_pipeline,
+            render_pipeline_layout,
+            shader,
+            reversed_depth,
             camera_controller,
             camera,
             camera_uniform,
             camera_buffer,
+            camera_bind_group_layout,
             camera_bind_group,
-            transform,
+            scene: SceneGraph::new(),
+            animations: vec![],
+            camera_path: None,
             transform_buffer,
+            transform_stride,
+            transform_capacity,
+            lighting,
+            lighting_buffer,
+            lighting_bind_group,
             models: vec![],
+            model_bounds: vec![],
+            hierarchies: vec![],
+            default_material,
         }
     }
 
-    /// Load a model into the scene
-    pub fn add_model(&mut self, model: Model) {
+    /// Copy the last frame of a headless renderer back from the GPU
+    pub fn read_image(&self) -> Result<RgbaImage, RenderError> {
+        self.target.read_image(&self.device, &self.queue)
+    }
+
+    /// Save the last frame of a headless renderer as a PNG
+    pub fn save_png(&self, path: &Path) -> Result<(), RenderError> {
+        self.read_image()?
+            .save_with_format(path, ImageFormat::Png)
+            .map_err(RenderError::Image)
+    }
+
+    /// Lights of the scene, with none the models are drawn unlit
+    pub fn lighting(&self) -> &Lighting {
+        &self.lighting
+    }
+
+    pub fn lighting_mut(&mut self) -> &mut Lighting {
+        &mut self.lighting
+    }
+
+    /// Load a model into a new root node of the scene
+    ///
+    /// Returns the node, whose transform places the model
+    pub fn add_model(&mut self, model: Model) -> usize {
+        self.model_bounds.push(model.bounding_sphere());
+        self.hierarchies.push(TriangleBvh::new(&model));
         let model = GpuModel::from_model(
             model,
             &self.device,
@@ -274,7 +711,101 @@ impl<'a> Render3D<'a> {
             Some("model"),
         );
 
+        let node = self
+            .scene
+            .add_node("model", transform::Transform::new(), None);
+        self.scene.node_mut(node).model = Some(self.models.len());
         self.models.push(model);
+
+        node
+    }
+
+    /// Move the camera to show all of a node's model where the scene places it, keeping
+    /// the view direction
+    pub fn frame_model(&mut self, node: usize) {
+        let sphere = self
+            .scene
+            .node(node)
+            .model
+            .and_then(|model| self.model_bounds[model]);
+        if let Some(sphere) = sphere {
+            self.camera
+                .frame(&sphere.transform(self.scene.world_matrix(node)));
+        }
+    }
+
+    /// Nodes placing the models
+    pub fn scene(&self) -> &SceneGraph {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut SceneGraph {
+        &mut self.scene
+    }
+
+    /// Play an animation of the scene's nodes & camera, advanced on every update
+    pub fn add_animation(&mut self, animation: Animation) {
+        self.animations.push(animation);
+    }
+
+    pub fn animations_mut(&mut self) -> &mut [Animation] {
+        &mut self.animations
+    }
+
+    /// Fly the camera along a path on every update, or stop with None
+    pub fn set_camera_path(&mut self, camera_path: Option<CameraPath>) {
+        self.camera_path = camera_path;
+    }
+
+    pub fn camera_path_mut(&mut self) -> Option<&mut CameraPath> {
+        self.camera_path.as_mut()
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Cursor position in pixels from the top left of the window, if it's over it
+    pub fn cursor(&self) -> Option<Point2<f32>> {
+        self.camera_controller.cursor()
+    }
+
+    /// Nearest triangle of the models under a pixel, from the top left of the window
+    pub fn pick(&self, pixel: Point2<f32>) -> Option<ModelPick> {
+        let ray = self.camera.pixel_ray(pixel, self.size);
+        picking::pick_model(&self.scene, &self.hierarchies, &ray)
+    }
+
+    /// Write the matrices of every draw, growing the buffer if it's too small
+    fn write_transforms(&mut self, draws: &[(usize, Matrix4<f32>)]) {
+        if draws.len() > self.transform_capacity {
+            self.transform_capacity = draws.len().next_power_of_two();
+            self.transform_buffer = create_transform_buffer(
+                &self.device,
+                self.transform_stride,
+                self.transform_capacity,
+            );
+            self.camera_bind_group = create_camera_bind_group(
+                &self.device,
+                &self.camera_bind_group_layout,
+                &self.camera_buffer,
+                &self.transform_buffer,
+            );
+        }
+
+        let mut contents = vec![0; self.transform_stride as usize * draws.len()];
+        for (i, (_, matrix)) in draws.iter().enumerate() {
+            let uniform = TransformUniform::new(*matrix);
+            let start = i * self.transform_stride as usize;
+            contents[start..start + TRANSFORM_SIZE as usize]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        self.queue
+            .write_buffer(&self.transform_buffer, 0, &contents);
     }
 }
 
@@ -290,10 +821,13 @@ impl<'a> Renderer for Render3D<'a> {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            self.target.configure(&self.device, &self.config);
 
             self.depth_texture =
                 texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.camera
+                .set_aspect(new_size.width as f32 / new_size.height as f32);
+            self.camera_controller.set_viewport(new_size);
         }
     }
 
@@ -302,10 +836,29 @@ impl<'a> Renderer for Render3D<'a> {
         self.camera_controller.process_events(event)
     }
 
-    fn update(&mut self) {}
+    fn update(&mut self, delta: Duration) {
+        self.camera_controller
+            .update_camera(&mut self.camera, delta);
+        for animation in self.animations.iter_mut() {
+            animation.update(delta, &mut self.scene, &mut self.camera);
+        }
+        if let Some(camera_path) = self.camera_path.as_mut() {
+            camera_path.update(delta, &mut self.camera);
+        }
+    }
 
     fn render(&mut self) -> Result<(), SurfaceError> {
-        self.camera_controller.update_camera(&mut self.camera);
+        let reversed_depth = self.camera.projection().reversed_depth();
+        if reversed_depth != self.reversed_depth {
+            self.reversed_depth = reversed_depth;
+            self.render_pipeline = create_model_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                &self.shader,
+                self.config.format,
+                reversed_depth,
+            );
+        }
         self.camera_uniform.update_view_proj(&self.camera);
         self.queue.write_buffer(
             &self.camera_buffer,
@@ -313,16 +866,16 @@ impl<'a> Renderer for Render3D<'a> {
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
 
+        let draws = self.scene.draws();
+        self.write_transforms(&draws);
+
         self.queue.write_buffer(
-            &self.transform_buffer,
+            &self.lighting_buffer,
             0,
-            bytemuck::cast_slice(&[self.transform.as_uniform()]),
+            bytemuck::cast_slice(&[self.lighting.as_uniform()]),
         );
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&TextureViewDescriptor::default());
+        let (output, view) = self.target.view()?;
         let mut encoder = self
             .device
             .create_command_encoder(&CommandEncoderDescriptor {
@@ -348,7 +901,7 @@ impl<'a> Renderer for Render3D<'a> {
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(Operations {
-                        load: LoadOp::Clear(1.0),
+                        load: LoadOp::Clear(depth_test(self.reversed_depth).1),
                         store: true,
                     }),
                     stencil_ops: None,
@@ -356,45 +909,62 @@ impl<'a> Renderer for Render3D<'a> {
             });
 
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.lighting_bind_group, &[]);
 
-            for model in self.models.iter() {
+            for (i, (model, _)) in draws.iter().enumerate() {
+                let model = &self.models[*model];
+                let offset = (i as u64 * self.transform_stride) as u32;
+                render_pass.set_bind_group(1, &self.camera_bind_group, &[offset]);
                 for mesh in model.meshes.iter() {
                     render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
                     render_pass.set_index_buffer(mesh.index_buffer.slice(..), IndexFormat::Uint32);
-                    if let Some(material) = mesh.material {
-                        render_pass.set_bind_group(
-                            0,
-                            &model.materials[material].texture_bind_group,
-                            &[],
-                        );
-                    }
+                    let material = mesh
+                        .material
+                        .and_then(|material| model.materials.get(material))
+                        .unwrap_or(&self.default_material);
+                    render_pass.set_bind_group(0, &material.texture_bind_group, &[]);
                     render_pass.draw_indexed(0..mesh.vertex_count, 0, 0..1);
                 }
             }
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(output) = output {
+            output.present();
+        }
 
         Ok(())
     }
 }
 
 pub struct Render2D<'a> {
-    surface: Surface,
+    target: RenderTarget,
     device: Device,
     queue: Queue,
     config: SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     render_pipeline: RenderPipeline,
+
+    /// Layout & shader of the pipeline, rebuilt when the camera's depth reverses
+    render_pipeline_layout: PipelineLayout,
+    shader: ShaderModule,
+    reversed_depth: bool,
     depth_texture: texture::Texture,
     camera_controller: CameraController<'a>,
     camera: Camera,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    curves: Vec<Buffer>,
+
+    curves: Vec<CurveBuffer>,
+}
+
+/// Curve drawn by `Render2D`, with its points kept on the CPU for fitting the view &
+/// picking
+struct CurveBuffer {
+    vertices: Vec<CurveVertex>,
+    control_points: Vec<Point3<f32>>,
+    buffer: Buffer,
 }
 
 impl<'a> Render2D<'a> {
@@ -405,28 +975,9 @@ impl<'a> Render2D<'a> {
     ) -> Render2D<'a> {
         let size = window.inner_size();
 
-        let instance = Instance::new(Backends::VULKAN);
+        let instance = create_instance();
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &DeviceDescriptor {
-                    features: Features::empty(),
-                    limits: Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (adapter, device, queue) = request_device(&instance, Some(&surface)).await.unwrap();
 
         let config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
@@ -437,6 +988,40 @@ impl<'a> Render2D<'a> {
         };
         surface.configure(&device, &config);
 
+        let target = RenderTarget::Surface(surface);
+        Self::with_target(target, device, queue, config, camera, camera_controller)
+    }
+
+    /// Create a renderer drawing into an offscreen texture instead of a window, see
+    /// `read_image`
+    pub async fn new_headless(
+        size: PhysicalSize<u32>,
+        camera: Camera,
+        camera_controller: CameraController<'a>,
+    ) -> Result<Render2D<'a>, RenderError> {
+        let (device, queue, config, target) = headless_target(size).await?;
+
+        Ok(Self::with_target(
+            target,
+            device,
+            queue,
+            config,
+            camera,
+            camera_controller,
+        ))
+    }
+
+    fn with_target(
+        target: RenderTarget,
+        device: Device,
+        queue: Queue,
+        config: SurfaceConfiguration,
+        camera: Camera,
+        mut camera_controller: CameraController<'a>,
+    ) -> Render2D<'a> {
+        let size = PhysicalSize::new(config.width, config.height);
+        camera_controller.set_viewport(size);
+
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera);
         let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -477,55 +1062,26 @@ impl<'a> Render2D<'a> {
             bind_group_layouts: &[&camera_bind_group_layout],
             push_constant_ranges: &[],
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[CurveVertex::desc()],
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[ColorTargetState {
-                    format: config.format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                }],
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::LineStrip,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
-                polygon_mode: PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-            }),
-            multisample: MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let reversed_depth = camera.projection().reversed_depth();
+        let render_pipeline = create_curve_pipeline(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            reversed_depth,
+        );
 
         Self {
-            surface,
+            target,
             device,
             queue,
             config,
             size,
             depth_texture,
             render_pipeline,
+            render_pipeline_layout,
+            shader,
+            reversed_depth,
             camera_controller,
             camera,
             camera_uniform,
@@ -535,17 +1091,78 @@ impl<'a> Render2D<'a> {
         }
     }
 
+    /// Copy the last frame of a headless renderer back from the GPU
+    pub fn read_image(&self) -> Result<RgbaImage, RenderError> {
+        self.target.read_image(&self.device, &self.queue)
+    }
+
+    /// Save the last frame of a headless renderer as a PNG
+    pub fn save_png(&self, path: &Path) -> Result<(), RenderError> {
+        self.read_image()?
+            .save_with_format(path, ImageFormat::Png)
+            .map_err(RenderError::Image)
+    }
+
     /// Add a new curve to be rendered
     pub fn add_curve<T: Curve>(&mut self, curve: T) {
         let vertices = curve.to_vertices(0.0..1.0, 50);
 
         let buffer = self.device.create_buffer_init(&BufferInitDescriptor {
-            label: Some(&format!("Vertex Buffer")),
+            label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(&vertices),
             usage: BufferUsages::VERTEX,
         });
 
-        self.curves.push(buffer);
+        self.curves.push(CurveBuffer {
+            vertices,
+            control_points: curve.control_points(),
+            buffer,
+        });
+    }
+
+    /// Move the camera to show all of the curves, keeping the view direction
+    pub fn fit_curves(&mut self) {
+        let points = self
+            .curves
+            .iter()
+            .flat_map(|curve| &curve.vertices)
+            .map(|vertex| Point3::from(vertex.position));
+        if let Some(sphere) = BoundingSphere::from_points(points) {
+            self.camera.frame(&sphere);
+        }
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Cursor position in pixels from the top left of the window, if it's over it
+    pub fn cursor(&self) -> Option<Point2<f32>> {
+        self.camera_controller.cursor()
+    }
+
+    /// Curve control point nearest a pixel, from the top left of the window, if it's
+    /// within `radius` pixels
+    pub fn pick_control_point(&self, pixel: Point2<f32>, radius: f32) -> Option<ControlPointPick> {
+        let picks: Vec<ControlPointPick> = self
+            .curves
+            .iter()
+            .enumerate()
+            .flat_map(|(curve, buffer)| {
+                (0..buffer.control_points.len()).map(move |point| ControlPointPick { curve, point })
+            })
+            .collect();
+        let points: Vec<Point3<f32>> = self
+            .curves
+            .iter()
+            .flat_map(|curve| curve.control_points.iter().copied())
+            .collect();
+
+        picking::pick_point(&points, &self.camera, pixel, self.size, radius).map(|i| picks[i])
     }
 }
 
@@ -561,21 +1178,50 @@ impl<'a> Renderer for Render2D<'a> {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            self.target.configure(&self.device, &self.config);
 
             self.depth_texture =
                 texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.camera
+                .set_aspect(new_size.width as f32 / new_size.height as f32);
+            self.camera_controller.set_viewport(new_size);
         }
     }
 
     fn input(&mut self, event: &ControlEvent) -> bool {
+        if let ControlEvent::WindowEvent(WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F),
+                    ..
+                },
+            ..
+        }) = event
+        {
+            self.fit_curves();
+            return true;
+        }
         self.camera_controller.process_events(event)
     }
 
-    fn update(&mut self) {}
+    fn update(&mut self, delta: Duration) {
+        self.camera_controller
+            .update_camera(&mut self.camera, delta);
+    }
 
     fn render(&mut self) -> Result<(), SurfaceError> {
-        self.camera_controller.update_camera(&mut self.camera);
+        let reversed_depth = self.camera.projection().reversed_depth();
+        if reversed_depth != self.reversed_depth {
+            self.reversed_depth = reversed_depth;
+            self.render_pipeline = create_curve_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                &self.shader,
+                self.config.format,
+                reversed_depth,
+            );
+        }
         self.camera_uniform.update_view_proj(&self.camera);
         self.queue.write_buffer(
             &self.camera_buffer,
@@ -583,10 +1229,7 @@ impl<'a> Renderer for Render2D<'a> {
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&TextureViewDescriptor::default());
+        let (output, view) = self.target.view()?;
         let mut encoder = self
             .device
             .create_command_encoder(&CommandEncoderDescriptor {
@@ -612,7 +1255,7 @@ impl<'a> Renderer for Render2D<'a> {
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(Operations {
-                        load: LoadOp::Clear(1.0),
+                        load: LoadOp::Clear(depth_test(self.reversed_depth).1),
                         store: true,
                     }),
                     stencil_ops: None,
@@ -623,14 +1266,81 @@ impl<'a> Renderer for Render2D<'a> {
             render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
 
             for curve in self.curves.iter() {
-                render_pass.set_vertex_buffer(0, curve.slice(..));
-                render_pass.draw(0..55, 0..1)
+                render_pass.set_vertex_buffer(0, curve.buffer.slice(..));
+                render_pass.draw(0..curve.vertices.len() as u32, 0..1)
             }
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(output) = output {
+            output.present();
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+    use winit::dpi::PhysicalSize;
+
+    use crate::{
+        camera::{Camera, CameraController, MoveMode, Projection},
+        test_util::{load_model, output_dir},
+    };
+
+    use super::{Render3D, RenderError, Renderer};
+
+    #[test]
+    pub fn headless_render() {
+        let camera = Camera::new(
+            (0.0, 0.0, 3.0).into(),
+            Point3::origin(),
+            Vector3::unit_y(),
+            1.0,
+            45.0,
+            0.1,
+            100.0,
+        );
+        let mode = MoveMode::new();
+        let renderer = pollster::block_on(Render3D::new_headless(
+            PhysicalSize::new(32, 24),
+            camera,
+            CameraController::new(0.0, &mode),
+        ));
+        let mut renderer = match renderer {
+            Ok(renderer) => renderer,
+            Err(RenderError::NoAdapter) => {
+                eprintln!("skipping headless render, no GPU or software adapter available");
+                return;
+            }
+            Err(err) => panic!("{:?}", err),
+        };
+
+        let node = renderer.add_model(load_model("cube.obj"));
+        renderer.frame_model(node);
+        renderer.render().unwrap();
+
+        let image = renderer.read_image().unwrap();
+        assert_eq!(image.dimensions(), (32, 24));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_ne!(image.get_pixel(16, 12).0, [0, 0, 0, 255]);
+
+        let output = output_dir("headless").join("cube.png");
+        renderer.save_png(&output).unwrap();
+        assert_eq!(image::open(&output).unwrap().to_rgba8(), image);
+
+        // Reversing depth switches the depth test too, so the picture doesn't change
+        renderer
+            .camera_mut()
+            .set_projection(Projection::InfinitePerspective { fovy: 45.0 });
+        renderer.render().unwrap();
+        let reversed = renderer.read_image().unwrap();
+        assert!(image.pixels().zip(reversed.pixels()).all(|(a, b)| a
+            .0
+            .iter()
+            .zip(b.0)
+            .all(|(a, b)| a.abs_diff(b) <= 2)));
+    }
+}
@@ -0,0 +1,649 @@
+//! Mesh validation & repair
+//!
+//! Topology checks work on welded positions, so vertices split for texture or normal
+//! seams aren't mistaken for holes
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::model::{Mesh, Model};
+
+/// Problems found in a mesh, each listing the offending triangles, vertices or edges
+///
+/// Triangles are indexed by their position in the index buffer divided by three, edges
+/// are pairs of vertex indices
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MeshReport {
+    /// Triangles with an index past the end of the vertices
+    pub out_of_range_triangles: Vec<usize>,
+
+    /// Triangles with no area
+    pub degenerate_triangles: Vec<usize>,
+
+    /// Triangles using the same three positions as an earlier triangle
+    pub duplicate_triangles: Vec<usize>,
+
+    /// Vertices that no triangle uses
+    pub unreferenced_vertices: Vec<usize>,
+
+    /// Vertices with a NaN or infinite attribute
+    pub non_finite_vertices: Vec<usize>,
+
+    /// Edges shared by more than two triangles
+    pub non_manifold_edges: Vec<(u32, u32)>,
+
+    /// Edges whose two triangles both run along them in the same direction
+    pub inconsistent_edges: Vec<(u32, u32)>,
+
+    /// Edges used by only one triangle
+    pub boundary_edges: Vec<(u32, u32)>,
+}
+
+impl MeshReport {
+    /// Whether the mesh has no problems, open boundaries are allowed
+    pub fn is_valid(&self) -> bool {
+        self.out_of_range_triangles.is_empty()
+            && self.degenerate_triangles.is_empty()
+            && self.duplicate_triangles.is_empty()
+            && self.unreferenced_vertices.is_empty()
+            && self.non_finite_vertices.is_empty()
+            && self.non_manifold_edges.is_empty()
+            && self.inconsistent_edges.is_empty()
+    }
+
+    /// Whether the mesh is valid & has no open boundaries
+    pub fn is_closed(&self) -> bool {
+        self.is_valid() && self.boundary_edges.is_empty()
+    }
+}
+
+impl Model {
+    /// Check every mesh for problems
+    pub fn validate(&self) -> Vec<MeshReport> {
+        self.meshes.iter().map(Mesh::validate).collect()
+    }
+}
+
+impl Mesh {
+    /// Check the mesh for problems
+    pub fn validate(&self) -> MeshReport {
+        let mut report = MeshReport::default();
+
+        for (index, vertex) in self.vertices.iter().enumerate() {
+            let mut attributes = vertex
+                .position
+                .iter()
+                .chain(vertex.texture_coords.iter())
+                .chain(vertex.normal.iter())
+                .chain(vertex.tangent.iter());
+            if attributes.any(|value| !value.is_finite()) {
+                report.non_finite_vertices.push(index);
+            }
+        }
+
+        let welded = self.welded_indices();
+        let mut referenced = vec![false; self.vertices.len()];
+        let mut triangles = HashSet::new();
+        let mut edges: HashMap<(u32, u32), Vec<bool>> = HashMap::new();
+        for (triangle, indices) in self.indices.chunks_exact(3).enumerate() {
+            if self.is_out_of_range(triangle) {
+                report.out_of_range_triangles.push(triangle);
+                continue;
+            }
+            for index in indices {
+                referenced[*index as usize] = true;
+            }
+
+            if self.is_degenerate(triangle) {
+                report.degenerate_triangles.push(triangle);
+                continue;
+            }
+
+            let corners = [0, 1, 2].map(|i| welded[indices[i] as usize]);
+            let mut sorted = corners;
+            sorted.sort_unstable();
+            if !triangles.insert(sorted) {
+                report.duplicate_triangles.push(triangle);
+                continue;
+            }
+
+            for i in 0..3 {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                edges.entry(edge_key(a, b)).or_default().push(a < b);
+            }
+        }
+
+        report.unreferenced_vertices = (0..self.vertices.len())
+            .filter(|index| !referenced[*index])
+            .collect();
+
+        let mut edges: Vec<((u32, u32), Vec<bool>)> = edges.into_iter().collect();
+        edges.sort_unstable_by_key(|(edge, _)| *edge);
+        for (edge, directions) in edges {
+            match directions.len() {
+                1 => report.boundary_edges.push(edge),
+                2 if directions[0] == directions[1] => report.inconsistent_edges.push(edge),
+                2 => {}
+                _ => report.non_manifold_edges.push(edge),
+            }
+        }
+
+        report
+    }
+
+    /// Snap together vertices closer than `epsilon`, then merge vertices that end up
+    /// identical, returning the number of vertices removed
+    ///
+    /// An `epsilon` of zero or less only merges vertices that are already identical
+    pub fn weld(&mut self, epsilon: f32) -> usize {
+        if epsilon > 0.0 {
+            self.snap_positions(epsilon);
+        }
+
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut vertex_map: HashMap<[u32; 12], u32> = HashMap::new();
+        let remap: Vec<u32> = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                *vertex_map
+                    .entry(bytemuck::cast(*vertex))
+                    .or_insert_with(|| {
+                        vertices.push(*vertex);
+                        (vertices.len() - 1) as u32
+                    })
+            })
+            .collect();
+
+        let removed = self.vertices.len() - vertices.len();
+        for index in self.indices.iter_mut() {
+            if let Some(new_index) = remap.get(*index as usize) {
+                *index = *new_index;
+            }
+        }
+        self.vertices = vertices;
+
+        removed
+    }
+
+    fn snap_positions(&mut self, epsilon: f32) {
+        // Bucket positions into cells the size of epsilon, so only neighbouring cells
+        // need comparing. A tiny epsilon saturates far positions into the outermost cells,
+        // which only makes the search slower
+        let cell = |position: [f32; 3]| position.map(|p| (p / epsilon).floor() as i64);
+        let mut cells: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        for index in 0..self.vertices.len() {
+            let position = self.vertices[index].position;
+            let [x, y, z] = cell(position);
+
+            let mut snapped = None;
+            'search: for neighbour in neighbour_cells(x, y, z) {
+                for other in cells.get(&neighbour).into_iter().flatten() {
+                    let other_position = self.vertices[*other].position;
+                    if (Vector3::from(other_position) - Vector3::from(position)).magnitude()
+                        <= epsilon
+                    {
+                        snapped = Some(other_position);
+                        break 'search;
+                    }
+                }
+            }
+
+            match snapped {
+                Some(other_position) => self.vertices[index].position = other_position,
+                None => cells.entry([x, y, z]).or_default().push(index),
+            }
+        }
+    }
+
+    /// Remove triangles with out of range indices or no area, and triangles repeating an
+    /// earlier one, returning the number removed
+    pub fn remove_degenerate_triangles(&mut self) -> usize {
+        let report = self.validate();
+        let removed: HashSet<usize> = report
+            .out_of_range_triangles
+            .into_iter()
+            .chain(report.degenerate_triangles)
+            .chain(report.duplicate_triangles)
+            .collect();
+
+        self.retain_triangles(|triangle| !removed.contains(&triangle));
+        removed.len()
+    }
+
+    /// Remove vertices no triangle uses, returning the number removed
+    pub fn remove_unreferenced_vertices(&mut self) -> usize {
+        let mut remap = vec![None; self.vertices.len()];
+        let mut vertices = vec![];
+        for index in self.indices.iter_mut() {
+            let old = *index as usize;
+            if old >= remap.len() {
+                continue;
+            }
+
+            *index = *remap[old].get_or_insert_with(|| {
+                vertices.push(self.vertices[old]);
+                (vertices.len() - 1) as u32
+            });
+        }
+
+        let removed = self.vertices.len() - vertices.len();
+        self.vertices = vertices;
+        removed
+    }
+
+    /// Flip triangles so neighbouring triangles wind the same way, returning the number
+    /// flipped
+    ///
+    /// Closed parts end up facing outwards, open parts keep the winding most of their
+    /// triangles already had. Triangles are only connected across manifold edges, and
+    /// triangles with out of range indices are left alone
+    pub fn orient_consistently(&mut self) -> usize {
+        let welded = self.welded_indices();
+        let triangle_count = self.indices.len() / 3;
+        let corners =
+            |triangle: usize| [0, 1, 2].map(|i| welded[self.indices[triangle * 3 + i] as usize]);
+
+        let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+        for triangle in (0..triangle_count).filter(|triangle| !self.is_out_of_range(*triangle)) {
+            let corners = corners(triangle);
+            for i in 0..3 {
+                edges
+                    .entry(edge_key(corners[i], corners[(i + 1) % 3]))
+                    .or_default()
+                    .push(triangle);
+            }
+        }
+        // Whether a triangle runs along an edge from its lower to higher vertex
+        let forwards = |triangle: usize, (a, b): (u32, u32)| {
+            let corners = corners(triangle);
+            (0..3).any(|i| corners[i] == a && corners[(i + 1) % 3] == b)
+        };
+
+        let mut flip = vec![None; triangle_count];
+        for seed in 0..triangle_count {
+            if flip[seed].is_some() || self.is_out_of_range(seed) {
+                continue;
+            }
+
+            // Spread the seed's winding across the connected part of the mesh
+            flip[seed] = Some(false);
+            let mut component = vec![seed];
+            let mut closed = true;
+            let mut queue = VecDeque::from([seed]);
+            while let Some(triangle) = queue.pop_front() {
+                let triangle_corners = corners(triangle);
+                for i in 0..3 {
+                    let edge = edge_key(triangle_corners[i], triangle_corners[(i + 1) % 3]);
+                    let sharing = &edges[&edge];
+                    if sharing.len() == 1 {
+                        closed = false;
+                    }
+                    if sharing.len() != 2 {
+                        continue;
+                    }
+
+                    let other = if sharing[0] == triangle {
+                        sharing[1]
+                    } else {
+                        sharing[0]
+                    };
+                    if flip[other].is_none() {
+                        let direction = forwards(triangle, edge) != flip[triangle].unwrap();
+                        flip[other] = Some(forwards(other, edge) == direction);
+                        component.push(other);
+                        queue.push_back(other);
+                    }
+                }
+            }
+
+            // Closed parts should enclose a positive volume, open parts change as little
+            // as possible
+            let invert = if closed {
+                let volume: f32 = component
+                    .iter()
+                    .map(|triangle| {
+                        let [a, b, c] = self.face_positions(*triangle);
+                        let volume = a.dot(b.cross(c));
+                        if flip[*triangle].unwrap() {
+                            -volume
+                        } else {
+                            volume
+                        }
+                    })
+                    .sum();
+                volume < 0.0
+            } else {
+                let flipped = component
+                    .iter()
+                    .filter(|triangle| flip[**triangle].unwrap())
+                    .count();
+                flipped * 2 > component.len()
+            };
+            if invert {
+                for triangle in component {
+                    flip[triangle] = flip[triangle].map(|flip| !flip);
+                }
+            }
+        }
+
+        let mut flipped = 0;
+        for (triangle, flip) in flip.into_iter().enumerate() {
+            if flip == Some(true) {
+                self.indices.swap(triangle * 3 + 1, triangle * 3 + 2);
+                flipped += 1;
+            }
+        }
+        if flipped > 0 {
+            self.face_sizes.clear();
+        }
+
+        flipped
+    }
+
+    /// Close holes bounded by at most `max_edges` edges with a fan of triangles, returning
+    /// the number of holes filled
+    ///
+    /// Holes touching another hole at a vertex are left alone, as are triangles with out
+    /// of range indices
+    pub fn fill_holes(&mut self, max_edges: usize) -> usize {
+        let welded = self.welded_indices();
+        let triangles: Vec<&[u32]> = self
+            .indices
+            .chunks_exact(3)
+            .enumerate()
+            .filter(|(triangle, _)| !self.is_out_of_range(*triangle))
+            .map(|(_, indices)| indices)
+            .collect();
+        let mut directed = HashSet::new();
+        for triangle in triangles.iter() {
+            for i in 0..3 {
+                directed.insert((
+                    welded[triangle[i] as usize],
+                    welded[triangle[(i + 1) % 3] as usize],
+                ));
+            }
+        }
+
+        // A hole runs backwards along the boundary edges, keeping an actual vertex to
+        // use for each welded one
+        let mut hole_next: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+        for triangle in triangles {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                let (welded_a, welded_b) = (welded[a as usize], welded[b as usize]);
+                if !directed.contains(&(welded_b, welded_a)) {
+                    hole_next.entry(welded_b).or_default().push((welded_a, a));
+                }
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let mut filled = 0;
+        let starts: Vec<u32> = hole_next.keys().copied().collect();
+        for start in starts {
+            if visited.contains(&start) {
+                continue;
+            }
+
+            let mut hole = vec![];
+            let mut vertex = start;
+            let fillable = loop {
+                match hole_next.get(&vertex).map(|next| &next[..]) {
+                    Some([(next, index)]) => {
+                        if !visited.insert(vertex) {
+                            break vertex == start;
+                        }
+                        hole.push(*index);
+                        vertex = *next;
+                    }
+                    _ => break false,
+                }
+            };
+            if !fillable || hole.len() < 3 || hole.len() > max_edges {
+                continue;
+            }
+
+            // Each entry is the vertex at the end of an edge, rotate so they start at the
+            // beginning of the hole
+            hole.rotate_right(1);
+            for i in 1..hole.len() - 1 {
+                self.indices.extend([hole[0], hole[i], hole[i + 1]]);
+            }
+            filled += 1;
+        }
+        if filled > 0 {
+            self.face_sizes.clear();
+        }
+
+        filled
+    }
+
+    /// Each vertex mapped to the first vertex at exactly the same position
+    fn welded_indices(&self) -> Vec<u32> {
+        let mut first: HashMap<[u32; 3], u32> = HashMap::new();
+        self.vertices
+            .iter()
+            .enumerate()
+            .map(|(index, vertex)| {
+                *first
+                    .entry(vertex.position.map(f32::to_bits))
+                    .or_insert(index as u32)
+            })
+            .collect()
+    }
+
+    /// Whether a triangle has an index past the end of the vertices
+    fn is_out_of_range(&self, triangle: usize) -> bool {
+        self.indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .any(|index| *index as usize >= self.vertices.len())
+    }
+
+    /// Whether a triangle's area is negligible next to the length of its edges
+    fn is_degenerate(&self, triangle: usize) -> bool {
+        let [a, b, c] = self.face_positions(triangle);
+        let longest = (b - a)
+            .magnitude2()
+            .max((c - b).magnitude2())
+            .max((a - c).magnitude2());
+        let area = (b - a).cross(c - a).magnitude();
+        area <= longest * 1e-7
+    }
+
+    fn retain_triangles(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let indices = std::mem::take(&mut self.indices);
+        self.indices = indices
+            .chunks_exact(3)
+            .enumerate()
+            .filter(|(triangle, _)| keep(*triangle))
+            .flat_map(|(_, triangle)| triangle.to_vec())
+            .collect();
+        self.face_sizes.clear();
+    }
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn neighbour_cells(x: i64, y: i64, z: i64) -> impl Iterator<Item = [i64; 3]> {
+    (-1..=1).flat_map(move |dx| {
+        (-1..=1).flat_map(move |dy| {
+            (-1..=1).map(move |dz| {
+                [
+                    x.saturating_add(dx),
+                    y.saturating_add(dy),
+                    z.saturating_add(dz),
+                ]
+            })
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::load_mesh;
+
+    #[test]
+    pub fn loaded_meshes_are_valid() {
+        let sphere = load_mesh("sphere.obj").validate();
+        assert!(sphere.is_closed(), "{:?}", sphere);
+
+        let cube = load_mesh("cube.obj").validate();
+        assert!(cube.is_closed(), "{:?}", cube);
+    }
+
+    #[test]
+    pub fn detects_bad_triangles() {
+        let mut mesh = load_mesh("sphere.obj");
+        let triangle = mesh.indices[0..3].to_vec();
+        let vertex_count = mesh.vertices.len() as u32;
+        mesh.indices.extend([triangle[2], triangle[1], triangle[0]]);
+        mesh.indices.extend([triangle[0], triangle[0], triangle[1]]);
+        mesh.indices.extend([0, 1, vertex_count]);
+        mesh.vertices.push(mesh.vertices[0]);
+        mesh.vertices[vertex_count as usize].normal[0] = f32::NAN;
+
+        let triangles = mesh.indices.len() / 3;
+        let report = mesh.validate();
+        assert_eq!(report.duplicate_triangles, vec![triangles - 3]);
+        assert_eq!(
+            report.degenerate_triangles,
+            vec![triangles - 2, triangles - 1]
+        );
+        assert!(report.out_of_range_triangles.is_empty());
+        assert_eq!(report.non_finite_vertices, vec![vertex_count as usize]);
+        assert!(!report.is_valid());
+
+        assert_eq!(mesh.remove_degenerate_triangles(), 3);
+        assert_eq!(mesh.indices.len() / 3, triangles - 3);
+    }
+
+    #[test]
+    pub fn detects_out_of_range_and_unreferenced() {
+        let mut mesh = load_mesh("cube.obj");
+        let vertex_count = mesh.vertices.len() as u32;
+        mesh.vertices.push(mesh.vertices[0]);
+        mesh.indices.extend([0, 1, vertex_count + 5]);
+
+        let report = mesh.validate();
+        assert_eq!(
+            report.out_of_range_triangles,
+            vec![mesh.indices.len() / 3 - 1]
+        );
+        assert_eq!(report.unreferenced_vertices, vec![vertex_count as usize]);
+
+        assert_eq!(mesh.remove_degenerate_triangles(), 1);
+        assert_eq!(mesh.remove_unreferenced_vertices(), 1);
+        assert!(mesh.validate().is_closed());
+    }
+
+    #[test]
+    pub fn repairs_skip_out_of_range_triangles() {
+        let mut mesh = load_mesh("cube.obj");
+        let vertex_count = mesh.vertices.len() as u32;
+        mesh.indices.extend([0, vertex_count, vertex_count + 1]);
+        let indices = mesh.indices.clone();
+
+        assert_eq!(mesh.orient_consistently(), 0);
+        assert_eq!(mesh.fill_holes(8), 0);
+        assert_eq!(mesh.indices, indices);
+    }
+
+    #[test]
+    pub fn orients_flipped_triangles() {
+        let mut mesh = load_mesh("sphere.obj");
+        for triangle in (0..mesh.indices.len() / 3).step_by(7) {
+            mesh.indices.swap(triangle * 3 + 1, triangle * 3 + 2);
+        }
+        assert!(!mesh.validate().inconsistent_edges.is_empty());
+
+        let flipped = (0..mesh.indices.len() / 3).step_by(7).count();
+        assert_eq!(mesh.orient_consistently(), flipped);
+        assert!(mesh.validate().is_closed());
+
+        // Turning the whole sphere inside out makes it face outwards again
+        let original = mesh.indices.clone();
+        for triangle in mesh.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+        mesh.orient_consistently();
+        assert_eq!(mesh.indices, original);
+    }
+
+    #[test]
+    pub fn fills_small_holes() {
+        let mut mesh = load_mesh("sphere.obj");
+        let triangles = mesh.indices.len() / 3;
+
+        // Cutting out the triangles around a vertex leaves a hole bounded by its ring
+        let welded = mesh.welded_indices();
+        let centre = welded[mesh.indices[0] as usize];
+        let before = mesh.indices.len();
+        let indices = std::mem::take(&mut mesh.indices);
+        mesh.indices = indices
+            .chunks_exact(3)
+            .filter(|triangle| !triangle.iter().any(|i| welded[*i as usize] == centre))
+            .flat_map(|triangle| triangle.to_vec())
+            .collect();
+        let removed = (before - mesh.indices.len()) / 3;
+        assert!(mesh.remove_unreferenced_vertices() > 0);
+        let report = mesh.validate();
+        assert_eq!(report.boundary_edges.len(), removed);
+        assert!(report.is_valid());
+
+        assert_eq!(mesh.fill_holes(removed - 1), 0);
+        assert_eq!(mesh.fill_holes(removed), 1);
+        assert_eq!(mesh.indices.len() / 3, triangles - 2);
+        assert!(mesh.validate().is_closed());
+    }
+
+    #[test]
+    pub fn welds_nearby_vertices() {
+        let mut mesh = load_mesh("sphere.obj");
+        let vertex_count = mesh.vertices.len();
+        let triangles = mesh.indices.len() / 3;
+
+        // Give every corner its own slightly jittered vertex
+        let mut vertices = vec![];
+        for (corner, index) in mesh.indices.iter_mut().enumerate() {
+            let mut vertex = mesh.vertices[*index as usize];
+            vertex.position[0] += (corner % 5) as f32 * 1e-6;
+            vertices.push(vertex);
+            *index = corner as u32;
+        }
+        mesh.vertices = vertices;
+        assert_eq!(mesh.validate().boundary_edges.len(), triangles * 3);
+
+        mesh.weld(1e-4);
+        assert_eq!(mesh.vertices.len(), vertex_count);
+        assert!(mesh.validate().is_closed());
+    }
+
+    #[test]
+    pub fn weld_without_epsilon_merges_identical_vertices() {
+        let mut mesh = load_mesh("sphere.obj");
+        let vertex_count = mesh.vertices.len();
+        let mut nudged = mesh.vertices[0];
+        nudged.position[0] += 1e-6;
+        mesh.vertices.push(mesh.vertices[0]);
+        mesh.vertices.push(nudged);
+
+        for epsilon in [0.0, -1.0] {
+            let mut mesh = mesh.clone();
+            assert_eq!(mesh.weld(epsilon), 1);
+            assert_eq!(mesh.vertices.len(), vertex_count + 1);
+        }
+
+        // Positions far from the origin overflow the cells of a tiny epsilon, which then
+        // only merges identical vertices
+        let mut mesh = mesh.clone();
+        mesh.vertices[0].position = [f32::MAX, f32::MIN, 1e30];
+        let mut exact = mesh.clone();
+        assert_eq!(mesh.weld(f32::MIN_POSITIVE), exact.weld(0.0));
+        assert_eq!(mesh.indices, exact.indices);
+        assert_eq!(mesh.vertices[0].position, [f32::MAX, f32::MIN, 1e30]);
+    }
+}
@@ -0,0 +1,247 @@
+//! Scene graph placing models in the world
+//!
+//! Each node has a transform relative to its parent, so moving a node moves everything
+//! below it. Renderers own a graph whose nodes refer to the models loaded into them
+
+use cgmath::*;
+
+use crate::transform::Transform;
+
+/// A node in a scene graph
+#[derive(Debug, Clone)]
+pub struct SceneNode {
+    pub name: String,
+
+    /// Transform of the node relative to its parent
+    pub transform: Transform,
+
+    /// Index of the model drawn at this node, among the models of the graph's renderer
+    pub model: Option<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+impl SceneNode {
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[usize] {
+        &self.children
+    }
+}
+
+/// Hierarchy of nodes, indexed in the order they were added
+#[derive(Debug, Default, Clone)]
+pub struct SceneGraph {
+    nodes: Vec<SceneNode>,
+    roots: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    /// The new parent is the node itself or one of its descendants
+    Cycle,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node, at the root if it has no parent, returning its index
+    ///
+    /// Panics if the parent isn't in the graph
+    pub fn add_node(&mut self, name: &str, transform: Transform, parent: Option<usize>) -> usize {
+        let node = self.nodes.len();
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(node),
+            None => self.roots.push(node),
+        }
+        self.nodes.push(SceneNode {
+            name: name.to_string(),
+            transform,
+            model: None,
+            parent,
+            children: vec![],
+        });
+
+        node
+    }
+
+    pub fn node(&self, node: usize) -> &SceneNode {
+        &self.nodes[node]
+    }
+
+    pub fn node_mut(&mut self, node: usize) -> &mut SceneNode {
+        &mut self.nodes[node]
+    }
+
+    pub fn nodes(&self) -> &[SceneNode] {
+        &self.nodes
+    }
+
+    /// Nodes without a parent
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// First node with the name
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    /// Move a node & everything below it under a new parent, or to the root, keeping its
+    /// local transform
+    pub fn set_parent(&mut self, node: usize, parent: Option<usize>) -> Result<(), SceneError> {
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == node {
+                return Err(SceneError::Cycle);
+            }
+            ancestor = self.nodes[current].parent;
+        }
+
+        match self.nodes[node].parent {
+            Some(old) => self.nodes[old].children.retain(|child| *child != node),
+            None => self.roots.retain(|root| *root != node),
+        }
+        match parent {
+            Some(parent) => self.nodes[parent].children.push(node),
+            None => self.roots.push(node),
+        }
+        self.nodes[node].parent = parent;
+
+        Ok(())
+    }
+
+    /// Transform from the node's space to the world, combining every ancestor
+    pub fn world_matrix(&self, node: usize) -> Matrix4<f32> {
+        let mut matrix = self.nodes[node].transform.build_transform_matrix();
+        let mut ancestor = self.nodes[node].parent;
+        while let Some(current) = ancestor {
+            matrix = self.nodes[current].transform.build_transform_matrix() * matrix;
+            ancestor = self.nodes[current].parent;
+        }
+
+        matrix
+    }
+
+    /// World transform of every node, computed once per node from the roots down
+    pub fn world_matrices(&self) -> Vec<Matrix4<f32>> {
+        let mut world = vec![Matrix4::identity(); self.nodes.len()];
+        let mut stack: Vec<(usize, Matrix4<f32>)> = self
+            .roots
+            .iter()
+            .map(|root| (*root, Matrix4::identity()))
+            .collect();
+
+        while let Some((node, parent)) = stack.pop() {
+            world[node] = parent * self.nodes[node].transform.build_transform_matrix();
+            for child in self.nodes[node].children.iter() {
+                stack.push((*child, world[node]));
+            }
+        }
+
+        world
+    }
+
+    /// Model & world transform of every node drawing a model, in the order nodes were
+    /// added
+    pub fn draws(&self) -> Vec<(usize, Matrix4<f32>)> {
+        let world = self.world_matrices();
+        self.nodes
+            .iter()
+            .zip(world)
+            .filter_map(|(node, matrix)| node.model.map(|model| (model, matrix)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use crate::transform::Transform;
+
+    use super::*;
+
+    fn translation(x: f32, y: f32, z: f32) -> Transform {
+        Transform {
+            translate: [x, y, z],
+            ..Transform::new()
+        }
+    }
+
+    #[test]
+    pub fn world_matrices_combine_ancestors() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node("root", translation(1.0, 0.0, 0.0), None);
+        let child = scene.add_node(
+            "child",
+            Transform {
+                scale: [2.0; 3],
+                ..translation(0.0, 1.0, 0.0)
+            },
+            Some(root),
+        );
+        let grandchild = scene.add_node("grandchild", translation(0.0, 0.0, 1.0), Some(child));
+
+        let world = scene.world_matrices();
+        let origin = |matrix: Matrix4<f32>| Point3::from_vec(matrix.w.truncate());
+        assert_abs_diff_eq!(origin(world[root]), Point3::new(1.0, 0.0, 0.0));
+        assert_abs_diff_eq!(origin(world[child]), Point3::new(1.0, 1.0, 0.0));
+
+        // The child's scale applies to the grandchild's offset
+        assert_abs_diff_eq!(origin(world[grandchild]), Point3::new(1.0, 1.0, 2.0));
+        for node in [root, child, grandchild] {
+            assert_abs_diff_eq!(scene.world_matrix(node), world[node]);
+        }
+        assert_eq!(scene.find("child"), Some(child));
+    }
+
+    #[test]
+    pub fn reparenting() {
+        let mut scene = SceneGraph::new();
+        let a = scene.add_node("a", translation(1.0, 0.0, 0.0), None);
+        let b = scene.add_node("b", translation(0.0, 1.0, 0.0), None);
+        let c = scene.add_node("c", Transform::new(), Some(a));
+
+        scene.set_parent(c, Some(b)).unwrap();
+        assert_eq!(scene.node(a).children(), &[] as &[usize]);
+        assert_eq!(scene.node(b).children(), &[c]);
+        assert_eq!(scene.node(c).parent(), Some(b));
+        assert_abs_diff_eq!(
+            Point3::from_vec(scene.world_matrix(c).w.truncate()),
+            Point3::new(0.0, 1.0, 0.0)
+        );
+
+        scene.set_parent(b, None).unwrap();
+        scene.set_parent(a, Some(c)).unwrap();
+        assert_eq!(scene.roots(), &[b]);
+        assert!(matches!(
+            scene.set_parent(b, Some(a)),
+            Err(SceneError::Cycle)
+        ));
+        assert!(matches!(
+            scene.set_parent(b, Some(b)),
+            Err(SceneError::Cycle)
+        ));
+    }
+
+    #[test]
+    pub fn draws_only_nodes_with_models() {
+        let mut scene = SceneGraph::new();
+        let group = scene.add_node("group", translation(0.0, 0.0, 5.0), None);
+        let first = scene.add_node("first", Transform::new(), Some(group));
+        let second = scene.add_node("second", translation(1.0, 0.0, 0.0), None);
+        scene.node_mut(first).model = Some(1);
+        scene.node_mut(second).model = Some(0);
+
+        let draws = scene.draws();
+        assert_eq!(draws.len(), 2);
+        assert_eq!(draws[0].0, 1);
+        assert_abs_diff_eq!(draws[0].1, scene.world_matrix(first));
+        assert_eq!(draws[1].0, 0);
+    }
+}
@@ -1,11 +1,14 @@
 struct CameraUniform {
   view_proj: mat4x4<f32>;
+  view_position: vec4<f32>;
 };
 [[group(1), binding(0)]]
 var<uniform> camera: CameraUniform;
 
 struct TransformUniform {
   transform: mat4x4<f32>;
+  // Inverse transpose of the transform, keeping normals perpendicular under non-uniform scale
+  normal: mat4x4<f32>;
 };
 [[group(1), binding(1)]]
 var<uniform> transform: TransformUniform;
@@ -13,11 +16,16 @@ var<uniform> transform: TransformUniform;
 struct VertexInput {
   [[location(0)]] position: vec3<f32>;
   [[location(1)]] tex_coords: vec2<f32>;
+  [[location(2)]] normal: vec3<f32>;
+  [[location(3)]] tangent: vec4<f32>;
 };
 
 struct VertexOutput {
   [[builtin(position)]] clip_position: vec4<f32>;
   [[location(0)]] tex_coords: vec2<f32>;
+  [[location(1)]] world_position: vec3<f32>;
+  [[location(2)]] world_normal: vec3<f32>;
+  [[location(3)]] world_tangent: vec4<f32>;
 };
 
 [[stage(vertex)]]
@@ -25,9 +33,14 @@ fn vs_main(
  model: VertexInput,
 ) -> VertexOutput {
   var out: VertexOutput;
+  let world_position = transform.transform * vec4<f32>(model.position, 1.0);
   out.tex_coords = model.tex_coords;
-  out.clip_position = camera.view_proj * transform.transform * vec4<f32>(model.position, 1.0);
-  
+  out.world_position = world_position.xyz;
+  out.world_normal = (transform.normal * vec4<f32>(model.normal, 0.0)).xyz;
+  let world_tangent = transform.transform * vec4<f32>(model.tangent.xyz, 0.0);
+  out.world_tangent = vec4<f32>(world_tangent.xyz, model.tangent.w);
+  out.clip_position = camera.view_proj * world_position;
+
   return out;
 }
 
@@ -35,8 +48,127 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 [[group(0), binding(1)]]
 var s_diffuse: sampler;
+[[group(0), binding(2)]]
+var t_normal: texture_2d<f32>;
+[[group(0), binding(3)]]
+var s_normal: sampler;
+
+struct MaterialUniform {
+  ambient: vec3<f32>;
+  specular_exponent: f32;
+  diffuse: vec3<f32>;
+  textured: u32;
+  specular: vec3<f32>;
+  // 0 for no lighting, 1 for no highlights & 2 for both
+  illumination: u32;
+  emissive: vec3<f32>;
+  bump_multiplier: f32;
+  // 1 if the normal map's green channel points along the bitangent, -1 if against it
+  normal_green: f32;
+};
+[[group(0), binding(4)]]
+var<uniform> material: MaterialUniform;
+
+struct Light {
+  position: vec3<f32>;
+  // 0 for directional, 1 for point & 2 for spot lights
+  kind: u32;
+  direction: vec3<f32>;
+  range: f32;
+  colour: vec3<f32>;
+  cos_inner: f32;
+  cos_outer: f32;
+};
+
+struct LightingUniform {
+  lights: array<Light, 8>;
+  ambient: vec3<f32>;
+  count: u32;
+  // 0 for Phong & 1 for Blinn-Phong
+  shading_model: u32;
+};
+[[group(2), binding(0)]]
+var<uniform> lighting: LightingUniform;
+
+// Tilt the surface normal by a tangent space normal map sample, mirrors `perturb_normal`
+// in light.rs
+fn perturb_normal(normal: vec3<f32>, tangent: vec4<f32>, mapped: vec3<f32>) -> vec3<f32> {
+  let n = normalize(normal);
+  let orthogonal = tangent.xyz - n * dot(n, tangent.xyz);
+  if (dot(orthogonal, orthogonal) < 0.00000001) {
+    return n;
+  }
+
+  let t = normalize(orthogonal);
+  let b = cross(n, t) * tangent.w;
+  let tilt = (mapped * 2.0 - 1.0) * vec3<f32>(
+    material.bump_multiplier,
+    material.bump_multiplier * material.normal_green,
+    1.0
+  );
+  return normalize(t * tilt.x + b * tilt.y + n * tilt.z);
+}
 
+// Mirrors `Lighting::shade` in light.rs
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-  return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+  let sample = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+  let mapped = textureSample(t_normal, s_normal, in.tex_coords).xyz;
+  if (lighting.count == 0u) {
+    if (material.textured == 1u) {
+      return sample;
+    }
+    return vec4<f32>(material.diffuse, 1.0);
+  }
+
+  let diffuse = sample.rgb * material.diffuse;
+  if (material.illumination == 0u) {
+    return vec4<f32>(diffuse, sample.a);
+  }
+
+  let normal = perturb_normal(in.world_normal, in.world_tangent, mapped);
+  let view = normalize(camera.view_position.xyz - in.world_position);
+  var colour = material.emissive + material.ambient * lighting.ambient * diffuse;
+
+  for (var i: u32 = 0u; i < lighting.count; i = i + 1u) {
+    let light = lighting.lights[i];
+    var direction = -light.direction;
+    var radiance = light.colour;
+    if (light.kind != 0u) {
+      let offset = light.position - in.world_position;
+      let distance2 = max(dot(offset, offset), 0.00000001);
+      direction = offset / sqrt(distance2);
+
+      var window = 1.0;
+      if (light.range > 0.0) {
+        let ratio = distance2 / (light.range * light.range);
+        let falloff = clamp(1.0 - ratio * ratio, 0.0, 1.0);
+        window = falloff * falloff;
+      }
+      radiance = radiance * window / distance2;
+
+      if (light.kind == 2u) {
+        let cone = dot(-direction, light.direction);
+        radiance = radiance * smoothStep(light.cos_outer, light.cos_inner, cone);
+      }
+    }
+
+    let lambert = dot(normal, direction);
+    if (lambert <= 0.0) {
+      continue;
+    }
+
+    var reflected = diffuse * lambert;
+    if (material.illumination == 2u) {
+      var alignment = dot(normal, normalize(direction + view));
+      if (lighting.shading_model == 0u) {
+        alignment = dot(normal * 2.0 * lambert - direction, view);
+      }
+      let highlight = pow(max(alignment, 0.0), max(material.specular_exponent, 1.0));
+      reflected = reflected + material.specular * highlight;
+    }
+    colour = colour + reflected * radiance;
+  }
+
+  return vec4<f32>(colour, sample.a);
+}
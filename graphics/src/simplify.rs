@@ -0,0 +1,935 @@
+//! Mesh simplification using quadric error metrics
+//!
+//! See "Surface Simplification Using Quadric Error Metrics" (Garland and Heckbert 1997)
+//! and "Simplifying Surfaces with Color and Texture using Quadric Error Metrics"
+//! (Garland and Heckbert 1998) for attribute quadrics
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use crate::model::{Mesh, ModelVertex};
+
+/// Controls when simplification stops and what it tries to preserve
+#[derive(Debug, Clone)]
+pub struct SimplifyOptions {
+    /// Stop once the mesh has at most this many triangles
+    pub target_triangles: usize,
+
+    /// Stop once the cheapest collapse would add more than this much error, measured as
+    /// the sum of squared distances from the original surface planes
+    pub max_error: Option<f64>,
+
+    /// How strongly open boundaries are kept in place
+    pub boundary_weight: f64,
+
+    /// Weight of texture coordinates in the error metric, `None` ignores them and moved
+    /// vertices keep their original texture coordinates
+    pub texture_coord_weight: Option<f64>,
+
+    /// Weight of normals in the error metric, `None` ignores them and moved vertices keep
+    /// their original normals
+    pub normal_weight: Option<f64>,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> Self {
+        SimplifyOptions {
+            target_triangles: 0,
+            max_error: None,
+            boundary_weight: 1000.0,
+            texture_coord_weight: None,
+            normal_weight: None,
+        }
+    }
+}
+
+impl Mesh {
+    /// Reduce the triangle count by repeatedly collapsing the edge that changes the
+    /// surface the least
+    ///
+    /// Vertices that were split for texture or normal seams only move along the seam, all
+    /// copies at once onto the next vertex of the seam, so the seams can't open up. Where
+    /// seams meet, the vertex can't move at all. Collapses that would flip a triangle or
+    /// make the mesh non-manifold are skipped, so the target may not be reached
+    pub fn simplify(&self, options: &SimplifyOptions) -> Mesh {
+        let mut simplifier = Simplifier::new(self, options);
+        simplifier.run(options);
+        simplifier.export(self)
+    }
+}
+
+/// A general quadric `vᵀAv + 2bᵀv + c` over the position & attribute space
+#[derive(Debug, Clone)]
+struct Quadric {
+    a: Vec<f64>,
+    b: Vec<f64>,
+    c: f64,
+}
+
+impl Quadric {
+    fn zero(dimension: usize) -> Self {
+        Quadric {
+            a: vec![0.0; dimension * dimension],
+            b: vec![0.0; dimension],
+            c: 0.0,
+        }
+    }
+
+    /// Quadric measuring squared distance from the plane of a triangle, extended to
+    /// however many attribute dimensions the points have
+    fn from_triangle(p: &[f64], q: &[f64], r: &[f64]) -> Option<Self> {
+        let n = p.len();
+        let e1 = normalize(&sub(q, p))?;
+        let to_r = sub(r, p);
+        let e2 = normalize(&sub(&to_r, &scale(&e1, dot(&e1, &to_r))))?;
+
+        let mut quadric = Quadric::zero(n);
+        for i in 0..n {
+            for j in 0..n {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                quadric.a[i * n + j] = identity - e1[i] * e1[j] - e2[i] * e2[j];
+            }
+        }
+
+        let (p_e1, p_e2) = (dot(p, &e1), dot(p, &e2));
+        for i in 0..n {
+            quadric.b[i] = p_e1 * e1[i] + p_e2 * e2[i] - p[i];
+        }
+        quadric.c = dot(p, p) - p_e1 * p_e1 - p_e2 * p_e2;
+
+        Some(quadric)
+    }
+
+    fn scale(&mut self, weight: f64) {
+        self.a.iter_mut().for_each(|a| *a *= weight);
+        self.b.iter_mut().for_each(|b| *b *= weight);
+        self.c *= weight;
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, other) in self.a.iter_mut().zip(other.a.iter()) {
+            *a += other;
+        }
+        for (b, other) in self.b.iter_mut().zip(other.b.iter()) {
+            *b += other;
+        }
+        self.c += other.c;
+    }
+
+    fn error(&self, v: &[f64]) -> f64 {
+        let n = v.len();
+        let mut error = self.c;
+        for (i, row) in self.a.chunks_exact(n).enumerate() {
+            error += v[i] * dot(row, v) + 2.0 * self.b[i] * v[i];
+        }
+
+        // Rounding can push a perfect fit slightly negative
+        error.max(0.0)
+    }
+
+    /// The point minimising the error, if the quadric isn't singular
+    fn minimum(&self) -> Option<Vec<f64>> {
+        let n = self.b.len();
+        let mut a = self.a.clone();
+        let mut x: Vec<f64> = self.b.iter().map(|b| -b).collect();
+
+        // Gaussian elimination with partial pivoting
+        for column in 0..n {
+            let pivot = (column..n)
+                .max_by(|i, j| a[i * n + column].abs().total_cmp(&a[j * n + column].abs()))?;
+            if a[pivot * n + column].abs() < 1e-10 {
+                return None;
+            }
+            for k in 0..n {
+                a.swap(column * n + k, pivot * n + k);
+            }
+            x.swap(column, pivot);
+
+            for row in column + 1..n {
+                let factor = a[row * n + column] / a[column * n + column];
+                for k in column..n {
+                    a[row * n + k] -= factor * a[column * n + k];
+                }
+                x[row] -= factor * x[column];
+            }
+        }
+        for column in (0..n).rev() {
+            for k in column + 1..n {
+                x[column] -= a[column * n + k] * x[k];
+            }
+            x[column] /= a[column * n + column];
+        }
+
+        Some(x)
+    }
+}
+
+/// A candidate edge collapse, ordered so the cheapest is at the top of the heap
+#[derive(Debug)]
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    target: Vec<f64>,
+
+    /// Copies of `from` split for a seam, each collapsing onto the copy of `to` on the
+    /// same side of the seam
+    twins: Vec<[usize; 2]>,
+
+    /// Versions of both vertices of each collapsing edge when the collapse was computed,
+    /// to spot stale entries
+    versions: Vec<(u32, u32)>,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+struct Simplifier {
+    /// Position followed by any weighted attributes for each vertex
+    points: Vec<Vec<f64>>,
+    quadrics: Vec<Quadric>,
+    faces: Vec<[usize; 3]>,
+    face_alive: Vec<bool>,
+    vertex_faces: Vec<Vec<usize>>,
+
+    /// Vertices sharing each position & the position of each vertex, vertices sharing
+    /// a position are split for a seam
+    wedges: Vec<Vec<usize>>,
+    position_ids: Vec<usize>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+    triangle_count: usize,
+    texture_coord_weight: Option<f64>,
+    normal_weight: Option<f64>,
+}
+
+impl Simplifier {
+    fn new(mesh: &Mesh, options: &SimplifyOptions) -> Self {
+        let points: Vec<Vec<f64>> = mesh
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let mut point: Vec<f64> = vertex.position.iter().map(|p| *p as f64).collect();
+                if let Some(weight) = options.texture_coord_weight {
+                    point.extend(vertex.texture_coords.iter().map(|t| *t as f64 * weight));
+                }
+                if let Some(weight) = options.normal_weight {
+                    point.extend(vertex.normal.iter().map(|n| *n as f64 * weight));
+                }
+                point
+            })
+            .collect();
+        let dimension = 3
+            + options.texture_coord_weight.map_or(0, |_| 2)
+            + options.normal_weight.map_or(0, |_| 3);
+
+        let faces: Vec<[usize; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|face| [face[0] as usize, face[1] as usize, face[2] as usize])
+            .filter(|[a, b, c]| a != b && b != c && c != a)
+            .collect();
+
+        let mut vertex_faces = vec![vec![]; points.len()];
+        let mut quadrics = vec![Quadric::zero(dimension); points.len()];
+        for (f, face) in faces.iter().enumerate() {
+            let quadric =
+                Quadric::from_triangle(&points[face[0]], &points[face[1]], &points[face[2]]);
+            for vertex in face {
+                vertex_faces[*vertex].push(f);
+                if let Some(quadric) = &quadric {
+                    quadrics[*vertex].add(quadric);
+                }
+            }
+        }
+
+        // Count how often each edge is used, an edge used once is on a boundary
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for face in faces.iter() {
+            for i in 0..3 {
+                let (a, b) = (face[i], face[(i + 1) % 3]);
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+
+        // Vertices sharing a position are split for a seam, moving one alone would crack it
+        let mut positions: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut wedges: Vec<Vec<usize>> = vec![];
+        let mut position_ids = Vec::with_capacity(mesh.vertices.len());
+        for (v, vertex) in mesh.vertices.iter().enumerate() {
+            let id = *positions
+                .entry(vertex.position.map(f32::to_bits))
+                .or_insert_with(|| {
+                    wedges.push(vec![]);
+                    wedges.len() - 1
+                });
+            wedges[id].push(v);
+            position_ids.push(id);
+        }
+
+        // Planes through each boundary edge, perpendicular to its face, keep boundaries &
+        // seams from shrinking. The plane shares the edge's attributes so it doesn't pull
+        // them apart
+        for face in faces.iter() {
+            let corners = face.map(|v| position(&points[v]));
+            let normal = normalize(&cross(
+                &sub(&corners[1], &corners[0]),
+                &sub(&corners[2], &corners[0]),
+            ));
+            let normal = match normal {
+                Some(normal) => normal,
+                None => continue,
+            };
+
+            for i in 0..3 {
+                let (a, b) = (face[i], face[(i + 1) % 3]);
+                if edges[&(a.min(b), a.max(b))] != 1 {
+                    continue;
+                }
+
+                let edge = sub(&corners[(i + 1) % 3], &corners[i]);
+                let length_squared = dot(&edge, &edge);
+                let mut above = points[a].clone();
+                for (p, n) in above.iter_mut().zip(normal.iter()) {
+                    *p += n * length_squared.sqrt();
+                }
+
+                if let Some(mut quadric) = Quadric::from_triangle(&points[a], &points[b], &above) {
+                    quadric.scale(options.boundary_weight * length_squared);
+                    quadrics[a].add(&quadric);
+                    quadrics[b].add(&quadric);
+                }
+            }
+        }
+
+        let mut simplifier = Simplifier {
+            triangle_count: faces.len(),
+            face_alive: vec![true; faces.len()],
+            versions: vec![0; points.len()],
+            heap: BinaryHeap::new(),
+            points,
+            quadrics,
+            faces,
+            vertex_faces,
+            wedges,
+            position_ids,
+            texture_coord_weight: options.texture_coord_weight,
+            normal_weight: options.normal_weight,
+        };
+        for (a, b) in edges.keys() {
+            simplifier.push_collapse(*a, *b);
+        }
+
+        simplifier
+    }
+
+    fn run(&mut self, options: &SimplifyOptions) {
+        while self.triangle_count > options.target_triangles {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let edges =
+                std::iter::once([collapse.from, collapse.to]).chain(collapse.twins.iter().copied());
+            if !edges
+                .zip(collapse.versions.iter())
+                .all(|([from, to], versions)| (self.versions[from], self.versions[to]) == *versions)
+            {
+                continue;
+            }
+            if let Some(max_error) = options.max_error {
+                if collapse.cost > max_error {
+                    break;
+                }
+            }
+
+            if self.is_valid_collapse(&collapse) {
+                self.collapse(collapse);
+            }
+        }
+    }
+
+    /// Vertex is split for a seam, so it can only move along with its twins
+    fn is_locked(&self, vertex: usize) -> bool {
+        self.wedges[self.position_ids[vertex]].len() > 1
+    }
+
+    /// Queue the cheapest way of collapsing the edge between two vertices
+    fn push_collapse(&mut self, a: usize, b: usize) {
+        if self.is_locked(a) && self.is_locked(b) {
+            self.push_seam_collapse(a, b);
+            return;
+        }
+
+        let mut quadric = self.quadrics[a].clone();
+        quadric.add(&self.quadrics[b]);
+
+        // A locked vertex can't move, so the other vertex has to collapse onto it
+        let candidates: Vec<(usize, usize, Vec<f64>)> = if self.is_locked(a) {
+            vec![(b, a, self.points[a].clone())]
+        } else if self.is_locked(b) {
+            vec![(a, b, self.points[b].clone())]
+        } else {
+            let midpoint = scale(&add(&self.points[a], &self.points[b]), 0.5);
+            vec![
+                (a, b, self.points[b].clone()),
+                (b, a, self.points[a].clone()),
+                (a, b, midpoint),
+            ]
+        };
+        let (mut from, mut to, mut target) = candidates
+            .into_iter()
+            .min_by(|(_, _, x), (_, _, y)| quadric.error(x).total_cmp(&quadric.error(y)))
+            .unwrap();
+
+        // Only move to the optimal point if it's clearly better, so corners & flat areas
+        // keep their exact original positions
+        if !self.is_locked(a) && !self.is_locked(b) {
+            if let Some(minimum) = quadric.minimum() {
+                if quadric.error(&minimum) < quadric.error(&target) - 1e-12 {
+                    (from, to, target) = (a, b, minimum);
+                }
+            }
+        }
+
+        self.heap.push(Collapse {
+            cost: quadric.error(&target),
+            versions: vec![(self.versions[from], self.versions[to])],
+            from,
+            to,
+            target,
+            twins: vec![],
+        });
+    }
+
+    /// Queue the cheaper direction of sliding a seam vertex & its twins onto the next
+    /// vertex along the seam, if every twin has a matching edge to follow
+    fn push_seam_collapse(&mut self, a: usize, b: usize) {
+        let collapse = [(a, b), (b, a)]
+            .into_iter()
+            .filter_map(|(from, to)| {
+                let twins = self.seam_twins(from, to)?;
+                let cost = std::iter::once([from, to])
+                    .chain(twins.iter().copied())
+                    .map(|[from, to]| {
+                        let mut quadric = self.quadrics[from].clone();
+                        quadric.add(&self.quadrics[to]);
+                        quadric.error(&self.points[to])
+                    })
+                    .sum::<f64>();
+                Some(Collapse {
+                    cost,
+                    versions: std::iter::once([from, to])
+                        .chain(twins.iter().copied())
+                        .map(|[from, to]| (self.versions[from], self.versions[to]))
+                        .collect(),
+                    from,
+                    to,
+                    target: self.points[to].clone(),
+                    twins,
+                })
+            })
+            .min_by(|x, y| x.cost.total_cmp(&y.cost));
+
+        if let Some(collapse) = collapse {
+            self.heap.push(collapse);
+        }
+    }
+
+    /// For each other copy of `from`, the copy of `to` it shares an edge with
+    fn seam_twins(&self, from: usize, to: usize) -> Option<Vec<[usize; 2]>> {
+        let targets = &self.wedges[self.position_ids[to]];
+        let mut used = vec![to];
+        let mut twins = vec![];
+        for twin in self.wedges[self.position_ids[from]].iter() {
+            if *twin == from || self.vertex_faces[*twin].is_empty() {
+                continue;
+            }
+
+            let neighbours = self.neighbours(*twin);
+            let target = *targets
+                .iter()
+                .find(|target| neighbours.contains(target) && !used.contains(target))?;
+            used.push(target);
+            twins.push([*twin, target]);
+        }
+
+        Some(twins)
+    }
+
+    fn is_valid_collapse(&self, collapse: &Collapse) -> bool {
+        let edges: Vec<[usize; 2]> = std::iter::once([collapse.from, collapse.to])
+            .chain(collapse.twins.iter().copied())
+            .collect();
+        if !edges.iter().all(|[from, to]| {
+            let target = if *to == collapse.to {
+                &collapse.target
+            } else {
+                &self.points[*to]
+            };
+            self.is_valid_edge_collapse(*from, *to, target)
+        }) {
+            return false;
+        }
+
+        // Once seams are welded shut, the edges must still meet the link condition, or
+        // copies on either side of a seam could pinch together
+        let position_neighbours = |position: usize| -> HashSet<usize> {
+            self.wedges[position]
+                .iter()
+                .flat_map(|vertex| self.neighbours(*vertex))
+                .map(|vertex| self.position_ids[vertex])
+                .collect()
+        };
+        let opposite: HashSet<usize> = edges
+            .iter()
+            .flat_map(|[from, to]| {
+                self.vertex_faces[*from]
+                    .iter()
+                    .map(|face| self.faces[*face])
+                    .filter(move |corners| corners.contains(to))
+                    .flatten()
+                    .filter(move |v| v != from && v != to)
+            })
+            .map(|vertex| self.position_ids[vertex])
+            .collect();
+        let (from, to) = (
+            self.position_ids[collapse.from],
+            self.position_ids[collapse.to],
+        );
+        let from_neighbours = position_neighbours(from);
+        if !position_neighbours(to)
+            .intersection(&from_neighbours)
+            .all(|position| opposite.contains(position))
+        {
+            return false;
+        }
+
+        // Nor may two faces end up on the same three positions
+        let face_positions = |position: usize| {
+            self.wedges[position]
+                .iter()
+                .flat_map(|vertex| self.vertex_faces[*vertex].iter())
+                .map(|face| self.faces[*face].map(|v| self.position_ids[v]))
+                .filter(|corners| !(corners.contains(&from) && corners.contains(&to)))
+                .map(|corners| {
+                    let mut corners = corners.map(|p| if p == from { to } else { p });
+                    corners.sort_unstable();
+                    corners
+                })
+        };
+        let to_faces: HashSet<[usize; 3]> = face_positions(to).collect();
+        face_positions(from).all(|corners| !to_faces.contains(&corners))
+    }
+
+    fn is_valid_edge_collapse(&self, from: usize, to: usize, target: &[f64]) -> bool {
+        // Link condition, the only vertices adjacent to both ends of the edge must be the
+        // ones opposite it, otherwise the collapse pinches the surface
+        let mut shared_faces = 0;
+        let mut opposite: HashSet<usize> = HashSet::new();
+        for face in self.vertex_faces[from].iter() {
+            if self.faces[*face].contains(&to) {
+                shared_faces += 1;
+                opposite.extend(
+                    self.faces[*face]
+                        .into_iter()
+                        .filter(|v| *v != from && *v != to),
+                );
+            }
+        }
+        if shared_faces == 0 {
+            return false;
+        }
+        let from_neighbours = self.neighbours(from);
+        let common = self
+            .neighbours(to)
+            .intersection(&from_neighbours)
+            .filter(|v| !opposite.contains(*v))
+            .count();
+        if common > 0 {
+            return false;
+        }
+
+        // No face may end up on the same three vertices as another, as happens when
+        // collapsing an edge of a tetrahedron
+        let sorted = |mut corners: [usize; 3]| {
+            corners.sort_unstable();
+            corners
+        };
+        let to_faces: HashSet<[usize; 3]> = self.vertex_faces[to]
+            .iter()
+            .map(|face| self.faces[*face])
+            .filter(|corners| !corners.contains(&from))
+            .map(sorted)
+            .collect();
+        for face in self.vertex_faces[from].iter() {
+            let corners = self.faces[*face];
+            if !corners.contains(&to)
+                && to_faces.contains(&sorted(corners.map(|v| if v == from { to } else { v })))
+            {
+                return false;
+            }
+        }
+
+        // No remaining triangle may flip or become degenerate
+        let target = position(target);
+        for vertex in [from, to] {
+            for face in self.vertex_faces[vertex].iter() {
+                let corners = self.faces[*face];
+                if corners.contains(&from) && corners.contains(&to) {
+                    continue;
+                }
+
+                let before = corners.map(|v| position(&self.points[v]));
+                let after = corners.map(|v| {
+                    if v == vertex {
+                        target
+                    } else {
+                        position(&self.points[v])
+                    }
+                });
+                let old_normal = cross(&sub(&before[1], &before[0]), &sub(&before[2], &before[0]));
+                let new_normal = cross(&sub(&after[1], &after[0]), &sub(&after[2], &after[0]));
+                match (normalize(&old_normal), normalize(&new_normal)) {
+                    (Some(old_normal), Some(new_normal)) => {
+                        if dot(&old_normal, &new_normal) < 0.1 {
+                            return false;
+                        }
+                    }
+                    (None, _) => {}
+                    (_, None) => return false,
+                }
+            }
+        }
+
+        true
+    }
+
+    fn collapse(&mut self, collapse: Collapse) {
+        for [from, to] in collapse.twins.iter() {
+            let target = self.points[*to].clone();
+            self.collapse_edge(*from, *to, target);
+        }
+        self.collapse_edge(collapse.from, collapse.to, collapse.target);
+
+        // Every edge around the merged vertices now has a different cost
+        let merged = std::iter::once(collapse.to).chain(collapse.twins.iter().map(|[_, to]| *to));
+        let neighbours: HashSet<usize> = merged.flat_map(|to| self.neighbours(to)).collect();
+        for neighbour in neighbours.iter() {
+            self.versions[*neighbour] += 1;
+        }
+        for neighbour in neighbours {
+            for second in self.neighbours(neighbour) {
+                self.push_collapse(neighbour, second);
+            }
+        }
+    }
+
+    fn collapse_edge(&mut self, from: usize, to: usize, target: Vec<f64>) {
+        let from_faces = std::mem::take(&mut self.vertex_faces[from]);
+        for face in from_faces {
+            if self.faces[face].contains(&to) {
+                // Triangles along the edge vanish
+                self.face_alive[face] = false;
+                self.triangle_count -= 1;
+                for vertex in self.faces[face] {
+                    self.vertex_faces[vertex].retain(|f| *f != face);
+                }
+            } else {
+                for vertex in self.faces[face].iter_mut() {
+                    if *vertex == from {
+                        *vertex = to;
+                    }
+                }
+                self.vertex_faces[to].push(face);
+            }
+        }
+
+        let from_quadric = self.quadrics[from].clone();
+        self.quadrics[to].add(&from_quadric);
+        self.points[to] = target;
+        self.versions[from] += 1;
+        self.versions[to] += 1;
+    }
+
+    fn neighbours(&self, vertex: usize) -> HashSet<usize> {
+        self.vertex_faces[vertex]
+            .iter()
+            .flat_map(|face| self.faces[*face])
+            .filter(|v| *v != vertex)
+            .collect()
+    }
+
+    /// Build the simplified mesh, dropping vertices no longer used by any triangle
+    fn export(&self, original: &Mesh) -> Mesh {
+        let mut vertex_map = HashMap::new();
+        let mut mesh = Mesh {
+            name: original.name.clone(),
+            material: original.material,
+            ..Default::default()
+        };
+
+        for (face, alive) in self.faces.iter().zip(self.face_alive.iter()) {
+            if !alive {
+                continue;
+            }
+
+            for vertex in face {
+                let index = *vertex_map.entry(*vertex).or_insert_with(|| {
+                    mesh.vertices
+                        .push(self.export_vertex(&original.vertices[*vertex], *vertex));
+                    mesh.vertices.len() - 1
+                });
+                mesh.indices.push(index as u32);
+            }
+        }
+
+        mesh.compute_tangents();
+        mesh
+    }
+
+    fn export_vertex(&self, original: &ModelVertex, vertex: usize) -> ModelVertex {
+        let point = &self.points[vertex];
+        let mut exported = *original;
+        exported.position = position(point).map(|p| p as f32);
+
+        let mut attributes = point[3..].iter();
+        if let Some(weight) = self.texture_coord_weight {
+            for coord in exported.texture_coords.iter_mut() {
+                *coord = (attributes.next().unwrap() / weight) as f32;
+            }
+        }
+        if let Some(weight) = self.normal_weight {
+            let normal: Vec<f64> = attributes.take(3).map(|n| n / weight).collect();
+            if let Some(normal) = normalize(&normal) {
+                exported.normal = [normal[0] as f32, normal[1] as f32, normal[2] as f32];
+            }
+        }
+
+        exported
+    }
+}
+
+fn position(point: &[f64]) -> [f64; 3] {
+    [point[0], point[1], point[2]]
+}
+
+fn add(a: &[f64], b: &[f64]) -> Vec<f64> {
+    a.iter().zip(b).map(|(a, b)| a + b).collect()
+}
+
+fn sub(a: &[f64], b: &[f64]) -> Vec<f64> {
+    a.iter().zip(b).map(|(a, b)| a - b).collect()
+}
+
+fn scale(a: &[f64], s: f64) -> Vec<f64> {
+    a.iter().map(|a| a * s).collect()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn cross(a: &[f64], b: &[f64]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: &[f64]) -> Option<Vec<f64>> {
+    let length = dot(a, a).sqrt();
+    if length < 1e-12 {
+        None
+    } else {
+        Some(scale(a, 1.0 / length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::SimplifyOptions;
+    use crate::model::Mesh;
+    use crate::test_util::{grid, load_mesh};
+
+    fn triangle_count(mesh: &Mesh) -> usize {
+        mesh.indices.len() / 3
+    }
+
+    fn bounds(mesh: &Mesh) -> ([f32; 3], [f32; 3]) {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for vertex in mesh.vertices.iter() {
+            for i in 0..3 {
+                min[i] = min[i].min(vertex.position[i]);
+                max[i] = max[i].max(vertex.position[i]);
+            }
+        }
+        (min, max)
+    }
+
+    fn welded_count(mesh: &Mesh) -> usize {
+        let positions: HashSet<[u32; 3]> = mesh
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position.map(f32::to_bits))
+            .collect();
+        positions.len()
+    }
+
+    /// Check that, once vertices at the same position are merged, every edge is shared
+    /// by exactly two triangles winding it in opposite directions
+    fn assert_closed_manifold(mesh: &Mesh) {
+        let mut positions: HashMap<[u32; 3], usize> = HashMap::new();
+        let welded: Vec<usize> = mesh
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let next = positions.len();
+                *positions
+                    .entry(vertex.position.map(f32::to_bits))
+                    .or_insert(next)
+            })
+            .collect();
+
+        let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+        for face in mesh.indices.chunks_exact(3) {
+            let face = [0, 1, 2].map(|i| welded[face[i] as usize]);
+            assert!(face[0] != face[1] && face[1] != face[2] && face[2] != face[0]);
+            for i in 0..3 {
+                *edges.entry((face[i], face[(i + 1) % 3])).or_default() += 1;
+            }
+        }
+        for ((a, b), count) in edges.iter() {
+            assert_eq!(*count, 1, "edge {} {} is used more than once", a, b);
+            assert!(edges.contains_key(&(*b, *a)), "edge {} {} is open", a, b);
+        }
+    }
+
+    #[test]
+    pub fn sphere_reaches_target_and_stays_manifold() {
+        let mesh = load_mesh("sphere.obj");
+        let target = triangle_count(&mesh) / 4;
+        let simplified = mesh.simplify(&SimplifyOptions {
+            target_triangles: target,
+            ..Default::default()
+        });
+
+        assert!(triangle_count(&simplified) <= target);
+        assert!(triangle_count(&simplified) > target / 2);
+        assert_closed_manifold(&simplified);
+    }
+
+    #[test]
+    pub fn flat_grid_collapses_to_corners() {
+        let mesh = grid(4);
+        let simplified = mesh.simplify(&SimplifyOptions {
+            max_error: Some(1e-6),
+            ..Default::default()
+        });
+
+        // The boundary keeps the square's shape, so only the corners are left
+        assert_eq!(triangle_count(&simplified), 2);
+        assert_eq!(simplified.vertices.len(), 4);
+        for vertex in simplified.vertices.iter() {
+            for coord in &vertex.position[..2] {
+                assert!(coord.abs() < 1e-6 || (coord - 4.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    pub fn texture_coords_are_interpolated() {
+        let mut mesh = grid(4);
+
+        // Move the centre vertex off the grid, so it can't be removed for free
+        mesh.vertices[12].position[2] = 0.5;
+        let simplified = mesh.simplify(&SimplifyOptions {
+            target_triangles: 4,
+            texture_coord_weight: Some(1.0),
+            ..Default::default()
+        });
+
+        assert!(triangle_count(&simplified) <= 4);
+        for vertex in simplified.vertices.iter() {
+            let [x, y, _] = vertex.position;
+            let [u, v] = vertex.texture_coords;
+            assert!((x - u).abs() < 1e-3 && (y - v).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    pub fn seams_slide_without_opening() {
+        let mesh = load_mesh("cube.obj");
+        let seams = mesh.vertices.len() - welded_count(&mesh);
+        assert!(seams > 0);
+
+        // Seam vertices can move along their seam, so the cube isn't held at the seams
+        let target = triangle_count(&mesh) / 8;
+        let simplified = mesh.simplify(&SimplifyOptions {
+            target_triangles: target,
+            ..Default::default()
+        });
+        assert!(triangle_count(&simplified) <= target);
+        assert!(simplified.vertices.len() > welded_count(&simplified));
+        assert_closed_manifold(&simplified);
+    }
+
+    #[test]
+    pub fn cube_keeps_shape() {
+        let mesh = load_mesh("cube.obj");
+        let simplified = mesh.simplify(&SimplifyOptions {
+            target_triangles: triangle_count(&mesh) / 2,
+            ..Default::default()
+        });
+
+        assert!(triangle_count(&simplified) < triangle_count(&mesh));
+        assert_closed_manifold(&simplified);
+        let (min, max) = bounds(&simplified);
+        let (original_min, original_max) = bounds(&mesh);
+        for i in 0..3 {
+            assert!((min[i] - original_min[i]).abs() < 0.05);
+            assert!((max[i] - original_max[i]).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    pub fn gargoyle_reduction() {
+        let mesh = load_mesh("garg.obj");
+        let simplified = mesh.simplify(&SimplifyOptions {
+            target_triangles: 5000,
+            ..Default::default()
+        });
+
+        assert!(triangle_count(&simplified) <= 5000);
+        let (min, max) = bounds(&simplified);
+        let (original_min, original_max) = bounds(&mesh);
+        for i in 0..3 {
+            let size = original_max[i] - original_min[i];
+            assert!((min[i] - original_min[i]).abs() < size * 0.05);
+            assert!((max[i] - original_max[i]).abs() < size * 0.05);
+        }
+    }
+}
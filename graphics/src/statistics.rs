@@ -0,0 +1,159 @@
+//! Summary statistics for meshes & models
+
+use cgmath::*;
+
+use crate::{
+    bounds::Aabb,
+    model::{Mesh, Model},
+};
+
+/// Size & shape of a single mesh
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshStatistics {
+    pub name: String,
+    pub vertices: usize,
+    pub triangles: usize,
+    pub surface_area: f32,
+
+    /// Enclosed volume, only for closed meshes
+    pub volume: Option<f32>,
+
+    pub aabb: Option<Aabb>,
+    pub material: Option<usize>,
+}
+
+/// Size & shape of a whole model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelStatistics {
+    pub meshes: Vec<MeshStatistics>,
+    pub vertices: usize,
+    pub triangles: usize,
+    pub surface_area: f32,
+
+    /// Total enclosed volume, only if every mesh is closed
+    pub volume: Option<f32>,
+
+    pub aabb: Option<Aabb>,
+
+    /// Number of triangles using each material, indexed like `Model::materials`
+    pub material_triangles: Vec<usize>,
+
+    /// Number of triangles with no material
+    pub unassigned_triangles: usize,
+}
+
+impl Mesh {
+    pub fn statistics(&self) -> MeshStatistics {
+        let triangles = self.indices.len() / 3;
+        let report = self.validate();
+        let valid = report.out_of_range_triangles.is_empty();
+
+        let surface_area = if valid {
+            (0..triangles)
+                .map(|triangle| {
+                    let [a, b, c] = self.face_positions(triangle);
+                    (b - a).cross(c - a).magnitude() / 2.0
+                })
+                .sum()
+        } else {
+            0.0
+        };
+
+        // Sum of the signed volumes of the tetrahedra between each triangle & the origin
+        let volume = report.is_closed().then(|| {
+            (0..triangles)
+                .map(|triangle| {
+                    let [a, b, c] = self.face_positions(triangle);
+                    a.dot(b.cross(c)) / 6.0
+                })
+                .sum()
+        });
+
+        MeshStatistics {
+            name: self.name.clone(),
+            vertices: self.vertices.len(),
+            triangles,
+            surface_area,
+            volume,
+            aabb: self.aabb(),
+            material: self.material,
+        }
+    }
+}
+
+impl Model {
+    pub fn statistics(&self) -> ModelStatistics {
+        let meshes: Vec<MeshStatistics> = self.meshes.iter().map(Mesh::statistics).collect();
+
+        let mut material_triangles = vec![0; self.materials.len()];
+        let mut unassigned_triangles = 0;
+        for mesh in &meshes {
+            match mesh
+                .material
+                .and_then(|material| material_triangles.get_mut(material))
+            {
+                Some(count) => *count += mesh.triangles,
+                None => unassigned_triangles += mesh.triangles,
+            }
+        }
+
+        ModelStatistics {
+            vertices: meshes.iter().map(|mesh| mesh.vertices).sum(),
+            triangles: meshes.iter().map(|mesh| mesh.triangles).sum(),
+            surface_area: meshes.iter().map(|mesh| mesh.surface_area).sum(),
+            volume: meshes.iter().map(|mesh| mesh.volume).sum(),
+            aabb: self.aabb(),
+            material_triangles,
+            unassigned_triangles,
+            meshes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use crate::test_util::load_model;
+
+    #[test]
+    pub fn sphere_statistics() {
+        let model = load_model("sphere.obj");
+        let statistics = model.statistics();
+        let mesh = &model.meshes[0];
+
+        assert_eq!(statistics.vertices, mesh.vertices.len());
+        assert_eq!(statistics.triangles, mesh.indices.len() / 3);
+
+        // A tessellated sphere is a little smaller than the real thing
+        let area = statistics.surface_area;
+        assert!(area < 4.0 * PI && area > 4.0 * PI * 0.95, "{}", area);
+        let volume = statistics.volume.unwrap();
+        assert!(volume < 4.0 / 3.0 * PI && volume > 4.0 / 3.0 * PI * 0.9);
+    }
+
+    #[test]
+    pub fn cube_statistics() {
+        let model = load_model("cube.obj");
+        let statistics = model.statistics();
+
+        let volume = statistics.volume.unwrap();
+        assert!(volume <= 8.0 && volume > 7.5, "{}", volume);
+        assert!(statistics.surface_area <= 24.0 && statistics.surface_area > 22.5);
+        assert_eq!(
+            statistics.material_triangles.iter().sum::<usize>() + statistics.unassigned_triangles,
+            statistics.triangles
+        );
+    }
+
+    #[test]
+    pub fn open_mesh_has_no_volume() {
+        let mut model = load_model("sphere.obj");
+        model.meshes[0].indices.truncate(30);
+
+        let statistics = model.statistics();
+        assert_eq!(statistics.triangles, 10);
+        assert!(statistics.volume.is_none());
+        assert!(statistics.surface_area > 0.0);
+    }
+}
@@ -0,0 +1,543 @@
+//! Subdivision surfaces
+//!
+//! Loop subdivision for triangle meshes and Catmull-Clark subdivision for any polygons.
+//! Open boundaries & sharp creases are subdivided as curves, so they stay sharp
+
+use std::collections::HashSet;
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Rad, Vector3, Zero};
+
+use crate::half_edge::{HalfEdgeError, HalfEdgeMesh};
+use crate::model::{Mesh, ModelVertex};
+use crate::normals::NormalMode;
+
+/// How each level of subdivision splits faces & smooths vertices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubdivisionScheme {
+    /// Splits each triangle into four, for triangle meshes only
+    Loop,
+
+    /// Splits each polygon into a quad per corner, best suited to quad meshes
+    CatmullClark,
+}
+
+impl Mesh {
+    /// Subdivide the mesh `levels` times, getting closer to the smooth limit surface with
+    /// each level
+    ///
+    /// Edges where faces meet at a sharper angle than the crease angle stay sharp, as do
+    /// open boundaries. Normals & tangents are regenerated for the subdivided mesh
+    pub fn subdivide(
+        &self,
+        scheme: SubdivisionScheme,
+        levels: usize,
+        crease_angle: Option<Rad<f32>>,
+    ) -> Result<Mesh, HalfEdgeError> {
+        let mut mesh = HalfEdgeMesh::from_mesh(self)?
+            .subdivide(scheme, levels, crease_angle)?
+            .to_mesh();
+        mesh.compute_normals(NormalMode::AngleWeighted, crease_angle);
+        mesh.compute_tangents();
+
+        Ok(mesh)
+    }
+}
+
+impl HalfEdgeMesh {
+    /// Subdivide the mesh `levels` times, see [`Mesh::subdivide`]
+    pub fn subdivide(
+        &self,
+        scheme: SubdivisionScheme,
+        levels: usize,
+        crease_angle: Option<Rad<f32>>,
+    ) -> Result<HalfEdgeMesh, HalfEdgeError> {
+        let mut mesh = self.clone();
+        let mut creases = match crease_angle {
+            Some(angle) => mesh.crease_edges(angle),
+            None => HashSet::new(),
+        };
+
+        for _ in 0..levels {
+            let (subdivided, subdivided_creases) = match scheme {
+                SubdivisionScheme::Loop => mesh.loop_level(&creases)?,
+                SubdivisionScheme::CatmullClark => mesh.catmull_clark_level(&creases)?,
+            };
+            mesh = subdivided;
+            mesh.name = self.name.clone();
+            mesh.material = self.material;
+            creases = subdivided_creases;
+        }
+
+        Ok(mesh)
+    }
+
+    /// Edges where the faces either side meet at a sharper angle than `angle`
+    fn crease_edges(&self, angle: Rad<f32>) -> HashSet<(usize, usize)> {
+        let normals: Vec<Vector3<f32>> = (0..self.faces().len())
+            .map(|face| self.face_normal(face))
+            .collect();
+
+        let mut creases = HashSet::new();
+        for (index, half_edge) in self.half_edges().iter().enumerate() {
+            let twin = &self.half_edges()[half_edge.twin];
+            if let (Some(face), Some(other)) = (half_edge.face, twin.face) {
+                if normals[face].dot(normals[other]) < angle.0.cos() {
+                    creases.insert(edge_key(half_edge.origin, self.destination(index)));
+                }
+            }
+        }
+        creases
+    }
+
+    /// Unit normal of a polygon, using Newell's method so it works for non planar faces
+    fn face_normal(&self, face: usize) -> Vector3<f32> {
+        let positions: Vec<Vector3<f32>> = self
+            .face_vertices(face)
+            .into_iter()
+            .map(|vertex| Vector3::from(self.vertices()[vertex].position))
+            .collect();
+
+        let mut normal = Vector3::zero();
+        for (i, position) in positions.iter().enumerate() {
+            normal += position.cross(positions[(i + 1) % positions.len()]);
+        }
+        if normal.is_zero() {
+            normal
+        } else {
+            normal.normalize()
+        }
+    }
+
+    fn is_sharp(&self, half_edge: usize, creases: &HashSet<(usize, usize)>) -> bool {
+        self.is_boundary_edge(half_edge)
+            || creases.contains(&edge_key(
+                self.half_edges()[half_edge].origin,
+                self.destination(half_edge),
+            ))
+    }
+
+    /// Index of every half-edge's edge, shared with its twin, and the number of edges
+    fn edge_indices(&self) -> (Vec<usize>, usize) {
+        let mut indices = vec![usize::MAX; self.half_edges().len()];
+        let mut count = 0;
+        for (index, half_edge) in self.half_edges().iter().enumerate() {
+            if indices[index] == usize::MAX {
+                indices[index] = count;
+                indices[half_edge.twin] = count;
+                count += 1;
+            }
+        }
+        (indices, count)
+    }
+
+    /// Position of an original vertex after smoothing, `smooth` gives the position for
+    /// vertices that aren't on a crease
+    fn vertex_point(
+        &self,
+        vertex: usize,
+        creases: &HashSet<(usize, usize)>,
+        smooth: impl Fn(&[usize]) -> Vector3<f32>,
+    ) -> Vector3<f32> {
+        let position = Vector3::from(self.vertices()[vertex].position);
+        let outgoing = self.outgoing(vertex);
+        let sharp: Vec<Vector3<f32>> = outgoing
+            .iter()
+            .filter(|half_edge| self.is_sharp(**half_edge, creases))
+            .map(|half_edge| Vector3::from(self.vertices()[self.destination(*half_edge)].position))
+            .collect();
+
+        // A boundary vertex in only one face is the corner of the surface
+        let faces = outgoing
+            .iter()
+            .filter(|half_edge| self.half_edges()[**half_edge].face.is_some())
+            .count();
+
+        match sharp.len() {
+            // A single crease edge fades out into the surface, so is smoothed as usual
+            0 | 1 => smooth(&outgoing),
+
+            2 if faces == 1 => position,
+
+            // Along a crease or boundary the vertex is smoothed as part of a curve
+            2 => position * 0.75 + (sharp[0] + sharp[1]) * 0.125,
+
+            // Where creases meet is a corner, which stays put
+            _ => position,
+        }
+    }
+
+    /// Split every triangle into four, weighting new positions with Loop's rules
+    fn loop_level(
+        &self,
+        creases: &HashSet<(usize, usize)>,
+    ) -> Result<(HalfEdgeMesh, HashSet<(usize, usize)>), HalfEdgeError> {
+        if (0..self.faces().len()).any(|face| self.face_half_edges(face).len() != 3) {
+            return Err(HalfEdgeError::NotTriangle);
+        }
+
+        let position = |vertex: usize| Vector3::from(self.vertices()[vertex].position);
+        let mut positions: Vec<[f32; 3]> = (0..self.vertices().len())
+            .map(|vertex| {
+                self.vertex_point(vertex, creases, |outgoing| {
+                    let n = outgoing.len() as f32;
+                    let beta = (0.625 - (0.375 + 0.25 * (2.0 * PI / n).cos()).powi(2)) / n;
+                    let neighbours: Vector3<f32> = outgoing
+                        .iter()
+                        .map(|half_edge| position(self.destination(*half_edge)))
+                        .sum();
+                    position(vertex) * (1.0 - n * beta) + neighbours * beta
+                })
+                .into()
+            })
+            .collect();
+
+        let (edge_indices, edge_count) = self.edge_indices();
+        let edge_vertex = |half_edge: usize| self.vertices().len() + edge_indices[half_edge];
+        positions.resize(self.vertices().len() + edge_count, [0.0; 3]);
+        for (index, half_edge) in self.half_edges().iter().enumerate() {
+            if index > half_edge.twin {
+                continue;
+            }
+
+            let ends = position(half_edge.origin) + position(self.destination(index));
+            positions[edge_vertex(index)] = if self.is_sharp(index, creases) {
+                ends * 0.5
+            } else {
+                // The vertices opposite the edge in the triangles either side
+                let opposite = position(self.half_edges()[self.prev(index)].origin)
+                    + position(self.half_edges()[self.prev(half_edge.twin)].origin);
+                ends * 0.375 + opposite * 0.125
+            }
+            .into();
+        }
+
+        let mut faces = Vec::with_capacity(self.faces().len() * 4);
+        for face in 0..self.faces().len() {
+            let half_edges = self.face_half_edges(face);
+            let corner = |i: usize| self.half_edges()[half_edges[i]].corner;
+            let vertex = |i: usize| (self.half_edges()[half_edges[i]].origin, corner(i));
+            let edge = |i: usize| {
+                (
+                    edge_vertex(half_edges[i]),
+                    average(&[corner(i), corner((i + 1) % 3)]),
+                )
+            };
+
+            faces.push(vec![vertex(0), edge(0), edge(2)]);
+            faces.push(vec![vertex(1), edge(1), edge(0)]);
+            faces.push(vec![vertex(2), edge(2), edge(1)]);
+            faces.push(vec![edge(0), edge(1), edge(2)]);
+        }
+
+        let creases = self.split_creases(creases, edge_vertex);
+        Ok((HalfEdgeMesh::from_corners(positions, faces)?, creases))
+    }
+
+    /// Split every polygon into a quad per corner, weighting new positions with
+    /// Catmull-Clark's rules
+    fn catmull_clark_level(
+        &self,
+        creases: &HashSet<(usize, usize)>,
+    ) -> Result<(HalfEdgeMesh, HashSet<(usize, usize)>), HalfEdgeError> {
+        let position = |vertex: usize| Vector3::from(self.vertices()[vertex].position);
+        let face_points: Vec<Vector3<f32>> = (0..self.faces().len())
+            .map(|face| {
+                let vertices = self.face_vertices(face);
+                vertices
+                    .iter()
+                    .map(|vertex| position(*vertex))
+                    .sum::<Vector3<f32>>()
+                    / vertices.len() as f32
+            })
+            .collect();
+
+        let mut positions: Vec<[f32; 3]> = (0..self.vertices().len())
+            .map(|vertex| {
+                self.vertex_point(vertex, creases, |outgoing| {
+                    // Average of the surrounding face points & edge midpoints
+                    let n = outgoing.len() as f32;
+                    let (faces, edges) = outgoing.iter().fold(
+                        (Vector3::zero(), Vector3::zero()),
+                        |(faces, edges), half_edge| {
+                            let face = self.half_edges()[*half_edge].face.unwrap();
+                            let midpoint =
+                                (position(vertex) + position(self.destination(*half_edge))) * 0.5;
+                            (faces + face_points[face], edges + midpoint)
+                        },
+                    );
+                    (faces / n + edges / n * 2.0 + position(vertex) * (n - 3.0)) / n
+                })
+                .into()
+            })
+            .collect();
+
+        let (edge_indices, edge_count) = self.edge_indices();
+        let edge_vertex = |half_edge: usize| self.vertices().len() + edge_indices[half_edge];
+        let face_vertex = |face: usize| self.vertices().len() + edge_count + face;
+        positions.resize(
+            self.vertices().len() + edge_count + self.faces().len(),
+            [0.0; 3],
+        );
+        for (index, half_edge) in self.half_edges().iter().enumerate() {
+            if index > half_edge.twin {
+                continue;
+            }
+
+            let ends = position(half_edge.origin) + position(self.destination(index));
+            positions[edge_vertex(index)] = if self.is_sharp(index, creases) {
+                ends * 0.5
+            } else {
+                let twin_face = self.half_edges()[half_edge.twin].face.unwrap();
+                (ends + face_points[half_edge.face.unwrap()] + face_points[twin_face]) * 0.25
+            }
+            .into();
+        }
+        for (face, point) in face_points.into_iter().enumerate() {
+            positions[face_vertex(face)] = point.into();
+        }
+
+        let mut faces = vec![];
+        for face in 0..self.faces().len() {
+            let half_edges = self.face_half_edges(face);
+            let n = half_edges.len();
+            let corners: Vec<ModelVertex> = half_edges
+                .iter()
+                .map(|half_edge| self.half_edges()[*half_edge].corner)
+                .collect();
+            let centre = (face_vertex(face), average(&corners));
+
+            for i in 0..n {
+                let before = (i + n - 1) % n;
+                faces.push(vec![
+                    (self.half_edges()[half_edges[i]].origin, corners[i]),
+                    (
+                        edge_vertex(half_edges[i]),
+                        average(&[corners[i], corners[(i + 1) % n]]),
+                    ),
+                    centre,
+                    (
+                        edge_vertex(half_edges[before]),
+                        average(&[corners[before], corners[i]]),
+                    ),
+                ]);
+            }
+        }
+
+        let creases = self.split_creases(creases, edge_vertex);
+        Ok((HalfEdgeMesh::from_corners(positions, faces)?, creases))
+    }
+
+    /// Both halves of every crease edge, once its midpoint has been inserted
+    fn split_creases(
+        &self,
+        creases: &HashSet<(usize, usize)>,
+        edge_vertex: impl Fn(usize) -> usize,
+    ) -> HashSet<(usize, usize)> {
+        let mut split = HashSet::new();
+        for (index, half_edge) in self.half_edges().iter().enumerate() {
+            let end = self.destination(index);
+            if creases.contains(&edge_key(half_edge.origin, end)) {
+                split.insert(edge_key(half_edge.origin, edge_vertex(index)));
+                split.insert(edge_key(edge_vertex(index), end));
+            }
+        }
+        split
+    }
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+/// Average the attributes of face corners
+fn average(corners: &[ModelVertex]) -> ModelVertex {
+    let mut average = ModelVertex::new([0.0; 3], [0.0; 2], [0.0; 3]);
+    let weight = 1.0 / corners.len() as f32;
+    for corner in corners {
+        for i in 0..3 {
+            average.position[i] += corner.position[i] * weight;
+            average.normal[i] += corner.normal[i] * weight;
+        }
+        for i in 0..2 {
+            average.texture_coords[i] += corner.texture_coords[i] * weight;
+        }
+        for i in 0..4 {
+            average.tangent[i] += corner.tangent[i] * weight;
+        }
+    }
+
+    let normal = Vector3::from(average.normal);
+    if !normal.is_zero() {
+        average.normal = normal.normalize().into();
+    }
+
+    average
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, InnerSpace, Vector3};
+
+    use super::SubdivisionScheme;
+    use crate::half_edge::{HalfEdgeError, HalfEdgeMesh};
+    use crate::model::{Mesh, ModelVertex};
+    use crate::test_util::{grid, load_mesh};
+
+    /// A cube from -1 to 1 made of six quads
+    fn cube_cage() -> Mesh {
+        let vertices = (0..8)
+            .map(|i| {
+                let corner = [i & 1, (i >> 1) & 1, (i >> 2) & 1].map(|c| c as f32 * 2.0 - 1.0);
+                ModelVertex::new(corner, [0.0; 2], corner)
+            })
+            .collect();
+        let quads = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+
+        Mesh {
+            vertices,
+            indices: quads
+                .iter()
+                .flat_map(|[a, b, c, d]| [*a, *b, *c, *a, *c, *d])
+                .collect(),
+            face_sizes: vec![4; 6],
+            ..Default::default()
+        }
+    }
+
+    fn euler_characteristic(mesh: &HalfEdgeMesh) -> isize {
+        mesh.vertices().len() as isize - mesh.edge_count() as isize + mesh.faces().len() as isize
+    }
+
+    #[test]
+    pub fn catmull_clark_cube_topology() {
+        let cage = HalfEdgeMesh::from_mesh(&cube_cage()).unwrap();
+        for (levels, faces) in [(1, 24), (2, 96), (3, 384)] {
+            let mesh = cage
+                .subdivide(SubdivisionScheme::CatmullClark, levels, None)
+                .unwrap();
+
+            assert_eq!(mesh.faces().len(), faces);
+            assert_eq!(euler_characteristic(&mesh), 2);
+            assert!(mesh.boundary_loops().is_empty());
+            for face in 0..mesh.faces().len() {
+                assert_eq!(mesh.face_vertices(face).len(), 4);
+            }
+        }
+    }
+
+    #[test]
+    pub fn catmull_clark_rounds_cube() {
+        let mesh = HalfEdgeMesh::from_mesh(&cube_cage())
+            .unwrap()
+            .subdivide(SubdivisionScheme::CatmullClark, 4, None)
+            .unwrap();
+
+        // The limit surface is a rounded blob inside the cage
+        let radii: Vec<f32> = mesh
+            .vertices()
+            .iter()
+            .map(|vertex| Vector3::from(vertex.position).magnitude())
+            .collect();
+        let min = radii.iter().cloned().fold(f32::MAX, f32::min);
+        let max = radii.iter().cloned().fold(0.0, f32::max);
+        assert!(max < 3.0f32.sqrt() * 0.6);
+        assert!(min > max * 0.8);
+        for vertex in mesh.vertices() {
+            assert!(vertex.position.iter().all(|c| c.abs() < 1.0));
+        }
+    }
+
+    #[test]
+    pub fn creases_keep_cube_sharp() {
+        let mesh = HalfEdgeMesh::from_mesh(&cube_cage())
+            .unwrap()
+            .subdivide(SubdivisionScheme::CatmullClark, 3, Some(Deg(60.0).into()))
+            .unwrap();
+
+        // Every vertex stays on the surface of the cube
+        for vertex in mesh.vertices() {
+            let largest = vertex.position.iter().fold(0.0f32, |m, c| m.max(c.abs()));
+            assert!((largest - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    pub fn loop_sphere() {
+        let sphere = load_mesh("sphere.obj");
+        let cage = HalfEdgeMesh::from_mesh(&sphere).unwrap();
+        let mesh = cage.subdivide(SubdivisionScheme::Loop, 2, None).unwrap();
+
+        assert_eq!(mesh.faces().len(), cage.faces().len() * 16);
+        assert_eq!(euler_characteristic(&mesh), 2);
+        for vertex in mesh.vertices() {
+            let radius = Vector3::from(vertex.position).magnitude();
+            assert!(radius > 0.97 && radius <= 1.0 + 1e-5);
+        }
+    }
+
+    #[test]
+    pub fn loop_boundary_stays_on_edge() {
+        let mesh = HalfEdgeMesh::from_mesh(&grid(2))
+            .unwrap()
+            .subdivide(SubdivisionScheme::Loop, 2, None)
+            .unwrap();
+
+        assert_eq!(mesh.faces().len(), 8 * 16);
+        let boundary = mesh.boundary_loops();
+        assert_eq!(boundary.len(), 1);
+        assert_eq!(boundary[0].len(), 8 * 4);
+        for vertex in mesh.vertices() {
+            let [x, y, z] = vertex.position;
+            assert_eq!(z, 0.0);
+            assert!((0.0..=2.0).contains(&x) && (0.0..=2.0).contains(&y));
+        }
+
+        // Corners in a single triangle are kept, others are rounded off
+        let corners: Vec<[f32; 3]> = boundary[0]
+            .iter()
+            .map(|vertex| mesh.vertices()[*vertex].position)
+            .collect();
+        assert!(corners.contains(&[2.0, 0.0, 0.0]));
+        assert!(corners.contains(&[0.0, 2.0, 0.0]));
+        assert!(!corners.contains(&[0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    pub fn loop_needs_triangles() {
+        let cage = HalfEdgeMesh::from_mesh(&cube_cage()).unwrap();
+        assert!(matches!(
+            cage.subdivide(SubdivisionScheme::Loop, 1, None),
+            Err(HalfEdgeError::NotTriangle)
+        ));
+    }
+
+    #[test]
+    pub fn obj_quads_subdivide() {
+        let cube = load_mesh("cube.obj");
+        assert_eq!(cube.face_sizes.len(), 218);
+        assert_eq!(
+            cube.face_sizes.iter().map(|size| size - 2).sum::<u32>() * 3,
+            cube.indices.len() as u32
+        );
+
+        let mesh = cube
+            .subdivide(SubdivisionScheme::CatmullClark, 1, None)
+            .unwrap();
+        let quads = cube.face_sizes.iter().sum::<u32>() as usize;
+        assert_eq!(mesh.face_sizes, vec![4; quads]);
+        assert_eq!(mesh.indices.len(), quads * 6);
+        for vertex in mesh.vertices.iter() {
+            let normal = Vector3::from(vertex.normal);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-4);
+            assert!(normal.dot(Vector3::from(vertex.position)) > 0.0);
+        }
+    }
+}
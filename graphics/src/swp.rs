@@ -0,0 +1,474 @@
+//! Loading of SWP swept surface scenes
+//!
+//! A scene lists named curves & surfaces built from them, with `.` for unnamed ones
+//!
+//! - `bez2`/`bez3 name steps count [x y (z)]...` piecewise cubic Bézier curve
+//! - `bsp2`/`bsp3 name steps count [x y (z)]...` uniform cubic B-spline
+//! - `circ name steps radius` circle in the xy plane
+//! - `srev name steps profile` profile revolved around the y axis
+//! - `gcyl name profile sweep` profile swept along a curve
+//!
+//! 2D curves lie in the xy plane, & the steps are per curve segment
+
+use std::{collections::HashMap, ops::Range, path::Path};
+
+use cgmath::*;
+
+use crate::{
+    curve::{transport_frames, Curve, CurveFrame, CurveVertex},
+    model::{Mesh, Model, ModelVertex},
+    normals::NormalMode,
+};
+
+/// Points of a curve along with a frame at each
+#[derive(Debug, Clone)]
+pub struct SwpCurve {
+    pub name: Option<String>,
+    pub points: Vec<CurveFrame>,
+
+    /// Points the curve was built from, none for circles
+    pub controls: Vec<Point3<f32>>,
+}
+
+/// Everything in an SWP file
+#[derive(Debug, Default, Clone)]
+pub struct SwpScene {
+    pub curves: Vec<SwpCurve>,
+    pub surfaces: Vec<Mesh>,
+}
+
+#[derive(Debug)]
+pub enum SwpLoadError {
+    FileLoadError(std::io::Error),
+    UnknownCommand(String),
+    InvalidNumber(String),
+    InvalidPoint,
+    UnexpectedEnd,
+
+    /// Bézier curves need 3n + 1 points & B-splines at least 4
+    InvalidPointCount,
+    UnknownCurve(String),
+}
+
+pub fn load_scene(file: &Path) -> Result<SwpScene, SwpLoadError> {
+    let source = std::fs::read_to_string(file).map_err(SwpLoadError::FileLoadError)?;
+    parse_scene(&source)
+}
+
+pub fn parse_scene(source: &str) -> Result<SwpScene, SwpLoadError> {
+    let spaced = source.replace('[', " [ ").replace(']', " ] ");
+    let mut tokens = Tokens(spaced.split_whitespace());
+
+    let mut scene = SwpScene::default();
+    let mut named: HashMap<String, usize> = HashMap::new();
+    while let Some(command) = tokens.0.next() {
+        let name = tokens.next()?.to_string();
+        let name = (name != ".").then_some(name);
+
+        match command {
+            "bez2" | "bez3" | "bsp2" | "bsp3" => {
+                let steps: usize = tokens.number()?;
+                let count: usize = tokens.number()?;
+                let dimensions = if command.ends_with('2') { 2 } else { 3 };
+                let controls = (0..count)
+                    .map(|_| tokens.point(dimensions))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let points = if command.starts_with("bez") {
+                    bezier(&controls, steps)?
+                } else {
+                    bspline(&controls, steps)?
+                };
+                push_curve(&mut scene, &mut named, name, points, controls);
+            }
+            "circ" => {
+                let steps: usize = tokens.number()?;
+                let radius: f32 = tokens.number()?;
+                let points = circle(steps.max(1), radius);
+                push_curve(&mut scene, &mut named, name, points, vec![]);
+            }
+            "srev" => {
+                let steps: usize = tokens.number()?;
+                let profile = find_curve(&scene, &named, tokens.next()?)?;
+                let mesh = revolve(&profile.points, steps.max(1));
+                scene.surfaces.push(surface_mesh(name, mesh));
+            }
+            "gcyl" => {
+                let profile = find_curve(&scene, &named, tokens.next()?)?;
+                let sweep = find_curve(&scene, &named, tokens.next()?)?;
+                let mesh = sweep_along(&profile.points, &sweep.points);
+                scene.surfaces.push(surface_mesh(name, mesh));
+            }
+            _ => return Err(SwpLoadError::UnknownCommand(command.to_string())),
+        }
+    }
+
+    Ok(scene)
+}
+
+impl SwpScene {
+    /// Model of the scene's surfaces, curves aren't included
+    pub fn to_model(&self) -> Model {
+        Model {
+            meshes: self.surfaces.clone(),
+            materials: vec![],
+        }
+    }
+}
+
+impl Curve for SwpCurve {
+    /// The points of the part of the curve in `range`, which goes from 0 to 1 along the
+    /// curve. The scene sets how finely it's sampled, so `steps` is unused
+    fn to_vertices(&self, range: Range<f32>, _steps: u32) -> Vec<CurveVertex> {
+        let last = self.points.len().saturating_sub(1) as f32;
+        let first = (range.start.clamp(0.0, 1.0) * last).floor() as usize;
+        let end = (range.end.clamp(0.0, 1.0) * last).ceil() as usize;
+
+        self.points
+            .iter()
+            .skip(first)
+            .take(end + 1 - first.min(end + 1))
+            .map(|point| CurveVertex {
+                position: point.position.into(),
+            })
+            .collect()
+    }
+
+    /// The curve's own points & frames
+    fn frames(&self, _steps: u32) -> Vec<CurveFrame> {
+        self.points.clone()
+    }
+
+    fn control_points(&self) -> Vec<Point3<f32>> {
+        self.controls.clone()
+    }
+}
+
+struct Tokens<'a>(std::str::SplitWhitespace<'a>);
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Result<&'a str, SwpLoadError> {
+        self.0.next().ok_or(SwpLoadError::UnexpectedEnd)
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, SwpLoadError> {
+        let token = self.next()?;
+        token
+            .parse()
+            .map_err(|_| SwpLoadError::InvalidNumber(token.to_string()))
+    }
+
+    /// A bracketed point, with z left at 0 for 2D points
+    fn point(&mut self, dimensions: usize) -> Result<Point3<f32>, SwpLoadError> {
+        if self.next()? != "[" {
+            return Err(SwpLoadError::InvalidPoint);
+        }
+        let mut point = Point3::origin();
+        for axis in 0..dimensions {
+            point[axis] = self.number()?;
+        }
+        if self.next()? != "]" {
+            return Err(SwpLoadError::InvalidPoint);
+        }
+
+        Ok(point)
+    }
+}
+
+fn push_curve(
+    scene: &mut SwpScene,
+    named: &mut HashMap<String, usize>,
+    name: Option<String>,
+    points: Vec<CurveFrame>,
+    controls: Vec<Point3<f32>>,
+) {
+    if let Some(name) = &name {
+        named.insert(name.clone(), scene.curves.len());
+    }
+    scene.curves.push(SwpCurve {
+        name,
+        points,
+        controls,
+    });
+}
+
+fn find_curve<'a>(
+    scene: &'a SwpScene,
+    named: &HashMap<String, usize>,
+    name: &str,
+) -> Result<&'a SwpCurve, SwpLoadError> {
+    named
+        .get(name)
+        .map(|index| &scene.curves[*index])
+        .ok_or_else(|| SwpLoadError::UnknownCurve(name.to_string()))
+}
+
+/// Weights of a B-spline segment's control points in the Bézier points of the same segment
+const BSPLINE_TO_BEZIER: [[f32; 4]; 4] = [
+    [1.0 / 6.0, 2.0 / 3.0, 1.0 / 6.0, 0.0],
+    [0.0, 2.0 / 3.0, 1.0 / 3.0, 0.0],
+    [0.0, 1.0 / 3.0, 2.0 / 3.0, 0.0],
+    [0.0, 1.0 / 6.0, 2.0 / 3.0, 1.0 / 6.0],
+];
+
+/// Piecewise cubic Bézier curve, consecutive segments sharing an end point
+fn bezier(controls: &[Point3<f32>], steps: usize) -> Result<Vec<CurveFrame>, SwpLoadError> {
+    if controls.len() < 4 || !(controls.len() - 1).is_multiple_of(3) {
+        return Err(SwpLoadError::InvalidPointCount);
+    }
+
+    let segments: Vec<[Point3<f32>; 4]> = controls
+        .windows(4)
+        .step_by(3)
+        .map(|window| [window[0], window[1], window[2], window[3]])
+        .collect();
+    Ok(sample_segments(&segments, steps.max(1)))
+}
+
+/// Uniform cubic B-spline, each window of four points making a segment
+fn bspline(controls: &[Point3<f32>], steps: usize) -> Result<Vec<CurveFrame>, SwpLoadError> {
+    if controls.len() < 4 {
+        return Err(SwpLoadError::InvalidPointCount);
+    }
+
+    let segments: Vec<[Point3<f32>; 4]> = controls
+        .windows(4)
+        .map(|window| {
+            BSPLINE_TO_BEZIER.map(|weights| {
+                Point3::from_vec(
+                    (0..4)
+                        .map(|index| window[index].to_vec() * weights[index])
+                        .sum(),
+                )
+            })
+        })
+        .collect();
+    Ok(sample_segments(&segments, steps.max(1)))
+}
+
+/// Evaluate Bézier segments `steps` times each, with a point shared between segments
+fn sample_segments(segments: &[[Point3<f32>; 4]], steps: usize) -> Vec<CurveFrame> {
+    let mut samples = vec![];
+    for (index, [p0, p1, p2, p3]) in segments.iter().enumerate() {
+        let first = if index == 0 { 0 } else { 1 };
+        for step in first..=steps {
+            let t = step as f32 / steps as f32;
+            let s = 1.0 - t;
+            let position = p0.to_vec() * (s * s * s)
+                + p1.to_vec() * (3.0 * s * s * t)
+                + p2.to_vec() * (3.0 * s * t * t)
+                + p3.to_vec() * (t * t * t);
+            let tangent =
+                (p1 - p0) * (3.0 * s * s) + (p2 - p1) * (6.0 * s * t) + (p3 - p2) * (3.0 * t * t);
+            samples.push((Point3::from_vec(position), tangent));
+        }
+    }
+
+    with_frames(samples)
+}
+
+fn circle(steps: usize, radius: f32) -> Vec<CurveFrame> {
+    let samples = (0..=steps)
+        .map(|step| {
+            let angle = std::f32::consts::TAU * step as f32 / steps as f32;
+            let (sin, cos) = angle.sin_cos();
+            (
+                Point3::new(radius * cos, radius * sin, 0.0),
+                Vector3::new(-sin, cos, 0.0),
+            )
+        })
+        .collect();
+
+    with_frames(samples)
+}
+
+/// Add normals & binormals to positions & tangents, with the frames carried along without
+/// twisting so 2D curves' binormals stay at +z
+fn with_frames(samples: Vec<(Point3<f32>, Vector3<f32>)>) -> Vec<CurveFrame> {
+    let (positions, tangents): (Vec<_>, Vec<_>) = samples.into_iter().unzip();
+    transport_frames(&positions, Some(&tangents), Vector3::unit_z())
+}
+
+/// Surface of revolution of a profile in the xy plane around the y axis
+fn revolve(profile: &[CurveFrame], steps: usize) -> Mesh {
+    let rings: Vec<Vec<Point3<f32>>> = (0..=steps)
+        .map(|step| {
+            let rotation =
+                Matrix3::from_angle_y(Rad(std::f32::consts::TAU * step as f32 / steps as f32));
+            profile
+                .iter()
+                .map(|point| Point3::from_vec(rotation * point.position.to_vec()))
+                .collect()
+        })
+        .collect();
+
+    grid_mesh(&rings)
+}
+
+/// Generalized cylinder, with the profile's x & y along the sweep's normal & binormal
+fn sweep_along(profile: &[CurveFrame], sweep: &[CurveFrame]) -> Mesh {
+    let rings: Vec<Vec<Point3<f32>>> = sweep
+        .iter()
+        .map(|frame| {
+            profile
+                .iter()
+                .map(|point| {
+                    frame.position
+                        + frame.normal * point.position.x
+                        + frame.binormal * point.position.y
+                })
+                .collect()
+        })
+        .collect();
+
+    grid_mesh(&rings)
+}
+
+/// Mesh joining rings of the same number of points with quads
+fn grid_mesh(rings: &[Vec<Point3<f32>>]) -> Mesh {
+    let ring_length = rings.first().map_or(0, Vec::len);
+    let mut mesh = Mesh::default();
+    for (ring_index, ring) in rings.iter().enumerate() {
+        for (point_index, point) in ring.iter().enumerate() {
+            let u = ring_index as f32 / (rings.len() - 1).max(1) as f32;
+            let v = point_index as f32 / (ring_length - 1).max(1) as f32;
+            mesh.vertices
+                .push(ModelVertex::new((*point).into(), [u, v], [0.0; 3]));
+        }
+    }
+
+    for ring in 0..rings.len().saturating_sub(1) {
+        for point in 0..ring_length.saturating_sub(1) {
+            let a = (ring * ring_length + point) as u32;
+            let b = a + ring_length as u32;
+            mesh.indices.extend([a, a + 1, b, b, a + 1, b + 1]);
+        }
+    }
+
+    mesh
+}
+
+/// Turn a grid into a finished surface, facing away from its centre
+fn surface_mesh(name: Option<String>, mut mesh: Mesh) -> Mesh {
+    mesh.name = name.unwrap_or_default();
+
+    // Whether the grid faces in or out depends on which way its curves run
+    let centre = mesh
+        .aabb()
+        .map_or(Vector3::zero(), |aabb| aabb.center().to_vec());
+    let outwards: f32 = (0..mesh.indices.len() / 3)
+        .map(|face| {
+            let [a, b, c] = mesh.face_positions(face);
+            (b - a).cross(c - a).dot((a + b + c) / 3.0 - centre)
+        })
+        .sum();
+    if outwards < 0.0 {
+        for triangle in mesh.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    mesh.compute_normals(NormalMode::AngleWeighted, None);
+    mesh.compute_tangents();
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use cgmath::*;
+
+    use super::*;
+
+    fn load(file: &str) -> SwpScene {
+        let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("swp").join(file);
+        load_scene(&file).unwrap()
+    }
+
+    #[test]
+    pub fn load_bundled_scenes() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("swp");
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            let scene = load_scene(&path).unwrap();
+            assert!(!scene.curves.is_empty(), "{:?}", path);
+        }
+
+        let tor = load("tor.swp");
+        assert_eq!(tor.curves.len(), 2);
+        assert_eq!(tor.surfaces.len(), 1);
+        assert_eq!(tor.surfaces[0].vertices.len(), 31 * 61);
+    }
+
+    #[test]
+    pub fn curves_pass_through_end_points() {
+        let scene = parse_scene("bez2 . 10 4 [0 0] [1 1] [2 1] [3 0]").unwrap();
+        let points = &scene.curves[0].points;
+        assert_eq!(points.len(), 11);
+        assert_abs_diff_eq!(points[0].position, Point3::origin());
+        assert_abs_diff_eq!(points[10].position, Point3::new(3.0, 0.0, 0.0));
+        assert_abs_diff_eq!(points[0].tangent, Vector3::new(1.0, 1.0, 0.0).normalize());
+        assert_abs_diff_eq!(points[5].binormal, Vector3::unit_z());
+        assert_eq!(
+            scene.curves[0].control_points()[2],
+            Point3::new(2.0, 1.0, 0.0)
+        );
+        assert!(parse_scene("circ . 8 1").unwrap().curves[0]
+            .control_points()
+            .is_empty());
+
+        // A B-spline of repeated points stays at that point
+        let scene = parse_scene("bsp3 . 4 4 [1 2 3] [1 2 3] [1 2 3] [1 2 3]").unwrap();
+        for point in &scene.curves[0].points {
+            assert_abs_diff_eq!(point.position, Point3::new(1.0, 2.0, 3.0), epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    pub fn torus_surface() {
+        let torus = &load("tor.swp").surfaces[0];
+        let statistics = torus.statistics();
+
+        // Area of a torus is 4 pi^2 R r
+        let expected = 4.0 * std::f32::consts::PI.powi(2) * 2.0 * 0.5;
+        assert!((statistics.surface_area - expected).abs() < expected * 0.02);
+
+        // Only the texture seam keeps it from being closed
+        let mut welded = torus.clone();
+        for vertex in &mut welded.vertices {
+            vertex.texture_coords = [0.0; 2];
+            vertex.normal = [0.0; 3];
+            vertex.tangent = [0.0; 4];
+        }
+        welded.weld(1e-4);
+        assert!(welded.validate().is_closed());
+
+        // Normals face away from the tube's centre line
+        for vertex in &torus.vertices {
+            let position = Vector3::from(vertex.position);
+            let centre = Vector3::new(position.x, position.y, 0.0).normalize() * 2.0;
+            assert!(Vector3::from(vertex.normal).dot(position - centre) > 0.0);
+        }
+    }
+
+    #[test]
+    pub fn invalid_scenes() {
+        assert!(matches!(
+            parse_scene("bez2 . 10 3 [0 0] [1 1] [2 1]"),
+            Err(SwpLoadError::InvalidPointCount)
+        ));
+        assert!(matches!(
+            parse_scene("srev . 10 missing"),
+            Err(SwpLoadError::UnknownCurve(_))
+        ));
+        assert!(matches!(
+            parse_scene("circ . 10"),
+            Err(SwpLoadError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            parse_scene("cube . 1"),
+            Err(SwpLoadError::UnknownCommand(_))
+        ));
+    }
+}
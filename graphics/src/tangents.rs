@@ -0,0 +1,193 @@
+//! Tangent space generation for normal mapping
+//!
+//! Tangents are generated with MikkTSpace, so they match the tangent space most tools
+//! bake normal maps in
+
+use std::collections::HashMap;
+
+use bevy_mikktspace::Geometry;
+
+use crate::model::Mesh;
+
+impl Mesh {
+    /// Replace the vertex tangents with MikkTSpace tangents generated from the normals &
+    /// texture coordinates, so should be called after any change to either
+    ///
+    /// Vertices are split where faces sharing them disagree on the tangent, for example
+    /// along a mirrored texture seam
+    pub fn compute_tangents(&mut self) {
+        let mut geometry = TangentGeometry {
+            mesh: self,
+            tangents: vec![[0.0; 4]; self.indices.len() / 3 * 3],
+        };
+
+        // Only fails for meshes with no faces, which have nothing to update
+        if !bevy_mikktspace::generate_tangents(&mut geometry) {
+            return;
+        }
+        let tangents = geometry.tangents;
+
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        let mut vertex_map: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+        let mut indices = Vec::with_capacity(tangents.len());
+        for (index, tangent) in self.indices.iter().zip(tangents) {
+            let key = (*index, tangent.map(f32::to_bits));
+            let new_index = *vertex_map.entry(key).or_insert_with(|| {
+                let mut vertex = self.vertices[*index as usize];
+                vertex.tangent = tangent;
+                vertices.push(vertex);
+                (vertices.len() - 1) as u32
+            });
+            indices.push(new_index);
+        }
+
+        self.vertices = vertices;
+        self.indices = indices;
+    }
+}
+
+/// Feeds a triangle mesh to MikkTSpace, collecting a tangent for every face corner
+struct TangentGeometry<'a> {
+    mesh: &'a Mesh,
+    tangents: Vec<[f32; 4]>,
+}
+
+impl<'a> TangentGeometry<'a> {
+    fn vertex(&self, face: usize, vert: usize) -> &crate::model::ModelVertex {
+        &self.mesh.vertices[self.mesh.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl<'a> Geometry for TangentGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.mesh.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).texture_coords
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = tangent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{assert_abs_diff_eq, InnerSpace, Vector2, Vector3, Vector4};
+
+    use crate::model::{Mesh, ModelVertex};
+    use crate::test_util::load_mesh;
+
+    /// A unit quad facing +z, with the given u coordinate at x = 0 & x = 1
+    fn quad(u: [f32; 2]) -> Mesh {
+        Mesh {
+            vertices: vec![
+                ModelVertex::new([0.0, 0.0, 0.0], [u[0], 0.0], [0.0, 0.0, 1.0]),
+                ModelVertex::new([1.0, 0.0, 0.0], [u[1], 0.0], [0.0, 0.0, 1.0]),
+                ModelVertex::new([1.0, 1.0, 0.0], [u[1], 1.0], [0.0, 0.0, 1.0]),
+                ModelVertex::new([0.0, 1.0, 0.0], [u[0], 1.0], [0.0, 0.0, 1.0]),
+            ],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..Default::default()
+        }
+    }
+
+    /// Check every tangent is a unit vector perpendicular to the normal, with a ±1 sign,
+    /// and points along increasing u
+    fn assert_valid_tangents(mesh: &Mesh) {
+        for vertex in mesh.vertices.iter() {
+            let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            assert_abs_diff_eq!(tangent.magnitude(), 1.0, epsilon = 1e-3);
+            assert!(tangent.dot(Vector3::from(vertex.normal).normalize()).abs() < 1e-3);
+            assert_eq!(vertex.tangent[3].abs(), 1.0);
+        }
+
+        for face in 0..mesh.indices.len() / 3 {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[mesh.indices[face * 3 + i] as usize]);
+            let (edge_1, edge_2) = (
+                Vector3::from(b.position) - Vector3::from(a.position),
+                Vector3::from(c.position) - Vector3::from(a.position),
+            );
+            let (uv_1, uv_2) = (
+                Vector2::from(b.texture_coords) - Vector2::from(a.texture_coords),
+                Vector2::from(c.texture_coords) - Vector2::from(a.texture_coords),
+            );
+
+            // Direction of increasing u across the face
+            let u_direction = edge_1 * uv_2.y - edge_2 * uv_1.y;
+            let determinant = uv_1.x * uv_2.y - uv_1.y * uv_2.x;
+            if determinant.abs() < 1e-8 || u_direction.magnitude() < 1e-8 {
+                continue;
+            }
+
+            let tangent = Vector3::new(a.tangent[0], a.tangent[1], a.tangent[2]);
+            assert!(tangent.dot(u_direction * determinant.signum()) > 0.0);
+        }
+    }
+
+    #[test]
+    pub fn quad_tangent_follows_u() {
+        let mut mesh = quad([0.0, 1.0]);
+        mesh.compute_tangents();
+
+        assert_eq!(mesh.vertices.len(), 4);
+        for vertex in mesh.vertices.iter() {
+            assert_abs_diff_eq!(
+                Vector4::from(vertex.tangent),
+                Vector4::new(1.0, 0.0, 0.0, 1.0),
+                epsilon = 1e-5
+            );
+        }
+    }
+
+    #[test]
+    pub fn mirrored_quad_flips_bitangent_sign() {
+        let mut mesh = quad([1.0, 0.0]);
+        mesh.compute_tangents();
+
+        for vertex in mesh.vertices.iter() {
+            assert_abs_diff_eq!(
+                Vector4::from(vertex.tangent),
+                Vector4::new(-1.0, 0.0, 0.0, -1.0),
+                epsilon = 1e-5
+            );
+        }
+    }
+
+    #[test]
+    pub fn cube_tangents() {
+        let mut mesh = load_mesh("cube.obj");
+        mesh.compute_tangents();
+        assert_valid_tangents(&mesh);
+    }
+
+    #[test]
+    pub fn sphere_tangents() {
+        let mut mesh = load_mesh("sphere.obj");
+        let face_count = mesh.indices.len() / 3;
+        mesh.compute_tangents();
+
+        assert_eq!(mesh.indices.len() / 3, face_count);
+        assert_valid_tangents(&mesh);
+    }
+
+    #[test]
+    pub fn empty_mesh_is_unchanged() {
+        let mut mesh = Mesh::default();
+        mesh.compute_tangents();
+        assert!(mesh.vertices.is_empty());
+    }
+}
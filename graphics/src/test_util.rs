@@ -0,0 +1,52 @@
+//! Fixtures shared by the unit tests
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    model::{Mesh, Model, ModelVertex},
+    obj,
+};
+
+/// Load an OBJ file from the crate's `data` directory
+pub fn load_model(file: &str) -> Model {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("data")
+        .join(file);
+    obj::load_model(&file).unwrap()
+}
+
+/// First mesh of an OBJ file from the crate's `data` directory
+pub fn load_mesh(file: &str) -> Mesh {
+    load_model(file).meshes.remove(0)
+}
+
+/// Scratch directory for a test to save into, unique to this run so parallel runs &
+/// checkouts don't write over each other
+pub fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("graphics-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// A flat square from 0 to `n` in the xy plane, split into `n` × `n` quads of two
+/// triangles each, with texture coordinates matching the positions
+pub fn grid(n: u32) -> Mesh {
+    let mut mesh = Mesh::default();
+    for y in 0..=n {
+        for x in 0..=n {
+            let (x, y) = (x as f32, y as f32);
+            mesh.vertices
+                .push(ModelVertex::new([x, y, 0.0], [x, y], [0.0, 0.0, 1.0]));
+        }
+    }
+    for y in 0..n {
+        for x in 0..n {
+            let corner = y * (n + 1) + x;
+            let above = corner + n + 1;
+            mesh.indices
+                .extend([corner, corner + 1, above + 1, corner, above + 1, above]);
+        }
+    }
+    mesh
+}
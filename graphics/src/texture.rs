@@ -18,7 +18,7 @@ impl Texture {
 
         match data {
             Ok(data) => Texture::from_bytes(device, queue, data.as_slice(), label),
-            Err(err) => return Err(anyhow::Error::new(err)),
+            Err(err) => Err(anyhow::Error::new(err)),
         }
     }
 
@@ -33,6 +33,27 @@ impl Texture {
         queue: &Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::with_format(device, queue, img, label, TextureFormat::Rgba8UnormSrgb)
+    }
+
+    /// Create a texture of data that isn't colour, like a normal map, which is sampled
+    /// without sRGB decoding
+    pub fn from_image_linear(
+        device: &Device,
+        queue: &Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Self::with_format(device, queue, img, label, TextureFormat::Rgba8Unorm)
+    }
+
+    fn with_format(
+        device: &Device,
+        queue: &Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -43,12 +64,12 @@ impl Texture {
             depth_or_array_layers: 1,
         };
         let texture = device.create_texture(&TextureDescriptor {
-            label: label,
-            size: size,
+            label,
+            size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
         });
         queue.write_texture(
@@ -127,3 +148,111 @@ impl Texture {
         }
     }
 }
+
+/// Texture decoded to floating point for sampling on the CPU
+#[derive(Debug, Clone)]
+pub struct CpuTexture {
+    width: u32,
+    height: u32,
+    texels: Vec<[f32; 4]>,
+}
+
+impl CpuTexture {
+    /// Decode an image, converting sRGB colour to linear if `srgb` is set
+    pub fn from_image(image: &image::DynamicImage, srgb: bool) -> Self {
+        let image = image.to_rgba8();
+        let texels = image
+            .pixels()
+            .map(|pixel| {
+                let mut texel = pixel.0.map(|channel| channel as f32 / 255.0);
+                if srgb {
+                    for channel in &mut texel[0..3] {
+                        *channel = srgb_to_linear(*channel);
+                    }
+                }
+                texel
+            })
+            .collect();
+
+        CpuTexture {
+            width: image.width(),
+            height: image.height(),
+            texels,
+        }
+    }
+
+    /// Decode embedded image data if there is any, otherwise the file, `None` if neither
+    /// can be loaded
+    pub fn load(data: Option<&[u8]>, file: &Path, srgb: bool) -> Option<Self> {
+        let image = match data {
+            Some(data) => image::load_from_memory(data).ok()?,
+            None => image::open(file).ok()?,
+        };
+
+        Some(Self::from_image(&image, srgb))
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Bilinearly filtered texel at texture coordinates, with `address_mode` deciding
+    /// what lies outside 0 to 1
+    pub fn sample(&self, [u, v]: [f32; 2], address_mode: AddressMode) -> [f32; 4] {
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let (x_0, y_0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x_0, y - y_0);
+
+        let texel = |x: f32, y: f32| {
+            let x = address(x as i64, self.width, address_mode);
+            let y = address(y as i64, self.height, address_mode);
+            self.texels[y * self.width as usize + x]
+        };
+        let (a, b) = (texel(x_0, y_0), texel(x_0 + 1.0, y_0));
+        let (c, d) = (texel(x_0, y_0 + 1.0), texel(x_0 + 1.0, y_0 + 1.0));
+
+        let mut sample = [0.0; 4];
+        for (i, channel) in sample.iter_mut().enumerate() {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            *channel = top + (bottom - top) * fy;
+        }
+        sample
+    }
+}
+
+/// Wrap a texel coordinate into `0..size`
+fn address(coordinate: i64, size: u32, address_mode: AddressMode) -> usize {
+    let size = size as i64;
+    let coordinate = match address_mode {
+        AddressMode::Repeat => coordinate.rem_euclid(size),
+        AddressMode::MirrorRepeat => {
+            let period = coordinate.rem_euclid(size * 2);
+            if period < size {
+                period
+            } else {
+                size * 2 - 1 - period
+            }
+        }
+        _ => coordinate.clamp(0, size - 1),
+    };
+
+    coordinate as usize
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
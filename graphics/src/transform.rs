@@ -1,31 +1,371 @@
-use cgmath::{Matrix4, Rad, Zero};
+use cgmath::{
+    InnerSpace, Matrix, Matrix3, Matrix4, One, Quaternion, Rad, Rotation3, SquareMatrix, Vector3,
+};
 
+/// Placement of an object, applied as scale, then rotation, then translation
+#[derive(Debug, Clone, Copy)]
 pub struct Transform {
-    pub rotation: [Rad<f32>; 3],
+    pub rotation: Quaternion<f32>,
     pub scale: [f32; 3],
     pub translate: [f32; 3],
 }
 
+/// Order that rotations about each axis are applied in, so `XYZ` rotates about x first
+/// and z last, giving the matrix Rz * Ry * Rx
+///
+/// Angles are always given & returned as [x, y, z], whatever the order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EulerOrder {
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+impl EulerOrder {
+    /// Axes in the order they're applied
+    fn axes(self) -> [usize; 3] {
+        match self {
+            EulerOrder::XYZ => [0, 1, 2],
+            EulerOrder::XZY => [0, 2, 1],
+            EulerOrder::YXZ => [1, 0, 2],
+            EulerOrder::YZX => [1, 2, 0],
+            EulerOrder::ZXY => [2, 0, 1],
+            EulerOrder::ZYX => [2, 1, 0],
+        }
+    }
+
+    pub fn to_quaternion(self, angles: [Rad<f32>; 3]) -> Quaternion<f32> {
+        let axis = |axis: usize| {
+            let mut unit = Vector3::new(0.0, 0.0, 0.0);
+            unit[axis] = 1.0;
+            Quaternion::from_axis_angle(unit, angles[axis])
+        };
+        let [a, b, c] = self.axes();
+        axis(c) * axis(b) * axis(a)
+    }
+
+    /// Angles that rebuild the rotation in this order, with the middle rotation between
+    /// -90 & 90 degrees
+    ///
+    /// When the middle rotation is +-90 degrees the first & last axes line up and only
+    /// their combination can be recovered, so it all goes in the first angle
+    pub fn from_quaternion(self, rotation: Quaternion<f32>) -> [Rad<f32>; 3] {
+        let matrix = Matrix3::from(rotation.normalize());
+        // cgmath matrices are indexed by column first
+        let m = |row: usize, column: usize| matrix[column][row];
+
+        // Reordering the axes of an odd order is a reflection, which reverses every angle
+        let [a, b, c] = self.axes();
+        let sign = if (b + 3 - a) % 3 == 1 { 1.0 } else { -1.0 };
+
+        let mut angles = [Rad(0.0); 3];
+        // atan2 keeps the middle angle accurate near +-90 degrees, where asin isn't
+        let cos_b = (m(c, b) * m(c, b) + m(c, c) * m(c, c)).sqrt();
+        angles[b] = Rad((-sign * m(c, a)).atan2(cos_b));
+        if cos_b > 1e-4 {
+            angles[a] = Rad((sign * m(c, b)).atan2(m(c, c)));
+            angles[c] = Rad((sign * m(b, a)).atan2(m(a, a)));
+        } else {
+            angles[a] = Rad((-sign * m(b, c)).atan2(m(b, b)));
+        }
+
+        angles
+    }
+}
+
 impl Transform {
     pub fn new() -> Self {
         Transform {
-            rotation: [Rad::zero(); 3],
+            rotation: Quaternion::one(),
             scale: [1.0; 3],
             translate: [0.0; 3],
         }
     }
 
+    /// Decompose an affine matrix into a transform that rebuilds the same matrix
+    ///
+    /// Shear cannot be represented, so matrices containing it are approximated by the
+    /// nearest rotation found by orthogonalising the columns in order
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Self {
+        let columns = [
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        ];
+        let mut scale = columns.map(|column| column.magnitude());
+
+        // A mirrored basis is folded into the x scale
+        let mirrored = Matrix3::from_cols(columns[0], columns[1], columns[2]).determinant() < 0.0;
+        if mirrored {
+            scale[0] = -scale[0];
+        }
+
+        // Gram-Schmidt, falling back to any perpendicular axis for zero or parallel columns
+        let unit = |vector: Vector3<f32>, fallback: Vector3<f32>| {
+            if vector.magnitude2() > 1e-12 {
+                vector.normalize()
+            } else {
+                fallback
+            }
+        };
+        let mut x = unit(columns[0], Vector3::unit_x());
+        if mirrored {
+            x = -x;
+        }
+        let perpendicular = if x.x.abs() < 0.9 {
+            Vector3::unit_x().cross(x)
+        } else {
+            Vector3::unit_y().cross(x)
+        };
+        let y = unit(
+            columns[1] - x * x.dot(columns[1]),
+            perpendicular.normalize(),
+        );
+        let z = x.cross(y);
+
+        Transform {
+            rotation: Quaternion::from(Matrix3::from_cols(x, y, z)).normalize(),
+            scale,
+            translate: matrix.w.truncate().into(),
+        }
+    }
+
+    /// Set the rotation from angles about each axis, applied in the given order
+    pub fn with_euler(self, angles: [Rad<f32>; 3], order: EulerOrder) -> Self {
+        Transform {
+            rotation: order.to_quaternion(angles),
+            ..self
+        }
+    }
+
+    /// Angles about each axis that rebuild the rotation in the given order
+    pub fn euler(&self, order: EulerOrder) -> [Rad<f32>; 3] {
+        order.from_quaternion(self.rotation)
+    }
+
+    /// T * R * S
     pub fn build_transform_matrix(&self) -> Matrix4<f32> {
-        // TODO - Figure out the right rotation axis for a single matrix
-        Matrix4::from_angle_x(self.rotation[0])
-            * Matrix4::from_angle_y(self.rotation[1])
-            * Matrix4::from_angle_z(self.rotation[2])
-            * Matrix4::from_translation(self.translate.into())
+        Matrix4::from_translation(self.translate.into())
+            * Matrix4::from(self.rotation.normalize())
             * Matrix4::from_nonuniform_scale(self.scale[0], self.scale[1], self.scale[2])
     }
 
+    /// S^-1 * R^-1 * T^-1, undoing `build_transform_matrix`
+    ///
+    /// Every scale must be non-zero
+    pub fn inverse_matrix(&self) -> Matrix4<f32> {
+        let [x, y, z] = self.scale;
+        let translate = Vector3::from(self.translate);
+        Matrix4::from_nonuniform_scale(1.0 / x, 1.0 / y, 1.0 / z)
+            * Matrix4::from(self.rotation.normalize().conjugate())
+            * Matrix4::from_translation(-translate)
+    }
+
+    /// Inverse transpose of the rotation & scale, which keeps normals perpendicular to
+    /// surfaces under non-uniform scale. Transformed normals need normalising
+    ///
+    /// Every scale must be non-zero
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        let [x, y, z] = self.scale;
+        Matrix3::from(self.rotation.normalize())
+            * Matrix3::from_diagonal(Vector3::new(1.0 / x, 1.0 / y, 1.0 / z))
+    }
+
     #[inline]
-    pub fn as_uniform(&self) -> [[f32; 4]; 4] {
-        self.build_transform_matrix().into()
+    pub fn as_uniform(&self) -> TransformUniform {
+        TransformUniform::new(self.build_transform_matrix())
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Inverse transpose of the upper 3x3 of a transform matrix, for transforming normals
+///
+/// Singular matrices have no inverse, so their upper 3x3 is used unchanged
+pub fn normal_matrix(matrix: Matrix4<f32>) -> Matrix3<f32> {
+    let linear = Matrix3::from_cols(
+        matrix.x.truncate(),
+        matrix.y.truncate(),
+        matrix.z.truncate(),
+    );
+    linear
+        .invert()
+        .map_or(linear, |inverse| inverse.transpose())
+}
+
+/// Model & normal matrices of one draw, matching `TransformUniform` in shader.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TransformUniform {
+    transform: [[f32; 4]; 4],
+    normal: [[f32; 4]; 4],
+}
+
+impl TransformUniform {
+    pub fn new(matrix: Matrix4<f32>) -> Self {
+        let normal = normal_matrix(matrix);
+        TransformUniform {
+            transform: matrix.into(),
+            normal: Matrix4::from(normal).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::*;
+
+    use super::{normal_matrix, EulerOrder, Transform};
+
+    const ORDERS: [EulerOrder; 6] = [
+        EulerOrder::XYZ,
+        EulerOrder::XZY,
+        EulerOrder::YXZ,
+        EulerOrder::YZX,
+        EulerOrder::ZXY,
+        EulerOrder::ZYX,
+    ];
+
+    fn axis_matrix(axis: usize, angle: Rad<f32>) -> Matrix4<f32> {
+        match axis {
+            0 => Matrix4::from_angle_x(angle),
+            1 => Matrix4::from_angle_y(angle),
+            _ => Matrix4::from_angle_z(angle),
+        }
+    }
+
+    fn example() -> Transform {
+        Transform {
+            scale: [2.0, 0.5, 3.0],
+            translate: [1.0, -2.0, 4.0],
+            ..Transform::new()
+        }
+        .with_euler([Rad(0.3), Rad(-1.1), Rad(2.5)], EulerOrder::XYZ)
+    }
+
+    #[test]
+    pub fn translation_is_applied_last() {
+        let transform = example();
+        let matrix = transform.build_transform_matrix();
+
+        // The origin lands on the translation, unaffected by rotation or scale
+        assert_abs_diff_eq!(
+            matrix * Vector4::unit_w(),
+            Vector4::new(1.0, -2.0, 4.0, 1.0)
+        );
+        assert_abs_diff_eq!(
+            matrix,
+            Matrix4::from_translation(Vector3::new(1.0, -2.0, 4.0))
+                * Matrix4::from_angle_z(Rad(2.5))
+                * Matrix4::from_angle_y(Rad(-1.1))
+                * Matrix4::from_angle_x(Rad(0.3))
+                * Matrix4::from_nonuniform_scale(2.0, 0.5, 3.0),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    pub fn euler_orders() {
+        let angles = [Rad(0.4), Rad(-0.7), Rad(1.2)];
+        for order in ORDERS {
+            // Rotations are applied in order, so the first is rightmost
+            let [a, b, c] = order.axes();
+            let expected =
+                axis_matrix(c, angles[c]) * axis_matrix(b, angles[b]) * axis_matrix(a, angles[a]);
+            let rotation = order.to_quaternion(angles);
+            assert_abs_diff_eq!(Matrix4::from(rotation), expected, epsilon = 1e-5);
+
+            let recovered = order.from_quaternion(rotation);
+            for (recovered, angle) in recovered.iter().zip(angles) {
+                assert_abs_diff_eq!(recovered.0, angle.0, epsilon = 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    pub fn euler_gimbal_lock() {
+        for order in ORDERS {
+            // With the middle rotation at 90 degrees the others can't be told apart, but
+            // the recovered angles still rebuild the same rotation
+            let [a, b, c] = order.axes();
+            let mut angles = [Rad(0.0); 3];
+            angles[a] = Rad(0.3);
+            angles[b] = Rad(std::f32::consts::FRAC_PI_2);
+            angles[c] = Rad(-0.5);
+            let rotation = order.to_quaternion(angles);
+
+            let recovered = order.from_quaternion(rotation);
+            assert_abs_diff_eq!(recovered[b].0, angles[b].0, epsilon = 1e-3);
+            assert_abs_diff_eq!(
+                Matrix4::from(order.to_quaternion(recovered)),
+                Matrix4::from(rotation),
+                epsilon = 1e-3
+            );
+        }
+    }
+
+    #[test]
+    pub fn inverse_and_normal_matrices() {
+        let transform = example();
+        let matrix = transform.build_transform_matrix();
+        assert_abs_diff_eq!(
+            transform.inverse_matrix() * matrix,
+            Matrix4::identity(),
+            epsilon = 1e-5
+        );
+        assert_abs_diff_eq!(
+            transform.inverse_matrix(),
+            matrix.invert().unwrap(),
+            epsilon = 1e-5
+        );
+
+        // A normal stays perpendicular to a tangent after non-uniform scaling
+        let (tangent, normal) = (Vector3::new(1.0, 1.0, 0.0), Vector3::new(1.0, -1.0, 0.0));
+        let tangent = (matrix * tangent.extend(0.0)).truncate();
+        let normal = transform.normal_matrix() * normal;
+        assert_abs_diff_eq!(tangent.dot(normal), 0.0, epsilon = 1e-5);
+        assert_abs_diff_eq!(
+            normal_matrix(matrix),
+            transform.normal_matrix(),
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    pub fn decomposition() {
+        let mirrored = Transform {
+            scale: [-2.0, 0.5, 3.0],
+            ..example()
+        };
+        for transform in [Transform::new(), example(), mirrored] {
+            let matrix = transform.build_transform_matrix();
+            let decomposed = Transform::from_matrix(matrix);
+            assert_abs_diff_eq!(decomposed.build_transform_matrix(), matrix, epsilon = 1e-5);
+            assert_abs_diff_eq!(
+                Vector3::from(decomposed.translate),
+                Vector3::from(transform.translate)
+            );
+        }
+
+        // Sheared matrices still give a pure rotation
+        let sheared = Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0))
+            * Matrix4::from_angle_y(Rad(0.5))
+            * Matrix4::from_cols(
+                Vector4::unit_x(),
+                Vector4::new(0.5, 1.0, 0.0, 0.0),
+                Vector4::unit_z(),
+                Vector4::unit_w(),
+            );
+        let decomposed = Transform::from_matrix(sheared);
+        assert_abs_diff_eq!(decomposed.rotation.magnitude(), 1.0, epsilon = 1e-5);
+        assert_abs_diff_eq!(decomposed.euler(EulerOrder::XYZ)[1].0, 0.5, epsilon = 1e-5);
+        assert_eq!(decomposed.translate, [0.0, 1.0, 0.0]);
     }
 }